@@ -0,0 +1,83 @@
+use axum::Json;
+use axum::extract::rejection::{PathRejection, QueryRejection};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use tracing::error;
+use utoipa::ToSchema;
+
+/// The JSON body returned for every failed request.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    /// A stable, machine-readable error code (e.g. "not_found").
+    pub error: &'static str,
+    /// A human-readable description of the problem.
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    NotFound(String),
+    Internal(anyhow::Error),
+}
+
+impl ApiError {
+    /// The status and body this error is served with, logging internal errors on the way.
+    pub fn into_parts(self) -> (StatusCode, ErrorBody) {
+        match self {
+            ApiError::BadRequest(message) => (
+                StatusCode::BAD_REQUEST,
+                ErrorBody {
+                    error: "bad_request",
+                    message,
+                },
+            ),
+            ApiError::NotFound(message) => (
+                StatusCode::NOT_FOUND,
+                ErrorBody {
+                    error: "not_found",
+                    message,
+                },
+            ),
+            ApiError::Internal(e) => {
+                // Don't leak database details to the client, but keep them in the logs.
+                error!("Internal error while handling request: {:#}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    ErrorBody {
+                        error: "internal",
+                        message: "An internal error occurred.".to_string(),
+                    },
+                )
+            }
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, body) = self.into_parts();
+        (status, Json(body)).into_response()
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        ApiError::Internal(e)
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        ApiError::BadRequest(rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        ApiError::BadRequest(rejection.body_text())
+    }
+}
+
+pub type ApiResult<T> = Result<T, ApiError>;
@@ -0,0 +1,14 @@
+//! Wrappers around axum's extractors that reject with an `ApiError`,
+//! so malformed paths and query strings get the same JSON error body as every other failure.
+
+use axum::extract::FromRequestParts;
+
+use crate::error::ApiError;
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ApiError))]
+pub struct Query<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(ApiError))]
+pub struct Path<T>(pub T);
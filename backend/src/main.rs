@@ -2,13 +2,27 @@ use clap::Parser;
 use common::args::PostgresSqlArguments;
 use common::state::AppState;
 use dotenvy::dotenv;
+use std::net::SocketAddr;
 use tracing::info;
 use tracing_subscriber::{EnvFilter, fmt};
 
+mod error;
+mod extract;
+mod openapi;
+mod routes;
+
 #[derive(Parser, Debug)]
 struct Cli {
     #[command(flatten)]
     postgres: PostgresSqlArguments,
+
+    /// The address the HTTP server listens on.
+    #[arg(long, env = "BIND_ADDRESS", default_value = "0.0.0.0:8080")]
+    bind_address: SocketAddr,
+
+    /// The externally visible base URL of this server, used for absolute links in FHIR bundles.
+    #[arg(long, env = "PUBLIC_URL", default_value = "http://localhost:8080")]
+    public_url: String,
 }
 
 #[tokio::main]
@@ -25,9 +39,22 @@ async fn main() -> anyhow::Result<()> {
     let args = Cli::parse();
 
     info!("Initializing application state...");
-    let _state = AppState::new(args.postgres).await?;
+    let state = AppState::new(args.postgres).await?;
 
-    info!("Backend started successfully with DB connection pool.");
+    let app = routes::router(state, &args.public_url);
+    let listener = tokio::net::TcpListener::bind(args.bind_address).await?;
+    info!("Backend listening on {}", listener.local_addr()?);
 
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+
+    info!("Backend shut down.");
     Ok(())
 }
+
+async fn shutdown_signal() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        tracing::error!("Failed to listen for shutdown signal: {}", e);
+    }
+}
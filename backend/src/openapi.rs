@@ -0,0 +1,184 @@
+use utoipa::OpenApi;
+
+/// The top-level OpenAPI document. Paths and schemas are added by `routes::api_router`,
+/// straight from the `#[utoipa::path]` annotations on each handler.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "healthcare-data-rest",
+        description = "CMS healthcare provider data served as REST endpoints."
+    ),
+    tags(
+        (name = "providers", description = "Provider of Services facilities and their addresses"),
+        (name = "fhir", description = "FHIR R4 Organization and Location views of providers"),
+        (name = "admin", description = "Loader run history")
+    )
+)]
+pub struct ApiDoc;
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::{api_router, router};
+    use axum::body::Body;
+    use axum::http::header::ALLOW;
+    use axum::http::{Method, Request, StatusCode};
+    use common::state::AppState;
+    use serde_json::Value;
+    use sqlx::PgPool;
+    use std::collections::BTreeSet;
+    use tower::ServiceExt;
+
+    fn document() -> Value {
+        serde_json::to_value(api_router().into_openapi()).expect("OpenAPI document serializes")
+    }
+
+    /// Collects every `$ref` in the document.
+    fn refs(value: &Value, out: &mut BTreeSet<String>) {
+        match value {
+            Value::Object(map) => {
+                for (key, v) in map {
+                    match (key.as_str(), v) {
+                        ("$ref", Value::String(r)) => {
+                            out.insert(r.clone());
+                        }
+                        _ => refs(v, out),
+                    }
+                }
+            }
+            Value::Array(items) => items.iter().for_each(|v| refs(v, out)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn document_is_openapi_3_1() {
+        let doc = document();
+        assert!(doc["openapi"].as_str().unwrap().starts_with("3.1"));
+    }
+
+    /// The method and path of every route the application serves, outside the Swagger UI,
+    /// taken from the router `main` runs rather than from the OpenAPI metadata.
+    ///
+    /// axum can't list its routes, but its `Debug` output names every path. A request with a
+    /// method no handler takes then gets a 405, whose `Allow` header lists the methods the
+    /// path does take.
+    async fn served_routes() -> BTreeSet<(String, String)> {
+        let pool = PgPool::connect_lazy("postgres://127.0.0.1:1/unused").unwrap();
+        let app = router(AppState { pool }, "http://localhost");
+
+        let debug = format!("{:?}", app);
+        let routed = debug.split("fallback_router").next().unwrap();
+        let paths: BTreeSet<&str> = routed
+            .split("): \"")
+            .skip(1)
+            .filter_map(|rest| rest.split('"').next())
+            .filter(|path| !path.starts_with("/docs") && *path != "/openapi.json")
+            .collect();
+
+        let mut served = BTreeSet::new();
+        for path in paths {
+            let uri: String = path.replace(['{', '}'], "");
+            let request = Request::builder()
+                .method(Method::TRACE)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{path} answers any method"
+            );
+            let allow = response.headers()[ALLOW].to_str().unwrap().to_lowercase();
+            for method in allow.split(',').map(str::trim) {
+                // axum answers HEAD wherever it answers GET.
+                if method != "head" {
+                    served.insert((method.to_string(), path.to_string()));
+                }
+            }
+        }
+        served
+    }
+
+    #[tokio::test]
+    async fn documented_routes_are_the_served_routes() {
+        let doc = document();
+        let mut documented = BTreeSet::new();
+        for (path, item) in doc["paths"].as_object().expect("document has paths") {
+            for method in item.as_object().unwrap().keys() {
+                documented.insert((method.clone(), path.clone()));
+            }
+        }
+        let served = served_routes().await;
+        assert!(!served.is_empty(), "no routes are served");
+        assert_eq!(documented, served);
+    }
+
+    #[tokio::test]
+    async fn every_route_is_fully_documented() {
+        let doc = document();
+        for (method, path) in served_routes().await {
+            let route = format!("{} {}", method.to_uppercase(), path);
+            let operation = &doc["paths"][&path][&method];
+            assert!(operation.is_object(), "{route} is not documented");
+            assert!(
+                operation["operationId"].is_string(),
+                "{route} has no operationId"
+            );
+            assert!(
+                operation["tags"].as_array().is_some_and(|t| !t.is_empty()),
+                "{route} has no tag"
+            );
+
+            let responses = operation["responses"]
+                .as_object()
+                .unwrap_or_else(|| panic!("{route} documents no responses"));
+            let success = responses
+                .iter()
+                .find(|(status, _)| status.starts_with('2'))
+                .unwrap_or_else(|| panic!("{route} documents no success response"));
+            assert!(
+                success.1["content"].is_object(),
+                "{route} success response has no body schema"
+            );
+            for (status, response) in responses {
+                if !status.starts_with('2') {
+                    assert!(
+                        response["content"].is_object(),
+                        "{route} {status} response has no error body schema"
+                    );
+                }
+            }
+
+            let parameters: BTreeSet<&str> = operation["parameters"]
+                .as_array()
+                .map(|params| params.iter().filter_map(|p| p["name"].as_str()).collect())
+                .unwrap_or_default();
+            for segment in path.split('/') {
+                if let Some(name) = segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                    assert!(
+                        parameters.contains(name),
+                        "{route} does not document path parameter '{name}'"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_schema_reference_resolves() {
+        let doc = document();
+        let mut found = BTreeSet::new();
+        refs(&doc, &mut found);
+
+        for reference in found {
+            let name = reference
+                .strip_prefix("#/components/schemas/")
+                .unwrap_or_else(|| panic!("unexpected reference {reference}"));
+            assert!(
+                doc["components"]["schemas"][name].is_object(),
+                "schema {name} is referenced but not registered"
+            );
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use axum::Json;
+use axum::extract::State;
+use common::queries::LoaderRun;
+use common::state::AppState;
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::error::{ApiError, ApiResult, ErrorBody};
+use crate::extract::Query;
+
+const DEFAULT_RUNS: i64 = 50;
+const MAX_RUNS: i64 = 500;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RunParams {
+    /// Only return runs of this loader, e.g. `pos_iqies`.
+    pub loader_key: Option<String>,
+    /// The maximum number of runs to return.
+    pub limit: Option<i64>,
+}
+
+/// Lists loader runs, most recent first, including failed and interrupted ones.
+#[utoipa::path(
+    get,
+    path = "/admin/runs",
+    tag = "admin",
+    params(RunParams),
+    responses(
+        (status = 200, description = "Loader runs, most recent first", body = Vec<LoaderRun>),
+        (status = 400, description = "Invalid limit", body = ErrorBody),
+    )
+)]
+pub async fn list_runs(
+    State(state): State<AppState>,
+    Query(params): Query<RunParams>,
+) -> ApiResult<Json<Vec<LoaderRun>>> {
+    let limit = match params.limit {
+        None => DEFAULT_RUNS,
+        Some(limit) if (1..=MAX_RUNS).contains(&limit) => limit,
+        Some(limit) => {
+            return Err(ApiError::BadRequest(format!(
+                "limit must be between 1 and {}, got {}.",
+                MAX_RUNS, limit
+            )));
+        }
+    };
+
+    let runs =
+        common::queries::list_loader_runs(&state.pool, params.loader_key.as_deref(), limit).await?;
+    Ok(Json(runs))
+}
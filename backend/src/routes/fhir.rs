@@ -0,0 +1,262 @@
+use axum::extract::{FromRequestParts, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use common::fhir::{Bundle, Location, OperationOutcome, Organization};
+use common::model::Ccn;
+use common::queries::ProviderFilter;
+use common::state::AppState;
+use serde::{Deserialize, Serialize};
+use utoipa::IntoParams;
+
+use crate::error::ApiError;
+use crate::routes::providers::PageParams;
+
+const FHIR_JSON: &str = "application/fhir+json";
+
+/// The absolute base URL of the FHIR endpoints, used for `fullUrl`s and paging links.
+#[derive(Debug, Clone)]
+pub struct FhirBaseUrl(pub String);
+
+/// A JSON response with the FHIR media type.
+pub struct Fhir<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Fhir<T> {
+    fn into_response(self) -> Response {
+        let mut response = Json(self.0).into_response();
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(FHIR_JSON));
+        response
+    }
+}
+
+/// An `ApiError` served as a FHIR `OperationOutcome`, as FHIR clients expect.
+#[derive(Debug)]
+pub struct FhirError(pub ApiError);
+
+impl IntoResponse for FhirError {
+    fn into_response(self) -> Response {
+        let (status, body) = self.0.into_parts();
+        let code = match status {
+            StatusCode::BAD_REQUEST => "invalid",
+            StatusCode::NOT_FOUND => "not-found",
+            _ => "exception",
+        };
+        (status, Fhir(OperationOutcome::error(code, body.message))).into_response()
+    }
+}
+
+impl<E: Into<ApiError>> From<E> for FhirError {
+    fn from(e: E) -> Self {
+        FhirError(e.into())
+    }
+}
+
+pub type FhirResult<T> = Result<Fhir<T>, FhirError>;
+
+/// `Query` and `Path`, rejecting with an `OperationOutcome`.
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(FhirError))]
+pub struct Query<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(FhirError))]
+pub struct Path<T>(pub T);
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OrganizationSearchParams {
+    /// Only return organizations located in this state.
+    #[serde(rename = "address-state")]
+    #[param(rename = "address-state")]
+    pub address_state: Option<String>,
+    /// The maximum number of organizations per page.
+    #[serde(rename = "_count")]
+    #[param(rename = "_count")]
+    pub count: Option<i64>,
+    /// Paging cursor, taken from the bundle's `next` link.
+    #[serde(rename = "_cursor")]
+    #[param(rename = "_cursor")]
+    pub cursor: Option<String>,
+}
+
+impl OrganizationSearchParams {
+    fn page_url(&self, base_url: &str, count: i64, cursor: Option<&str>) -> String {
+        let mut params = Vec::new();
+        if let Some(state) = &self.address_state {
+            params.push(("address-state", state.clone()));
+        }
+        params.push(("_count", count.to_string()));
+        if let Some(cursor) = cursor {
+            params.push(("_cursor", cursor.to_string()));
+        }
+
+        let query = serde_urlencoded::to_string(&params).unwrap_or_default();
+        format!("{}/Organization?{}", base_url, query)
+    }
+}
+
+fn not_found(resource: &str, id: &str) -> FhirError {
+    FhirError(ApiError::NotFound(format!(
+        "{}/{} not found.",
+        resource, id
+    )))
+}
+
+/// Reads a provider as a FHIR `Organization`.
+#[utoipa::path(
+    get,
+    path = "/fhir/Organization/{id}",
+    tag = "fhir",
+    params(("id" = String, Path, description = "The provider's CMS Certification Number")),
+    responses(
+        (status = 200, description = "The organization", body = Organization, content_type = "application/fhir+json"),
+        (status = 404, description = "No provider has this CCN", body = OperationOutcome, content_type = "application/fhir+json"),
+    )
+)]
+pub async fn read_organization(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> FhirResult<Organization> {
+    // A malformed id can't name any resource.
+    let ccn: Ccn = id.parse().map_err(|_| not_found("Organization", &id))?;
+    // Retired providers stay readable, as inactive organizations.
+    let detail = common::queries::get_provider(&state.pool, &ccn, true)
+        .await?
+        .ok_or_else(|| not_found("Organization", &id))?;
+    Ok(Fhir(Organization::from(&detail)))
+}
+
+/// Reads a provider's address as a FHIR `Location`.
+#[utoipa::path(
+    get,
+    path = "/fhir/Location/{id}",
+    tag = "fhir",
+    params(("id" = String, Path, description = "The provider's CMS Certification Number")),
+    responses(
+        (status = 200, description = "The location", body = Location, content_type = "application/fhir+json"),
+        (status = 404, description = "No provider with an address has this CCN", body = OperationOutcome, content_type = "application/fhir+json"),
+    )
+)]
+pub async fn read_location(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> FhirResult<Location> {
+    let ccn: Ccn = id.parse().map_err(|_| not_found("Location", &id))?;
+    common::queries::get_provider(&state.pool, &ccn, true)
+        .await?
+        .as_ref()
+        .and_then(Location::from_provider)
+        .map(Fhir)
+        .ok_or_else(|| not_found("Location", &id))
+}
+
+/// Searches organizations, returning a `searchset` bundle of organizations and their locations.
+#[utoipa::path(
+    get,
+    path = "/fhir/Organization",
+    tag = "fhir",
+    params(OrganizationSearchParams),
+    responses(
+        (status = 200, description = "A page of matching organizations", body = Bundle, content_type = "application/fhir+json"),
+        (status = 400, description = "Invalid search parameters", body = OperationOutcome, content_type = "application/fhir+json"),
+    )
+)]
+pub async fn search_organizations(
+    State(state): State<AppState>,
+    Extension(FhirBaseUrl(base_url)): Extension<FhirBaseUrl>,
+    Query(params): Query<OrganizationSearchParams>,
+) -> FhirResult<Bundle> {
+    let count = PageParams {
+        cursor: None,
+        limit: params.count,
+    }
+    .limit()?;
+
+    let filter = ProviderFilter {
+        state_code: params.address_state.clone(),
+        ..Default::default()
+    };
+    let page =
+        common::queries::list_providers(&state.pool, &filter, params.cursor.as_deref(), count)
+            .await?;
+
+    let self_url = params.page_url(&base_url, count, params.cursor.as_deref());
+    let next_url = page
+        .next_cursor
+        .as_deref()
+        .map(|cursor| params.page_url(&base_url, count, Some(cursor)));
+
+    Ok(Fhir(Bundle::search_set(
+        &page, &base_url, self_url, next_url,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Uri;
+    use serde_json::{Value, json};
+
+    async fn outcome(error: FhirError) -> (StatusCode, String, Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let content_type = response.headers()[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, content_type, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn missing_resources_are_not_found_outcomes() {
+        let (status, content_type, body) = outcome(not_found("Location", "229999")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(content_type, FHIR_JSON);
+        assert_eq!(
+            body,
+            json!({
+                "resourceType": "OperationOutcome",
+                "issue": [{
+                    "severity": "error",
+                    "code": "not-found",
+                    "diagnostics": "Location/229999 not found.",
+                }],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn bad_search_parameters_are_invalid_outcomes() {
+        let uri: Uri = "/fhir/Organization?_count=many".parse().unwrap();
+        let rejection =
+            axum::extract::Query::<OrganizationSearchParams>::try_from_uri(&uri).unwrap_err();
+        let (status, _, body) = outcome(rejection.into()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["resourceType"], "OperationOutcome");
+        assert_eq!(body["issue"][0]["code"], "invalid");
+        assert!(
+            body["issue"][0]["diagnostics"]
+                .as_str()
+                .unwrap()
+                .contains("_count")
+        );
+    }
+
+    #[tokio::test]
+    async fn internal_errors_hide_their_details() {
+        let error = FhirError::from(anyhow::anyhow!("connection refused"));
+        let (status, _, body) = outcome(error).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["issue"][0]["code"], "exception");
+        assert_eq!(
+            body["issue"][0]["diagnostics"],
+            "An internal error occurred."
+        );
+    }
+}
@@ -0,0 +1,49 @@
+use axum::{Extension, Router};
+use common::state::AppState;
+use utoipa::OpenApi;
+use utoipa_axum::router::{OpenApiRouter, UtoipaMethodRouter};
+use utoipa_axum::routes;
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::openapi::ApiDoc;
+
+pub mod admin;
+pub mod fhir;
+pub mod providers;
+
+/// Every API route, together with its OpenAPI metadata.
+///
+/// Routes can only be registered here, and only with `routes!`: it requires a
+/// `#[utoipa::path]` annotation on the handler. The tests in `openapi` check every route
+/// `router` serves against the document, so one added any other way fails them.
+pub fn api_routes() -> Vec<UtoipaMethodRouter<AppState>> {
+    vec![
+        routes!(providers::list_providers),
+        routes!(providers::search_providers),
+        routes!(providers::get_provider),
+        routes!(providers::get_provider_history),
+        routes!(fhir::search_organizations),
+        routes!(fhir::read_organization),
+        routes!(fhir::read_location),
+        routes!(admin::list_runs),
+    ]
+}
+
+/// The API routes of `api_routes`, with the OpenAPI document they make up.
+pub fn api_router() -> OpenApiRouter<AppState> {
+    api_routes().into_iter().fold(
+        OpenApiRouter::with_openapi(ApiDoc::openapi()),
+        |router, route| router.routes(route),
+    )
+}
+
+/// Builds the full application. `public_url` is the externally visible base URL of the
+/// server, which FHIR bundles need for their absolute links.
+pub fn router(state: AppState, public_url: &str) -> Router {
+    let (router, api) = api_router().with_state(state).split_for_parts();
+    let fhir_base_url = fhir::FhirBaseUrl(format!("{}/fhir", public_url.trim_end_matches('/')));
+
+    router
+        .layer(Extension(fhir_base_url))
+        .merge(SwaggerUi::new("/docs").url("/openapi.json", api))
+}
@@ -0,0 +1,185 @@
+use axum::Json;
+use axum::extract::State;
+use common::model::{Ccn, ProviderDetail};
+use common::queries::{Page, ProviderFilter, ProviderHistory, ProviderMatch};
+use common::state::AppState;
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::error::{ApiError, ApiResult, ErrorBody};
+use crate::extract::{Path, Query};
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+const DEFAULT_SEARCH_RESULTS: i64 = 25;
+const MAX_SEARCH_RESULTS: i64 = 100;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageParams {
+    /// The `next_cursor` value from the previous page.
+    pub cursor: Option<String>,
+    /// The maximum number of items to return.
+    pub limit: Option<i64>,
+}
+
+impl PageParams {
+    pub fn limit(&self) -> ApiResult<i64> {
+        match self.limit {
+            None => Ok(DEFAULT_PAGE_SIZE),
+            Some(limit) if (1..=MAX_PAGE_SIZE).contains(&limit) => Ok(limit),
+            Some(limit) => Err(ApiError::BadRequest(format!(
+                "limit must be between 1 and {}, got {}.",
+                MAX_PAGE_SIZE, limit
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchParams {
+    /// The (partial) facility name or city to look for.
+    pub q: String,
+    /// Only return providers located in this state.
+    pub state_code: Option<String>,
+    /// Also match providers that the latest POS file no longer lists.
+    pub include_retired: Option<bool>,
+    /// The maximum number of matches to return.
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RetiredParams {
+    /// Also return the provider if the latest POS file no longer lists it.
+    pub include_retired: Option<bool>,
+}
+
+/// Fuzzy-searches providers by facility name or city, best matches first.
+#[utoipa::path(
+    get,
+    path = "/providers/search",
+    tag = "providers",
+    params(SearchParams),
+    responses(
+        (status = 200, description = "Matching providers, ranked by similarity", body = Vec<ProviderMatch>),
+        (status = 400, description = "Missing query or invalid limit", body = ErrorBody),
+    )
+)]
+pub async fn search_providers(
+    State(state): State<AppState>,
+    Query(params): Query<SearchParams>,
+) -> ApiResult<Json<Vec<ProviderMatch>>> {
+    let q = params.q.trim();
+    if q.is_empty() {
+        return Err(ApiError::BadRequest("q must not be empty.".to_string()));
+    }
+
+    let limit = match params.limit {
+        None => DEFAULT_SEARCH_RESULTS,
+        Some(limit) if (1..=MAX_SEARCH_RESULTS).contains(&limit) => limit,
+        Some(limit) => {
+            return Err(ApiError::BadRequest(format!(
+                "limit must be between 1 and {}, got {}.",
+                MAX_SEARCH_RESULTS, limit
+            )));
+        }
+    };
+
+    let matches = common::queries::search_providers(
+        &state.pool,
+        q,
+        params.state_code.as_deref(),
+        params.include_retired.unwrap_or(false),
+        limit,
+    )
+    .await?;
+    Ok(Json(matches))
+}
+
+/// Lists providers matching the given filters, one page at a time.
+#[utoipa::path(
+    get,
+    path = "/providers",
+    tag = "providers",
+    params(ProviderFilter, PageParams),
+    responses(
+        (status = 200, description = "A page of matching providers", body = Page<ProviderDetail>),
+        (status = 400, description = "Invalid filter or page parameters", body = ErrorBody),
+    )
+)]
+pub async fn list_providers(
+    State(state): State<AppState>,
+    Query(filter): Query<ProviderFilter>,
+    Query(page): Query<PageParams>,
+) -> ApiResult<Json<Page<ProviderDetail>>> {
+    let limit = page.limit()?;
+    let page = common::queries::list_providers(&state.pool, &filter, page.cursor.as_deref(), limit)
+        .await?;
+    Ok(Json(page))
+}
+
+/// Returns a single provider, with its address, by CMS Certification Number.
+#[utoipa::path(
+    get,
+    path = "/providers/{cms_certification_number}",
+    tag = "providers",
+    params(
+        ("cms_certification_number" = String, Path, description = "The provider's CMS Certification Number"),
+        RetiredParams,
+    ),
+    responses(
+        (status = 200, description = "The provider", body = ProviderDetail),
+        (status = 404, description = "No provider has this CCN, or it is retired", body = ErrorBody),
+        (status = 400, description = "Not a well-formed CCN, or invalid query parameters", body = ErrorBody),
+    )
+)]
+pub async fn get_provider(
+    State(state): State<AppState>,
+    Path(cms_certification_number): Path<Ccn>,
+    Query(params): Query<RetiredParams>,
+) -> ApiResult<Json<ProviderDetail>> {
+    common::queries::get_provider(
+        &state.pool,
+        &cms_certification_number,
+        params.include_retired.unwrap_or(false),
+    )
+    .await?
+    .map(Json)
+    .ok_or_else(|| {
+        ApiError::NotFound(format!(
+            "No provider found with CMS Certification Number '{}'.",
+            cms_certification_number
+        ))
+    })
+}
+
+/// Returns every version of a provider across POS loads, as a field-level timeline.
+/// Retired providers keep their history.
+#[utoipa::path(
+    get,
+    path = "/providers/{cms_certification_number}/history",
+    tag = "providers",
+    params(("cms_certification_number" = String, Path, description = "The provider's CMS Certification Number")),
+    responses(
+        (status = 200, description = "The provider's versions, oldest first", body = ProviderHistory),
+        (status = 404, description = "No provider has ever had this CCN", body = ErrorBody),
+        (status = 400, description = "Not a well-formed CCN", body = ErrorBody),
+    )
+)]
+pub async fn get_provider_history(
+    State(state): State<AppState>,
+    Path(cms_certification_number): Path<Ccn>,
+) -> ApiResult<Json<ProviderHistory>> {
+    common::queries::get_provider_history(&state.pool, &cms_certification_number)
+        .await?
+        .map(Json)
+        .ok_or_else(|| {
+            ApiError::NotFound(format!(
+                "No history found for CMS Certification Number '{}'.",
+                cms_certification_number
+            ))
+        })
+}
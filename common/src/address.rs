@@ -0,0 +1,512 @@
+//! USPS-style address standardization, after Publication 28.
+//!
+//! POS files spell the same address many ways: "123 North Main Street", "123 N. MAIN ST",
+//! a ZIP with or without its +4. Addresses are deduplicated on their standardized street,
+//! city, state and five-digit ZIP, so every spelling resolves to the same `addresses` row.
+//! The rules are deliberately conservative: a word is only abbreviated where Publication 28
+//! would, so "100 West Avenue" keeps its street name.
+
+/// Directionals and their abbreviations.
+const DIRECTIONALS: &[(&str, &str)] = &[
+    ("NORTH", "N"),
+    ("SOUTH", "S"),
+    ("EAST", "E"),
+    ("WEST", "W"),
+    ("NORTHEAST", "NE"),
+    ("NORTHWEST", "NW"),
+    ("SOUTHEAST", "SE"),
+    ("SOUTHWEST", "SW"),
+    ("N", "N"),
+    ("S", "S"),
+    ("E", "E"),
+    ("W", "W"),
+    ("NE", "NE"),
+    ("NW", "NW"),
+    ("SE", "SE"),
+    ("SW", "SW"),
+];
+
+/// Common street suffixes and their variants, with the standard abbreviation.
+const SUFFIXES: &[(&str, &str)] = &[
+    ("ALLEY", "ALY"),
+    ("ALLEE", "ALY"),
+    ("AVENUE", "AVE"),
+    ("AV", "AVE"),
+    ("AVEN", "AVE"),
+    ("AVENU", "AVE"),
+    ("AVN", "AVE"),
+    ("AVNUE", "AVE"),
+    ("BOULEVARD", "BLVD"),
+    ("BOUL", "BLVD"),
+    ("BOULV", "BLVD"),
+    ("BYPASS", "BYP"),
+    ("CAUSEWAY", "CSWY"),
+    ("CENTER", "CTR"),
+    ("CENTRE", "CTR"),
+    ("CENTR", "CTR"),
+    ("CIRCLE", "CIR"),
+    ("CIRC", "CIR"),
+    ("CIRCL", "CIR"),
+    ("COURT", "CT"),
+    ("COVE", "CV"),
+    ("CREEK", "CRK"),
+    ("CROSSING", "XING"),
+    ("DRIVE", "DR"),
+    ("DRIV", "DR"),
+    ("DRV", "DR"),
+    ("EXPRESSWAY", "EXPY"),
+    ("EXPRESS", "EXPY"),
+    ("EXPW", "EXPY"),
+    ("FREEWAY", "FWY"),
+    ("FREEWY", "FWY"),
+    ("GROVE", "GRV"),
+    ("HARBOR", "HBR"),
+    ("HEIGHTS", "HTS"),
+    ("HIGHWAY", "HWY"),
+    ("HIGHWY", "HWY"),
+    ("HIWAY", "HWY"),
+    ("HILL", "HL"),
+    ("LANE", "LN"),
+    ("MEADOWS", "MDWS"),
+    ("PARKWAY", "PKWY"),
+    ("PARKWY", "PKWY"),
+    ("PKY", "PKWY"),
+    ("PLACE", "PL"),
+    ("PLAZA", "PLZ"),
+    ("PLZA", "PLZ"),
+    ("POINT", "PT"),
+    ("RIDGE", "RDG"),
+    ("ROAD", "RD"),
+    ("ROUTE", "RTE"),
+    ("SQUARE", "SQ"),
+    ("SQR", "SQ"),
+    ("STREET", "ST"),
+    ("STR", "ST"),
+    ("STRT", "ST"),
+    ("TERRACE", "TER"),
+    ("TERR", "TER"),
+    ("TRAIL", "TRL"),
+    ("TRAILS", "TRL"),
+    ("TURNPIKE", "TPKE"),
+    ("TURNPK", "TPKE"),
+    ("VIEW", "VW"),
+    // Already standard, listed so they are recognized as suffixes.
+    ("ALY", "ALY"),
+    ("AVE", "AVE"),
+    ("BLVD", "BLVD"),
+    ("BYP", "BYP"),
+    ("CIR", "CIR"),
+    ("CSWY", "CSWY"),
+    ("CT", "CT"),
+    ("CTR", "CTR"),
+    ("CV", "CV"),
+    ("DR", "DR"),
+    ("EXPY", "EXPY"),
+    ("FWY", "FWY"),
+    ("HWY", "HWY"),
+    ("LN", "LN"),
+    ("LOOP", "LOOP"),
+    ("PARK", "PARK"),
+    ("PIKE", "PIKE"),
+    ("PKWY", "PKWY"),
+    ("PL", "PL"),
+    ("PLZ", "PLZ"),
+    ("RD", "RD"),
+    ("RTE", "RTE"),
+    ("RUN", "RUN"),
+    ("SQ", "SQ"),
+    ("ST", "ST"),
+    ("TER", "TER"),
+    ("TPKE", "TPKE"),
+    ("TRL", "TRL"),
+    ("WAY", "WAY"),
+    ("XING", "XING"),
+];
+
+/// Secondary unit designators and their abbreviations.
+const UNIT_DESIGNATORS: &[(&str, &str)] = &[
+    ("APARTMENT", "APT"),
+    ("BASEMENT", "BSMT"),
+    ("BUILDING", "BLDG"),
+    ("BLD", "BLDG"),
+    ("DEPARTMENT", "DEPT"),
+    ("FLOOR", "FL"),
+    ("FLR", "FL"),
+    ("FRONT", "FRNT"),
+    ("LOBBY", "LBBY"),
+    ("LOWER", "LOWR"),
+    ("OFFICE", "OFC"),
+    ("PENTHOUSE", "PH"),
+    ("ROOM", "RM"),
+    ("SPACE", "SPC"),
+    ("SUITE", "STE"),
+    ("SUIT", "STE"),
+    ("STE", "STE"),
+    ("TRAILER", "TRLR"),
+    ("UPPER", "UPPR"),
+    ("APT", "APT"),
+    ("BLDG", "BLDG"),
+    ("BSMT", "BSMT"),
+    ("DEPT", "DEPT"),
+    ("FL", "FL"),
+    ("LOT", "LOT"),
+    ("OFC", "OFC"),
+    ("PH", "PH"),
+    ("REAR", "REAR"),
+    ("RM", "RM"),
+    ("SPC", "SPC"),
+    ("TRLR", "TRLR"),
+    ("UNIT", "UNIT"),
+];
+
+fn lookup(table: &[(&str, &'static str)], word: &str) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|(name, _)| *name == word)
+        .map(|&(_, abbreviation)| abbreviation)
+}
+
+/// The uppercase words of `s`, without periods or commas. A `#` is a word of its own.
+fn words(s: &str) -> Vec<String> {
+    s.to_uppercase()
+        .replace('#', " # ")
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .map(|word| word.replace('.', ""))
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// Whether a street suffix or a trailing directional may come before `next`: nothing, a
+/// directional or a unit follows, so the street name ends there.
+fn ends_street_name(next: Option<&str>) -> bool {
+    next.is_none_or(|next| {
+        next == "#"
+            || lookup(DIRECTIONALS, next).is_some()
+            || lookup(UNIT_DESIGNATORS, next).is_some()
+    })
+}
+
+/// Whether `word` is a house or unit number: "123", "2ND", "5B".
+fn is_number(word: &str) -> bool {
+    word.chars().next().is_some_and(|c| c.is_ascii_digit())
+}
+
+/// Standardizes a delivery line: uppercase, no periods or commas, and directionals, street
+/// suffixes and unit designators abbreviated.
+///
+/// A directional right before the street's suffix is its name ("500 EAST ST"), and so is a
+/// suffix right after the house number ("100 AVENUE OF THE AMERICAS"); both stay spelled
+/// out. A unit designator is recognized before a unit number ("BROADWAY SUITE 5"), after
+/// one at the end of the line ("BROADWAY 2ND FLOOR"), or anywhere after the suffix, but
+/// not elsewhere, so "FRONT ST" is not "FRNT ST".
+pub fn standardize_street(street: &str) -> String {
+    let words = words(street);
+    let mut out: Vec<&str> = Vec::with_capacity(words.len());
+    // Set once the street's suffix has been seen; what follows is a unit, if anything.
+    let mut past_suffix = false;
+    for (i, word) in words.iter().enumerate() {
+        let next = words.get(i + 1).map(String::as_str);
+        let previous = out.last().copied();
+
+        // "STE # 5" is "STE 5"; a "#" on its own is the designator.
+        if word == "#" && previous.is_some_and(|p| lookup(UNIT_DESIGNATORS, p).is_some()) {
+            continue;
+        }
+        let before_unit_number = next.is_some_and(|n| n == "#" || is_number(n));
+        let after_unit_number = next.is_none() && out.len() > 1 && previous.is_some_and(is_number);
+        if (before_unit_number || after_unit_number || past_suffix && next.is_some())
+            && let Some(unit) = lookup(UNIT_DESIGNATORS, word)
+        {
+            out.push(unit);
+            continue;
+        }
+        let names_street = next.is_some_and(|n| lookup(SUFFIXES, n).is_some())
+            && ends_street_name(words.get(i + 2).map(String::as_str));
+        if !names_street && let Some(directional) = lookup(DIRECTIONALS, word) {
+            out.push(directional);
+            continue;
+        }
+        if previous.is_some_and(|p| !is_number(p))
+            && ends_street_name(next)
+            && let Some(suffix) = lookup(SUFFIXES, word)
+        {
+            out.push(suffix);
+            past_suffix = true;
+            continue;
+        }
+        out.push(word);
+    }
+    out.join(" ")
+}
+
+/// Standardizes a city name: uppercase, single-spaced, without periods or commas.
+pub fn standardize_city(city: &str) -> String {
+    words(city).join(" ")
+}
+
+/// Standardizes a state code: uppercase, without surrounding space.
+pub fn standardize_state(state: &str) -> String {
+    state.trim().to_uppercase()
+}
+
+/// Splits a ZIP code into its five-digit ZIP and its +4, if any: "02115-1234",
+/// "021151234" and "02115 1234" are all ("02115", Some("1234")). Spreadsheets drop the
+/// leading zero of New England ZIPs, so four or eight digits are padded back. Anything
+/// else is kept as written, trimmed, with no +4.
+pub fn split_zip(zip: &str) -> (String, Option<String>) {
+    let zip = zip.trim();
+    let digits: String = zip.chars().filter(char::is_ascii_digit).collect();
+    let only_digits_and_separators = zip
+        .chars()
+        .all(|c| c.is_ascii_digit() || c == '-' || c == ' ');
+    if !only_digits_and_separators {
+        return (zip.to_string(), None);
+    }
+    let digits = match digits.len() {
+        4 | 8 => format!("0{}", digits),
+        _ => digits,
+    };
+    match digits.len() {
+        5 => (digits, None),
+        9 => {
+            let (zip5, zip4) = digits.split_at(5);
+            // "0000" is a placeholder, not a +4.
+            let zip4 = (zip4 != "0000").then(|| zip4.to_string());
+            (zip5.to_string(), zip4)
+        }
+        _ => (zip.to_string(), None),
+    }
+}
+
+/// The parts of an address that identify it, standardized.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StandardAddress {
+    pub street_address: Option<String>,
+    pub city: Option<String>,
+    pub state_code: Option<String>,
+    /// The five-digit ZIP.
+    pub zip_code: Option<String>,
+    pub zip4: Option<String>,
+}
+
+impl StandardAddress {
+    /// Standardizes each part. A part left empty becomes `None`.
+    pub fn new(
+        street_address: Option<&str>,
+        city: Option<&str>,
+        state_code: Option<&str>,
+        zip_code: Option<&str>,
+    ) -> Self {
+        let non_empty = |s: String| (!s.is_empty()).then_some(s);
+        let (zip_code, zip4) = match zip_code.map(split_zip) {
+            Some((zip5, zip4)) => (non_empty(zip5), zip4),
+            None => (None, None),
+        };
+        Self {
+            street_address: street_address.map(standardize_street).and_then(non_empty),
+            city: city.map(standardize_city).and_then(non_empty),
+            state_code: state_code.map(standardize_state).and_then(non_empty),
+            zip_code,
+            zip4,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Delivery lines and their standard form.
+    const STREETS: &[(&str, &str)] = &[
+        ("123 North Main Street", "123 N MAIN ST"),
+        ("123 N. MAIN ST.", "123 N MAIN ST"),
+        ("500 East St", "500 EAST ST"),
+        ("100 West Avenue", "100 WEST AVE"),
+        ("100 Avenue of the Americas", "100 AVENUE OF THE AMERICAS"),
+        ("200 Main Street Southwest", "200 MAIN ST SW"),
+        ("10 N Front St", "10 N FRONT ST"),
+        ("10 Front St Ste 5", "10 FRONT ST STE 5"),
+        ("1 Court Street, Suite #200", "1 COURT ST STE 200"),
+        ("9 Main St # 5", "9 MAIN ST # 5"),
+        ("123 Broadway Suite 5", "123 BROADWAY STE 5"),
+        ("123 Broadway Ste 5", "123 BROADWAY STE 5"),
+        ("123 Broadway Suite # 5", "123 BROADWAY STE 5"),
+        ("123 Broadway 2nd Floor", "123 BROADWAY 2ND FL"),
+        ("123 Main St 2nd Floor", "123 MAIN ST 2ND FL"),
+        ("45 Building 3, Room 12", "45 BLDG 3 RM 12"),
+        ("1 Medical Center Drive", "1 MEDICAL CENTER DR"),
+        ("  12   Elm   Road  ", "12 ELM RD"),
+        ("", ""),
+    ];
+
+    #[test]
+    fn streets_are_standardized() {
+        for (street, expected) in STREETS {
+            assert_eq!(standardize_street(street), *expected, "{:?}", street);
+        }
+    }
+
+    #[test]
+    fn spellings_of_a_unit_standardize_alike() {
+        assert_eq!(
+            standardize_street("123 BROADWAY SUITE 5"),
+            standardize_street("123 BROADWAY STE 5")
+        );
+        assert_eq!(
+            standardize_street("123 Broadway, 2nd Floor"),
+            standardize_street("123 BROADWAY 2ND FL")
+        );
+    }
+
+    #[test]
+    fn city_and_state_are_standardized() {
+        assert_eq!(standardize_city(" St. Louis "), "ST LOUIS");
+        assert_eq!(standardize_city("Winston-Salem"), "WINSTON-SALEM");
+        assert_eq!(standardize_state(" ma "), "MA");
+    }
+
+    #[test]
+    fn zip_is_split_from_its_plus_four() {
+        let split = |zip| split_zip(zip);
+        assert_eq!(split("02115"), ("02115".to_string(), None));
+        assert_eq!(
+            split("02115-1234"),
+            ("02115".to_string(), Some("1234".to_string()))
+        );
+        assert_eq!(
+            split("021151234"),
+            ("02115".to_string(), Some("1234".to_string()))
+        );
+        assert_eq!(
+            split("02115 1234"),
+            ("02115".to_string(), Some("1234".to_string()))
+        );
+        assert_eq!(split(" 02115 "), ("02115".to_string(), None));
+        assert_eq!(split("02115-0000"), ("02115".to_string(), None));
+    }
+
+    #[test]
+    fn zip_missing_its_leading_zero_is_padded() {
+        assert_eq!(split_zip("2115"), ("02115".to_string(), None));
+        assert_eq!(
+            split_zip("21151234"),
+            ("02115".to_string(), Some("1234".to_string()))
+        );
+    }
+
+    #[test]
+    fn malformed_zip_is_kept_as_written() {
+        assert_eq!(split_zip(" K1A 0B1 "), ("K1A 0B1".to_string(), None));
+        assert_eq!(split_zip("123"), ("123".to_string(), None));
+        assert_eq!(split_zip("0211512"), ("0211512".to_string(), None));
+    }
+
+    #[test]
+    fn empty_parts_are_none() {
+        let address = StandardAddress::new(Some(" "), Some("Boston"), Some(""), Some("2115"));
+        assert_eq!(
+            address,
+            StandardAddress {
+                street_address: None,
+                city: Some("BOSTON".to_string()),
+                state_code: None,
+                zip_code: Some("02115".to_string()),
+                zip4: None,
+            }
+        );
+    }
+
+    const MIGRATION: &str =
+        include_str!("../../migrations/20261017200000_standardize_addresses.sql");
+
+    /// The `(kind, word, abbreviation)` rows the migration inserts.
+    fn migration_abbreviations() -> Vec<(String, String, String)> {
+        let start = MIGRATION
+            .find("INSERT INTO address_abbreviations")
+            .expect("migration inserts the abbreviations");
+        let end = start + MIGRATION[start..].find(';').unwrap();
+        let quoted: Vec<&str> = MIGRATION[start..end]
+            .split('\'')
+            .skip(1)
+            .step_by(2)
+            .collect();
+        quoted
+            .chunks(3)
+            .map(|row| (row[0].to_string(), row[1].to_string(), row[2].to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn migration_abbreviations_match_the_rust_tables() {
+        let mut expected: Vec<(String, String, String)> = Vec::new();
+        for (kind, table) in [
+            ("directional", DIRECTIONALS),
+            ("suffix", SUFFIXES),
+            ("unit", UNIT_DESIGNATORS),
+        ] {
+            for (word, abbreviation) in table {
+                expected.push((kind.to_string(), word.to_string(), abbreviation.to_string()));
+            }
+        }
+        let mut actual = migration_abbreviations();
+        expected.sort();
+        actual.sort();
+        assert_eq!(actual, expected);
+    }
+
+    /// Runs the migration's functions against `STREETS` and some ZIPs, and compares them with
+    /// the Rust rules. The functions live in `pg_temp` and are rolled back with the
+    /// transaction, so any database will do.
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn migration_functions_match_the_rust_rules() {
+        use sqlx::{Connection, PgConnection};
+
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is set");
+        let mut conn = PgConnection::connect(&url).await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+
+        let start = MIGRATION
+            .find("CREATE TEMP TABLE address_abbreviations")
+            .unwrap();
+        let end = MIGRATION
+            .find("CREATE TEMP TABLE standardized_addresses")
+            .unwrap();
+        sqlx::raw_sql(&MIGRATION[start..end])
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+
+        for (street, _) in STREETS {
+            let standardized: Option<String> =
+                sqlx::query_scalar("SELECT pg_temp.standardize_street($1)")
+                    .bind(street)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .unwrap();
+            assert_eq!(
+                standardized.unwrap(),
+                standardize_street(street),
+                "{:?}",
+                street
+            );
+        }
+        for zip in [
+            "02115",
+            "02115-1234",
+            "2115",
+            "21151234",
+            "02115-0000",
+            "K1A 0B1",
+        ] {
+            let (zip5, zip4): (Option<String>, Option<String>) =
+                sqlx::query_as("SELECT zip5, zip4 FROM pg_temp.split_zip($1)")
+                    .bind(zip)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .unwrap();
+            assert_eq!((zip5.unwrap(), zip4), split_zip(zip), "{:?}", zip);
+        }
+        tx.rollback().await.unwrap();
+    }
+}
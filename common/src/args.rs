@@ -40,3 +40,28 @@ impl PostgresSqlArguments {
         .replace(":@", "@") // Handle empty password cleanly if needed by driver, though typically distinct
     }
 }
+
+/// Settings for the HTTP client that fetches the catalog and downloads datasets.
+#[derive(Debug, Args, Clone)]
+pub struct HttpArguments {
+    /// Send every request through this proxy, e.g. `http://proxy:3128`. Without it, the
+    /// `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` environment variables apply.
+    #[arg(long, env = "LOADER_HTTP_PROXY")]
+    pub http_proxy: Option<String>,
+
+    /// Seconds to wait for a connection to a server.
+    #[arg(long, env = "LOADER_CONNECT_TIMEOUT", default_value_t = 30)]
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for a response, or for more of its body, before retrying.
+    #[arg(long, env = "LOADER_READ_TIMEOUT", default_value_t = 120)]
+    pub read_timeout: u64,
+
+    /// How many times to try a download before giving up.
+    #[arg(long, env = "LOADER_DOWNLOAD_ATTEMPTS", default_value_t = 5)]
+    pub max_attempts: u32,
+
+    /// The `User-Agent` header sent with every request.
+    #[arg(long, env = "LOADER_USER_AGENT", default_value = crate::http::DEFAULT_USER_AGENT)]
+    pub user_agent: String,
+}
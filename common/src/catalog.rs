@@ -0,0 +1,230 @@
+//! Resolves dataset download URLs from the CMS DCAT catalog (`data.json`).
+//!
+//! CMS download links change with every release, but each dataset keeps a stable identifier
+//! and title in the catalog, along with its current distributions and when they were modified.
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::http::HttpClient;
+use crate::traits::{CatalogDataset, ResolvedSource};
+
+/// The public data.cms.gov catalog.
+pub const DEFAULT_CATALOG_URL: &str = "https://data.cms.gov/data.json";
+
+#[derive(Debug, Deserialize)]
+pub struct Catalog {
+    #[serde(default)]
+    pub dataset: Vec<Dataset>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Dataset {
+    pub identifier: String,
+    pub title: String,
+    pub modified: Option<String>,
+    #[serde(default)]
+    pub distribution: Vec<Distribution>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Distribution {
+    #[serde(rename = "downloadURL")]
+    pub download_url: Option<String>,
+    pub media_type: Option<String>,
+    pub modified: Option<String>,
+}
+
+impl Catalog {
+    pub async fn fetch(http: &HttpClient, url: &str) -> Result<Self> {
+        info!("Fetching dataset catalog from {}...", url);
+        let body = crate::download::fetch(http, url).await?;
+        let catalog: Catalog = serde_json::from_slice(&body)
+            .with_context(|| format!("Invalid DCAT catalog at {}", url))?;
+        info!("Catalog lists {} datasets.", catalog.dataset.len());
+        Ok(catalog)
+    }
+
+    /// Finds a dataset by identifier or title. An identifier matches either the full catalog
+    /// identifier or one of its path segments, so the bare dataset UUID is enough.
+    pub fn find(&self, dataset: &CatalogDataset) -> Option<&Dataset> {
+        self.dataset.iter().find(|d| match dataset {
+            CatalogDataset::Identifier(id) => {
+                d.identifier == *id || d.identifier.split('/').any(|segment| segment == id)
+            }
+            CatalogDataset::Title(title) => d.title.eq_ignore_ascii_case(title.trim()),
+        })
+    }
+
+    /// Resolves the most recently modified distribution of `dataset` with the given media type.
+    pub fn resolve(&self, dataset: &CatalogDataset, media_type: &str) -> Result<ResolvedSource> {
+        let found = self
+            .find(dataset)
+            .ok_or_else(|| anyhow!("Dataset {} not found in catalog", dataset))?;
+
+        // Catalogs list the latest release first, so on a tie keep the earlier entry.
+        let (distribution, url) = found
+            .distribution
+            .iter()
+            .filter(|d| {
+                d.media_type
+                    .as_deref()
+                    .is_some_and(|m| m.eq_ignore_ascii_case(media_type))
+            })
+            .filter_map(|d| d.download_url.as_ref().map(|url| (d, url)))
+            .rev()
+            .max_by_key(|(d, _)| d.modified.as_deref().and_then(parse_modified))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Dataset '{}' has no {} distribution in catalog",
+                    found.title,
+                    media_type
+                )
+            })?;
+
+        let modified = distribution
+            .modified
+            .as_deref()
+            .or(found.modified.as_deref())
+            .and_then(parse_modified);
+
+        Ok(ResolvedSource {
+            url: url.clone(),
+            modified,
+        })
+    }
+}
+
+/// DCAT `modified` values are ISO 8601 dates, or occasionally full timestamps.
+fn parse_modified(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|dt| dt.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::tests::{client, ok, serve};
+
+    const ENROLLMENTS_ID: &str = "f6f6505c-e8b0-4d57-b258-e2b94133aaf2";
+    const POS_TITLE: &str =
+        "Provider of Services File - Internet Quality Improvement and Evaluation System";
+
+    const DATA_JSON: &str = r#"{
+        "dataset": [
+            {
+                "identifier": "https://data.cms.gov/data-api/v1/dataset/f6f6505c-e8b0-4d57-b258-e2b94133aaf2/data-viewer",
+                "title": "Hospital Enrollments",
+                "modified": "2025-09-01",
+                "distribution": [
+                    {"downloadURL": "https://example.org/enrollments-2025-06.csv", "mediaType": "text/csv", "modified": "2025-06-01"},
+                    {"downloadURL": "https://example.org/enrollments-2025-09.csv", "mediaType": "text/csv", "modified": "2025-09-01"},
+                    {"downloadURL": "https://example.org/enrollments-api", "mediaType": "application/json", "modified": "2025-10-01"}
+                ]
+            },
+            {
+                "identifier": "https://data.cms.gov/data-api/v1/dataset/0a1b2c3d-0000-4000-8000-000000000000/data-viewer",
+                "title": "Provider of Services File - Internet Quality Improvement and Evaluation System",
+                "distribution": [
+                    {"downloadURL": "https://example.org/pos-latest.csv", "mediaType": "text/csv", "modified": "2025-09-30"},
+                    {"downloadURL": "https://example.org/pos-previous.csv", "mediaType": "TEXT/CSV", "modified": "2025-09-30"}
+                ]
+            }
+        ]
+    }"#;
+
+    /// The fixture catalog, fetched from a local server as the engine fetches the real one.
+    async fn catalog() -> Catalog {
+        let (url, requests) = serve(vec![ok(DATA_JSON)]).await;
+        let catalog = Catalog::fetch(&client(1), &url).await.unwrap();
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        catalog
+    }
+
+    fn identifier(id: &str) -> CatalogDataset {
+        CatalogDataset::Identifier(id.to_string())
+    }
+
+    fn title(title: &str) -> CatalogDataset {
+        CatalogDataset::Title(title.to_string())
+    }
+
+    #[tokio::test]
+    async fn bare_uuid_matches_a_segment_of_the_identifier() {
+        let catalog = catalog().await;
+        let found = catalog.find(&identifier(ENROLLMENTS_ID)).unwrap();
+        assert_eq!(found.title, "Hospital Enrollments");
+        assert!(catalog.find(&identifier(&found.identifier)).is_some());
+        // Part of a segment is not enough.
+        assert!(catalog.find(&identifier("f6f6505c")).is_none());
+    }
+
+    #[tokio::test]
+    async fn title_matches_ignoring_case() {
+        let catalog = catalog().await;
+        let found = catalog
+            .find(&title(&format!(" {} ", POS_TITLE.to_uppercase())))
+            .unwrap();
+        assert_eq!(found.title, POS_TITLE);
+    }
+
+    #[tokio::test]
+    async fn only_distributions_of_the_media_type_are_considered() {
+        let catalog = catalog().await;
+        let csv = catalog
+            .resolve(&identifier(ENROLLMENTS_ID), "text/csv")
+            .unwrap();
+        assert_eq!(csv.url, "https://example.org/enrollments-2025-09.csv");
+        let json = catalog
+            .resolve(&identifier(ENROLLMENTS_ID), "application/json")
+            .unwrap();
+        assert_eq!(json.url, "https://example.org/enrollments-api");
+    }
+
+    #[tokio::test]
+    async fn newest_distribution_wins_and_the_earlier_entry_breaks_a_tie() {
+        let catalog = catalog().await;
+        let enrollments = catalog
+            .resolve(&identifier(ENROLLMENTS_ID), "text/csv")
+            .unwrap();
+        assert_eq!(
+            enrollments.modified,
+            parse_modified("2025-09-01"),
+            "{:?}",
+            enrollments
+        );
+
+        let pos = catalog.resolve(&title(POS_TITLE), "text/csv").unwrap();
+        assert_eq!(pos.url, "https://example.org/pos-latest.csv");
+        assert_eq!(pos.modified, parse_modified("2025-09-30T00:00:00Z"));
+    }
+
+    #[tokio::test]
+    async fn nothing_matching_is_an_error() {
+        let catalog = catalog().await;
+        let error = catalog
+            .resolve(&title("Nursing Homes"), "text/csv")
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("not found in catalog"),
+            "{}",
+            error
+        );
+
+        let error = catalog
+            .resolve(&identifier(ENROLLMENTS_ID), "application/zip")
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Dataset 'Hospital Enrollments' has no application/zip distribution in catalog"
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Encoder for the binary format of Postgres `COPY ... FROM STDIN WITH (FORMAT binary)`.
+//!
+//! Rows are appended to an in-memory buffer, which callers drain in chunks and send with
+//! `PgConnection::copy_in_raw`, so only one chunk is held in memory at a time.
+//! See "Binary Format" in https://www.postgresql.org/docs/current/sql-copy.html
+
+use chrono::NaiveDate;
+
+const SIGNATURE: &[u8] = b"PGCOPY\n\xff\r\n\0";
+
+/// A value that can be written as one field of a binary COPY row.
+pub trait CopyValue {
+    fn write_field(&self, buf: &mut Vec<u8>);
+}
+
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as i32).to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+impl CopyValue for str {
+    fn write_field(&self, buf: &mut Vec<u8>) {
+        write_bytes(buf, self.as_bytes());
+    }
+}
+
+impl CopyValue for String {
+    fn write_field(&self, buf: &mut Vec<u8>) {
+        self.as_str().write_field(buf);
+    }
+}
+
+impl CopyValue for i32 {
+    fn write_field(&self, buf: &mut Vec<u8>) {
+        write_bytes(buf, &self.to_be_bytes());
+    }
+}
+
+impl CopyValue for i64 {
+    fn write_field(&self, buf: &mut Vec<u8>) {
+        write_bytes(buf, &self.to_be_bytes());
+    }
+}
+
+impl CopyValue for f64 {
+    fn write_field(&self, buf: &mut Vec<u8>) {
+        write_bytes(buf, &self.to_be_bytes());
+    }
+}
+
+impl CopyValue for bool {
+    fn write_field(&self, buf: &mut Vec<u8>) {
+        write_bytes(buf, &[*self as u8]);
+    }
+}
+
+impl CopyValue for NaiveDate {
+    fn write_field(&self, buf: &mut Vec<u8>) {
+        // Dates are sent as days since the Postgres epoch, 2000-01-01.
+        let epoch = NaiveDate::from_ymd_opt(2000, 1, 1).expect("valid date");
+        (self.signed_duration_since(epoch).num_days() as i32).write_field(buf);
+    }
+}
+
+impl<T: CopyValue> CopyValue for Option<T> {
+    fn write_field(&self, buf: &mut Vec<u8>) {
+        match self {
+            Some(value) => value.write_field(buf),
+            None => buf.extend_from_slice(&(-1i32).to_be_bytes()),
+        }
+    }
+}
+
+/// Builds a binary COPY stream: header, rows, then trailer.
+pub struct BinaryCopyWriter {
+    buf: Vec<u8>,
+}
+
+impl Default for BinaryCopyWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BinaryCopyWriter {
+    /// Starts a stream with the COPY header.
+    pub fn new() -> Self {
+        let mut buf = Vec::with_capacity(64 * 1024);
+        buf.extend_from_slice(SIGNATURE);
+        // Flags, then the length of the (empty) header extension area
+        buf.extend_from_slice(&0i32.to_be_bytes());
+        buf.extend_from_slice(&0i32.to_be_bytes());
+        Self { buf }
+    }
+
+    /// Starts a row of `fields` values. Exactly that many `field` calls must follow.
+    pub fn row(&mut self, fields: usize) -> &mut Self {
+        self.buf.extend_from_slice(&(fields as i16).to_be_bytes());
+        self
+    }
+
+    pub fn field<T: CopyValue + ?Sized>(&mut self, value: &T) -> &mut Self {
+        value.write_field(&mut self.buf);
+        self
+    }
+
+    /// The number of bytes buffered since the last `take`.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Drains the buffered bytes, to be sent as the next chunk of the stream.
+    pub fn take(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buf)
+    }
+
+    /// Ends the stream, returning the last chunk.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.buf.extend_from_slice(&(-1i16).to_be_bytes());
+        self.buf
+    }
+}
@@ -1,176 +1,866 @@
-use crate::model::{Address, Provider};
-use anyhow::Result;
-use sqlx::{Postgres, QueryBuilder, Row, postgres::PgPool};
+use crate::address::StandardAddress;
+use crate::copy::BinaryCopyWriter;
+use crate::model::{
+    CountyGazetteerRow, HospitalEnrollment, ProviderOfServiceRow, ZctaGazetteerRow,
+};
+use crate::validation::RejectedRow;
+use anyhow::{Result, bail};
+use sqlx::{PgConnection, PgPool, Postgres, QueryBuilder};
 
-pub async fn bulk_insert_addresses(pool: &PgPool, addresses: &[Address]) -> Result<Vec<i32>> {
-    let mut tx = pool.begin().await?;
-    let mut all_ids = Vec::with_capacity(addresses.len());
+/// The address columns of the POS staging table, in COPY order. The first four identify an
+/// address and hold standardized values.
+const POS_ADDRESS_COLUMNS: &[(&str, &str)] = &[
+    ("street_address", "TEXT"),
+    ("city", "TEXT"),
+    ("state_code", "TEXT"),
+    ("zip_code", "TEXT"),
+    ("zip4", "TEXT"),
+    ("ssa_county_code", "TEXT"),
+    ("ssa_state_code", "TEXT"),
+    ("state_region_code", "TEXT"),
+    ("region_code", "TEXT"),
+    ("fips_state_code", "TEXT"),
+    ("fips_county_code", "TEXT"),
+    ("cbsa_code", "TEXT"),
+    ("cbsa_urban_rural_indicator", "TEXT"),
+];
 
-    // PostgreSQL has a limit of 65535 parameters per query.
-    // We have 12 columns, batch size of 1000 is safe.
+/// The provider columns of the POS staging table, in COPY order. `address_id` is filled in
+/// when merging, from the address each row resolves to.
+const POS_PROVIDER_COLUMNS: &[(&str, &str)] = &[
+    ("cms_certification_number", "TEXT"),
+    ("name", "TEXT"),
+    ("provider_subtype_id", "INTEGER"),
+    ("medicaid_vendor_number", "TEXT"),
+    ("provider_type_id", "INTEGER"),
+    ("original_participation_date", "DATE"),
+    ("certification_date", "DATE"),
+    ("termination_expiration_date", "DATE"),
+    ("change_of_ownership_date", "DATE"),
+    ("asc_begin_service_date", "DATE"),
+    ("processing_date", "DATE"),
+    ("phone_number", "TEXT"),
+    ("fax_number", "TEXT"),
+    ("accreditation_type_code", "TEXT"),
+    ("intermediary_carrier_code", "TEXT"),
+    ("acceptable_poc_switch", "BOOLEAN"),
+    ("fiscal_year_end_date", "TEXT"),
+    ("compliance_status_code", "TEXT"),
+    ("certification_action_type_code", "TEXT"),
+    ("bed_count", "INTEGER"),
+    ("certified_bed_count", "INTEGER"),
+    ("hospice_bed_count", "INTEGER"),
+    ("aids_bed_count", "INTEGER"),
+    ("alzheimer_bed_count", "INTEGER"),
+    ("dialysis_bed_count", "INTEGER"),
+    ("disabled_children_bed_count", "INTEGER"),
+    ("head_trauma_bed_count", "INTEGER"),
+    ("huntington_disease_bed_count", "INTEGER"),
+    ("medicare_medicaid_snf_bed_count", "INTEGER"),
+    ("medicare_snf_bed_count", "INTEGER"),
+    ("rehab_bed_count", "INTEGER"),
+    ("ventilator_bed_count", "INTEGER"),
+    ("lpn_lvn_count", "DOUBLE PRECISION"),
+    ("rn_count", "DOUBLE PRECISION"),
+    ("employee_count", "DOUBLE PRECISION"),
+    ("change_of_ownership_switch", "BOOLEAN"),
+    ("hospital_based_switch", "BOOLEAN"),
+    ("multi_owned_facility_switch", "BOOLEAN"),
+    ("clia_lab_number", "TEXT"),
+    ("facility_category_code", "TEXT"),
+    ("ownership_type_code", "TEXT"),
+];
+
+/// Text code columns of the staging table and the lookup tables that decode them.
+const POS_CODE_LOOKUPS: &[(&str, &str)] = &[
+    ("ownership_type_code", "ownership_types"),
+    ("accreditation_type_code", "accreditation_types"),
+    ("compliance_status_code", "compliance_statuses"),
+    (
+        "certification_action_type_code",
+        "certification_action_types",
+    ),
+    ("facility_category_code", "facility_categories"),
+    ("cbsa_urban_rural_indicator", "cbsa_urban_rural_indicators"),
+];
+
+/// Matches the expressions of the `idx_addresses_identity` unique index. The columns hold
+/// standardized values (see `StandardAddress`), so spellings of one address share a row.
+const ADDRESS_IDENTITY: &str = "COALESCE(street_address, ''), COALESCE(city, ''), \
+     COALESCE(state_code, ''), COALESCE(zip_code, '')";
+
+fn column_names<'a>(columns: &'a [(&'a str, &str)]) -> impl Iterator<Item = &'a str> {
+    columns.iter().map(|(name, _)| *name)
+}
+
+fn join_columns(columns: &[(&str, &str)], prefix: &str) -> String {
+    column_names(columns)
+        .map(|name| format!("{prefix}{name}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Creates the `pos_staging` temp table, dropped when the surrounding transaction ends.
+/// Each row holds one line of the POS file: its line number, address and provider.
+pub async fn create_pos_staging(conn: &mut PgConnection) -> Result<()> {
+    let columns = std::iter::once(&("line", "BIGINT"))
+        .chain(POS_ADDRESS_COLUMNS)
+        .chain(POS_PROVIDER_COLUMNS)
+        .map(|(name, ty)| format!("{name} {ty}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    sqlx::query(&format!(
+        "CREATE TEMP TABLE pos_staging ({columns}) ON COMMIT DROP"
+    ))
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// The statement to pass to `PgConnection::copy_in_raw` for rows written by `write_pos_row`.
+pub fn pos_staging_copy_statement() -> String {
+    format!(
+        "COPY pos_staging (line, {}, {}) FROM STDIN WITH (FORMAT binary)",
+        join_columns(POS_ADDRESS_COLUMNS, ""),
+        join_columns(POS_PROVIDER_COLUMNS, "")
+    )
+}
+
+/// Encodes one POS row for `pos_staging`, in the order of `pos_staging_copy_statement`.
+/// The address is standardized on the way.
+pub fn write_pos_row(w: &mut BinaryCopyWriter, line: i64, r: &ProviderOfServiceRow) {
+    let address = StandardAddress::new(
+        r.street_address.as_deref(),
+        r.city.as_deref(),
+        r.state_code.as_deref(),
+        r.zip_code.as_deref(),
+    );
+    w.row(1 + POS_ADDRESS_COLUMNS.len() + POS_PROVIDER_COLUMNS.len())
+        .field(&line)
+        .field(&address.street_address)
+        .field(&address.city)
+        .field(&address.state_code)
+        .field(&address.zip_code)
+        .field(&address.zip4)
+        .field(&r.ssa_county_code)
+        .field(&r.ssa_state_code)
+        .field(&r.state_region_code)
+        .field(&r.region_code)
+        .field(&r.fips_state_code)
+        .field(&r.fips_county_code)
+        .field(&r.cbsa_code)
+        .field(&r.cbsa_urban_rural_indicator)
+        .field(&r.cms_certification_number)
+        .field(&r.name)
+        .field(&r.provider_subtype_id)
+        .field(&r.medicaid_vendor_number)
+        .field(&r.provider_type_id)
+        .field(&r.original_participation_date)
+        .field(&r.certification_date)
+        .field(&r.termination_expiration_date)
+        .field(&r.change_of_ownership_date)
+        .field(&r.asc_begin_service_date)
+        .field(&r.processing_date)
+        .field(&r.phone_number)
+        .field(&r.fax_number)
+        .field(&r.accreditation_type_code)
+        .field(&r.intermediary_carrier_code)
+        .field(&r.acceptable_poc_switch)
+        .field(&r.fiscal_year_end_date)
+        .field(&r.compliance_status_code)
+        .field(&r.certification_action_type_code)
+        .field(&r.bed_count)
+        .field(&r.certified_bed_count)
+        .field(&r.hospice_bed_count)
+        .field(&r.aids_bed_count)
+        .field(&r.alzheimer_bed_count)
+        .field(&r.dialysis_bed_count)
+        .field(&r.disabled_children_bed_count)
+        .field(&r.head_trauma_bed_count)
+        .field(&r.huntington_disease_bed_count)
+        .field(&r.medicare_medicaid_snf_bed_count)
+        .field(&r.medicare_snf_bed_count)
+        .field(&r.rehab_bed_count)
+        .field(&r.ventilator_bed_count)
+        .field(&r.lpn_lvn_count)
+        .field(&r.rn_count)
+        .field(&r.employee_count)
+        .field(&r.change_of_ownership_switch)
+        .field(&r.hospital_based_switch)
+        .field(&r.multi_owned_facility_switch)
+        .field(&r.clia_lab_number)
+        .field(&r.facility_category_code)
+        .field(&r.ownership_type_code);
+}
+
+/// Row counts from `merge_pos_staging`.
+#[derive(Debug, Clone, Copy)]
+pub struct PosMergeCounts {
+    pub new_addresses: u64,
+    /// Providers seen for the first time.
+    pub inserted_providers: u64,
+    /// Existing providers overwritten with the file's values, including reinstated ones.
+    pub updated_providers: u64,
+    /// Providers retired because the file no longer lists them.
+    pub retired: u64,
+    /// New versions written to `provider_history`, for new or changed providers.
+    pub new_versions: u64,
+}
+
+/// Registers codes the lookup tables don't know yet, so the foreign keys from `providers` and
+/// `addresses` accept codes introduced by a newer POS file. Their labels stay NULL until the
+/// lookup migrations catch up.
+async fn register_staged_codes(conn: &mut PgConnection) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO provider_types (id)
+         SELECT DISTINCT provider_type_id FROM pos_staging WHERE provider_type_id IS NOT NULL
+         ON CONFLICT (id) DO NOTHING",
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        "INSERT INTO provider_subtypes (provider_type_id, id)
+         SELECT DISTINCT provider_type_id, provider_subtype_id FROM pos_staging
+         WHERE provider_type_id IS NOT NULL AND provider_subtype_id IS NOT NULL
+         ON CONFLICT (provider_type_id, id) DO NOTHING",
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    for (column, table) in POS_CODE_LOOKUPS {
+        sqlx::query(&format!(
+            "INSERT INTO {table} (code)
+             SELECT DISTINCT {column} FROM pos_staging WHERE {column} IS NOT NULL
+             ON CONFLICT (code) DO NOTHING"
+        ))
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// The state of a provider kept in `provider_history`: the POS columns other than the CCN,
+/// with the address inlined. Built from `POS_PROVIDER_COLUMNS` rather than the whole row, so a
+/// column added to `providers` later doesn't make every record differ. Reads `providers p`
+/// joined to `addresses a`.
+fn provider_history_record() -> String {
+    let provider = column_names(POS_PROVIDER_COLUMNS)
+        .filter(|&name| name != "cms_certification_number")
+        .map(|name| format!("'{name}', p.{name}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "jsonb_build_object({provider})
+        || jsonb_build_object(
+            'street_address', a.street_address,
+            'city', a.city,
+            'state_code', a.state_code,
+            'zip_code', a.zip_code,
+            'zip4', a.zip4
+        )"
+    )
+}
+
+/// Brings `provider_history` up to date with `providers` after a merge: the open version of
+/// each changed or retired provider is closed at the transaction time, and providers without
+/// an open version get a new one starting then. Returns the number of new versions.
+async fn record_provider_history(conn: &mut PgConnection) -> Result<u64> {
+    let record = provider_history_record();
+    sqlx::query(&format!(
+        "UPDATE provider_history h SET valid_to = NOW()
+         FROM providers p
+         LEFT JOIN addresses a ON a.id = p.address_id
+         WHERE h.cms_certification_number = p.cms_certification_number
+           AND h.valid_to IS NULL
+           AND (p.retired_at IS NOT NULL OR h.record IS DISTINCT FROM {record})"
+    ))
+    .execute(&mut *conn)
+    .await?;
+
+    let new_versions = sqlx::query(&format!(
+        "INSERT INTO provider_history (cms_certification_number, valid_from, record)
+         SELECT p.cms_certification_number, NOW(), {record}
+         FROM providers p
+         LEFT JOIN addresses a ON a.id = p.address_id
+         WHERE p.retired_at IS NULL
+           AND NOT EXISTS (
+               SELECT 1 FROM provider_history h
+               WHERE h.cms_certification_number = p.cms_certification_number
+                 AND h.valid_to IS NULL
+           )"
+    ))
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    Ok(new_versions)
+}
+
+/// Merges `pos_staging` into `addresses` and `providers` with set-based upserts.
+///
+/// Addresses are deduplicated on the identity index, keeping the first line's geographic codes
+/// for a new address. Providers are upserted on CCN; if a CCN appears twice, the last line wins.
+/// Every provider in the file is stamped with the transaction time as `last_seen_at`, and
+/// active providers missing from it are retired. `rejected` lists the CCNs of rows left out
+/// by validation: those providers are still in the file, so they keep their current row and
+/// are not retired. Changes are then recorded in `provider_history`.
+///
+/// A file that stages no provider, or would retire more than `max_retired_rate` of the active
+/// providers, is more likely truncated than a real release, so the merge fails instead.
+pub async fn merge_pos_staging(
+    conn: &mut PgConnection,
+    rejected: &[String],
+    max_retired_rate: f64,
+) -> Result<PosMergeCounts> {
+    // The planner knows nothing about a freshly filled temp table.
+    sqlx::query("ANALYZE pos_staging")
+        .execute(&mut *conn)
+        .await?;
+
+    register_staged_codes(conn).await?;
+
+    let address_columns = join_columns(POS_ADDRESS_COLUMNS, "");
+    let new_addresses = sqlx::query(&format!(
+        "INSERT INTO addresses ({address_columns})
+         SELECT DISTINCT ON ({ADDRESS_IDENTITY}) {address_columns}
+         FROM pos_staging
+         ORDER BY {ADDRESS_IDENTITY}, line
+         ON CONFLICT ({ADDRESS_IDENTITY}) DO NOTHING"
+    ))
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    let identity_match = column_names(&POS_ADDRESS_COLUMNS[..4])
+        .map(|name| format!("COALESCE(a.{name}, '') = COALESCE(s.{name}, '')"))
+        .collect::<Vec<_>>()
+        .join(" AND ");
+    let updates = column_names(POS_PROVIDER_COLUMNS)
+        .skip(1)
+        .chain(["address_id", "last_seen_at", "retired_at"])
+        .map(|name| format!("{name} = EXCLUDED.{name}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    // A row inserted by this statement has no deleting transaction yet (`xmax = 0`), while
+    // a row updated ON CONFLICT carries our own transaction id.
+    let (inserted_providers, updated_providers): (i64, i64) = sqlx::query_as(&format!(
+        "WITH upserted AS (
+            INSERT INTO providers ({}, address_id, last_seen_at, retired_at)
+            SELECT DISTINCT ON (s.cms_certification_number) {}, a.id, NOW(), NULL::TIMESTAMPTZ
+            FROM pos_staging s
+            LEFT JOIN addresses a ON {identity_match}
+            ORDER BY s.cms_certification_number, s.line DESC
+            ON CONFLICT (cms_certification_number) DO UPDATE SET {updates}
+            RETURNING xmax = 0 AS inserted
+        )
+        SELECT COUNT(*) FILTER (WHERE inserted), COUNT(*) FILTER (WHERE NOT inserted)
+        FROM upserted",
+        join_columns(POS_PROVIDER_COLUMNS, ""),
+        join_columns(POS_PROVIDER_COLUMNS, "s."),
+    ))
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if inserted_providers + updated_providers == 0 {
+        bail!("The file lists no providers; refusing to retire every active provider");
+    }
+
+    // NOW() is the transaction start, so it matches the stamp set above.
+    const MISSING: &str = "last_seen_at IS DISTINCT FROM NOW()
+           AND cms_certification_number <> ALL($1)";
+    let (missing, active): (i64, i64) = sqlx::query_as(&format!(
+        "SELECT COUNT(*) FILTER (WHERE {MISSING}), COUNT(*)
+         FROM providers
+         WHERE retired_at IS NULL"
+    ))
+    .bind(rejected)
+    .fetch_one(&mut *conn)
+    .await?;
+    let rate = missing as f64 / active as f64;
+    if rate > max_retired_rate {
+        bail!(
+            "The file would retire {} of {} active providers ({:.2}%), more than the {:.2}% allowed",
+            missing,
+            active,
+            rate * 100.0,
+            max_retired_rate * 100.0
+        );
+    }
+
+    let retired = sqlx::query(&format!(
+        "UPDATE providers SET retired_at = NOW()
+         WHERE retired_at IS NULL AND {MISSING}"
+    ))
+    .bind(rejected)
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    let new_versions = record_provider_history(conn).await?;
+
+    Ok(PosMergeCounts {
+        new_addresses,
+        inserted_providers: inserted_providers as u64,
+        updated_providers: updated_providers as u64,
+        retired,
+        new_versions,
+    })
+}
+
+/// Matches the expressions of the `idx_hospital_enrollments_location` unique index: an
+/// enrollment has a row per practice location.
+const ENROLLMENT_LOCATION_IDENTITY: &str = "enrollment_id, COALESCE(address_line_1, ''), \
+     COALESCE(address_line_2, ''), COALESCE(city, ''), COALESCE(state_code, ''), \
+     COALESCE(zip_code, ''), COALESCE(practice_location_type, '')";
+
+/// Replaces the contents of `hospital_enrollments` with `enrollments`, returning how many
+/// rows were inserted. The dataset is a full snapshot, so enrollments missing from the file
+/// are dropped.
+pub async fn replace_hospital_enrollments(
+    conn: &mut PgConnection,
+    enrollments: &[HospitalEnrollment],
+) -> Result<u64> {
+    sqlx::query("DELETE FROM hospital_enrollments")
+        .execute(&mut *conn)
+        .await?;
+
+    // 41 columns, so 1000 rows stay well under the 65535 parameter limit.
     const BATCH_SIZE: usize = 1000;
 
-    for chunk in addresses.chunks(BATCH_SIZE) {
+    let mut inserted = 0;
+    for chunk in enrollments.chunks(BATCH_SIZE) {
         let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
-            "INSERT INTO addresses (
-                street_address, city, state_code, zip_code,
-                ssa_county_code, ssa_state_code, state_region_code, region_code,
-                fips_state_code, fips_county_code, cbsa_code, cbsa_urban_rural_indicator
+            "INSERT INTO hospital_enrollments (
+                enrollment_id, enrollment_state, provider_type_code, provider_type_text, npi,
+                multiple_npi_flag, cms_certification_number, associate_id, organization_name,
+                doing_business_as_name, incorporation_date, incorporation_state,
+                organization_type_structure, organization_other_type_text, proprietary_nonprofit,
+                address_line_1, address_line_2, city, state_code, zip_code, practice_location_type,
+                location_other_type_text, critical_access_hospital, rural_emergency_hospital,
+                subgroup_general, subgroup_acute_care, subgroup_alcohol_drug, subgroup_childrens,
+                subgroup_long_term, subgroup_psychiatric, subgroup_rehabilitation, subgroup_short_term,
+                subgroup_swing_bed_approved, subgroup_psychiatric_unit, subgroup_rehabilitation_unit,
+                subgroup_specialty_hospital, subgroup_other, subgroup_other_text,
+                reh_conversion_flag, reh_conversion_date, cah_or_hospital_ccn
             ) ",
         );
 
-        query_builder.push_values(chunk, |mut b, addr| {
-            b.push_bind(&addr.street_address)
-                .push_bind(&addr.city)
-                .push_bind(&addr.state_code)
-                .push_bind(&addr.zip_code)
-                .push_bind(&addr.ssa_county_code)
-                .push_bind(&addr.ssa_state_code)
-                .push_bind(&addr.state_region_code)
-                .push_bind(&addr.region_code)
-                .push_bind(&addr.fips_state_code)
-                .push_bind(&addr.fips_county_code)
-                .push_bind(&addr.cbsa_code)
-                .push_bind(&addr.cbsa_urban_rural_indicator);
+        query_builder.push_values(chunk, |mut b, e| {
+            b.push_bind(&e.enrollment_id)
+                .push_bind(&e.enrollment_state)
+                .push_bind(&e.provider_type_code)
+                .push_bind(&e.provider_type_text)
+                .push_bind(&e.npi)
+                .push_bind(e.multiple_npi_flag)
+                .push_bind(&e.cms_certification_number)
+                .push_bind(&e.associate_id)
+                .push_bind(&e.organization_name)
+                .push_bind(&e.doing_business_as_name)
+                .push_bind(e.incorporation_date)
+                .push_bind(&e.incorporation_state)
+                .push_bind(&e.organization_type_structure)
+                .push_bind(&e.organization_other_type_text)
+                .push_bind(&e.proprietary_nonprofit)
+                .push_bind(&e.address_line_1)
+                .push_bind(&e.address_line_2)
+                .push_bind(&e.city)
+                .push_bind(&e.state_code)
+                .push_bind(&e.zip_code)
+                .push_bind(&e.practice_location_type)
+                .push_bind(&e.location_other_type_text)
+                .push_bind(e.is_critical_access_hospital())
+                .push_bind(e.is_rural_emergency_hospital())
+                .push_bind(e.subgroup_general)
+                .push_bind(e.subgroup_acute_care)
+                .push_bind(e.subgroup_alcohol_drug)
+                .push_bind(e.subgroup_childrens)
+                .push_bind(e.subgroup_long_term)
+                .push_bind(e.subgroup_psychiatric)
+                .push_bind(e.subgroup_rehabilitation)
+                .push_bind(e.subgroup_short_term)
+                .push_bind(e.subgroup_swing_bed_approved)
+                .push_bind(e.subgroup_psychiatric_unit)
+                .push_bind(e.subgroup_rehabilitation_unit)
+                .push_bind(e.subgroup_specialty_hospital)
+                .push_bind(e.subgroup_other)
+                .push_bind(&e.subgroup_other_text)
+                .push_bind(e.reh_conversion_flag)
+                .push_bind(e.reh_conversion_date)
+                .push_bind(&e.cah_or_hospital_ccn);
         });
 
-        // Use the functional index expressions for ON CONFLICT
-        // We do a dummy update to ensure RETURNING id includes existing rows
-        query_builder.push(
-            " ON CONFLICT (
-                COALESCE(street_address, ''), 
-                COALESCE(city, ''), 
-                COALESCE(state_code, ''), 
-                COALESCE(zip_code, '')
-            ) DO UPDATE SET street_address = EXCLUDED.street_address",
-        );
+        // A location listed twice for the same enrollment is kept once.
+        query_builder.push(format!(
+            " ON CONFLICT ({ENROLLMENT_LOCATION_IDENTITY}) DO NOTHING"
+        ));
 
-        query_builder.push(" RETURNING id");
+        inserted += query_builder
+            .build()
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+    }
 
-        let rows = query_builder.build().fetch_all(&mut *tx).await?;
-        for row in rows {
-            all_ids.push(row.get(0));
-        }
+    Ok(inserted)
+}
+
+/// Replaces the contents of `zcta_centroids` with the gazetteer's ZCTAs.
+pub async fn replace_zcta_centroids(
+    conn: &mut PgConnection,
+    rows: &[ZctaGazetteerRow],
+) -> Result<()> {
+    sqlx::query("DELETE FROM zcta_centroids")
+        .execute(&mut *conn)
+        .await?;
+
+    // 3 columns, so 10000 rows stay under the 65535 parameter limit.
+    const BATCH_SIZE: usize = 10_000;
+
+    for chunk in rows.chunks(BATCH_SIZE) {
+        let mut query_builder: QueryBuilder<Postgres> =
+            QueryBuilder::new("INSERT INTO zcta_centroids (zcta, latitude, longitude) ");
+        query_builder.push_values(chunk, |mut b, r| {
+            b.push_bind(&r.zcta)
+                .push_bind(r.latitude)
+                .push_bind(r.longitude);
+        });
+        query_builder.push(" ON CONFLICT (zcta) DO NOTHING");
+        query_builder.build().execute(&mut *conn).await?;
     }
 
-    tx.commit().await?;
-    Ok(all_ids)
+    Ok(())
 }
 
-pub async fn bulk_insert_providers(pool: &PgPool, providers: &[Provider]) -> Result<()> {
-    let mut tx = pool.begin().await?;
+/// Replaces the contents of `county_centroids` with the gazetteer's counties.
+pub async fn replace_county_centroids(
+    conn: &mut PgConnection,
+    rows: &[CountyGazetteerRow],
+) -> Result<()> {
+    sqlx::query("DELETE FROM county_centroids")
+        .execute(&mut *conn)
+        .await?;
 
-    const BATCH_SIZE: usize = 1000;
+    // 5 columns, so 10000 rows stay under the 65535 parameter limit.
+    const BATCH_SIZE: usize = 10_000;
 
-    for chunk in providers.chunks(BATCH_SIZE) {
+    for chunk in rows.chunks(BATCH_SIZE) {
         let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
-            "INSERT INTO providers (
-                cms_certification_number, name, provider_subtype_id, medicaid_vendor_number, provider_type_id,
-                address_id, original_participation_date, certification_date, termination_expiration_date,
-                change_of_ownership_date, asc_begin_service_date, processing_date, phone_number, fax_number,
-                accreditation_type_code, intermediary_carrier_code, acceptable_poc_switch, fiscal_year_end_date,
-                compliance_status_code, certification_action_type_code, bed_count, certified_bed_count,
-                hospice_bed_count, aids_bed_count, alzheimer_bed_count, dialysis_bed_count,
-                disabled_children_bed_count, head_trauma_bed_count, huntington_disease_bed_count,
-                medicare_medicaid_snf_bed_count, medicare_snf_bed_count, rehab_bed_count, ventilator_bed_count,
-                lpn_lvn_count, rn_count, employee_count, change_of_ownership_switch, hospital_based_switch,
-                multi_owned_facility_switch, clia_lab_number, facility_category_code, ownership_type_code
-            ) ",
+            "INSERT INTO county_centroids (geoid, state_code, name, latitude, longitude) ",
         );
+        query_builder.push_values(chunk, |mut b, r| {
+            b.push_bind(&r.geoid)
+                .push_bind(&r.state_code)
+                .push_bind(&r.name)
+                .push_bind(r.latitude)
+                .push_bind(r.longitude);
+        });
+        query_builder.push(" ON CONFLICT (geoid) DO NOTHING");
+        query_builder.build().execute(&mut *conn).await?;
+    }
+
+    Ok(())
+}
+
+/// How many addresses `geocode_addresses` placed by each method, and how many it couldn't.
+#[derive(Debug, Clone, Copy)]
+pub struct GeocodeCounts {
+    /// Addresses whose coordinates changed in this pass.
+    pub updated: u64,
+    pub by_zcta: u64,
+    pub by_county: u64,
+    pub missing: u64,
+}
+
+/// Places every address at the centroid of its ZIP's ZCTA, or failing that of its FIPS
+/// county, from the reference tables loaded so far. Only addresses whose coordinates change
+/// are written, so running it again after each load is cheap.
+pub async fn geocode_addresses(conn: &mut PgConnection) -> Result<GeocodeCounts> {
+    // POS and the gazetteers load concurrently, each in its own transaction. The lock, which
+    // conflicts with itself, has one geocode wait for the other to commit, so it then sees
+    // the other's new addresses or centroids rather than leaving them unplaced.
+    sqlx::query("LOCK TABLE addresses IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *conn)
+        .await?;
 
-        query_builder.push_values(chunk, |mut b, p| {
-            b.push_bind(&p.cms_certification_number)
-                .push_bind(&p.name)
-                .push_bind(p.provider_subtype_id)
-                .push_bind(&p.medicaid_vendor_number)
-                .push_bind(p.provider_type_id)
-                .push_bind(p.address_id)
-                .push_bind(p.original_participation_date)
-                .push_bind(p.certification_date)
-                .push_bind(p.termination_expiration_date)
-                .push_bind(p.change_of_ownership_date)
-                .push_bind(p.asc_begin_service_date)
-                .push_bind(p.processing_date)
-                .push_bind(&p.phone_number)
-                .push_bind(&p.fax_number)
-                .push_bind(&p.accreditation_type_code)
-                .push_bind(&p.intermediary_carrier_code)
-                .push_bind(p.acceptable_poc_switch)
-                .push_bind(&p.fiscal_year_end_date)
-                .push_bind(&p.compliance_status_code)
-                .push_bind(&p.certification_action_type_code)
-                .push_bind(p.bed_count)
-                .push_bind(p.certified_bed_count)
-                .push_bind(p.hospice_bed_count)
-                .push_bind(p.aids_bed_count)
-                .push_bind(p.alzheimer_bed_count)
-                .push_bind(p.dialysis_bed_count)
-                .push_bind(p.disabled_children_bed_count)
-                .push_bind(p.head_trauma_bed_count)
-                .push_bind(p.huntington_disease_bed_count)
-                .push_bind(p.medicare_medicaid_snf_bed_count)
-                .push_bind(p.medicare_snf_bed_count)
-                .push_bind(p.rehab_bed_count)
-                .push_bind(p.ventilator_bed_count)
-                .push_bind(p.lpn_lvn_count)
-                .push_bind(p.rn_count)
-                .push_bind(p.employee_count)
-                .push_bind(p.change_of_ownership_switch)
-                .push_bind(p.hospital_based_switch)
-                .push_bind(p.multi_owned_facility_switch)
-                .push_bind(&p.clia_lab_number)
-                .push_bind(&p.facility_category_code)
-                .push_bind(&p.ownership_type_code);
+    // POS files carry the county's three digits apart from the state's two, but a few rows
+    // hold the full five-digit code in the county column.
+    let updated = sqlx::query(
+        "UPDATE addresses a
+         SET latitude = g.latitude, longitude = g.longitude, geocode_method = g.method
+         FROM (
+             SELECT a.id,
+                 COALESCE(z.latitude, c.latitude) AS latitude,
+                 COALESCE(z.longitude, c.longitude) AS longitude,
+                 CASE
+                     WHEN z.zcta IS NOT NULL THEN 'zcta'
+                     WHEN c.geoid IS NOT NULL THEN 'county'
+                 END AS method
+             FROM addresses a
+             LEFT JOIN zcta_centroids z ON z.zcta = a.zip_code
+             LEFT JOIN county_centroids c ON c.geoid = CASE
+                 WHEN length(a.fips_county_code) = 5 THEN a.fips_county_code
+                 ELSE lpad(a.fips_state_code, 2, '0') || lpad(a.fips_county_code, 3, '0')
+             END
+         ) g
+         WHERE a.id = g.id
+           AND (a.latitude, a.longitude, a.geocode_method)
+               IS DISTINCT FROM (g.latitude, g.longitude, g.method)",
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    let (by_zcta, by_county, missing): (i64, i64, i64) = sqlx::query_as(
+        "SELECT
+             COUNT(*) FILTER (WHERE geocode_method = 'zcta'),
+             COUNT(*) FILTER (WHERE geocode_method = 'county'),
+             COUNT(*) FILTER (WHERE geocode_method IS NULL)
+         FROM addresses",
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(GeocodeCounts {
+        updated,
+        by_zcta: by_zcta as u64,
+        by_county: by_county as u64,
+        missing: missing as u64,
+    })
+}
+
+/// Stores the rows a run rejected, so they can be inspected after it ends.
+pub async fn insert_rejected_rows(
+    pool: &PgPool,
+    run_id: i64,
+    loader_key: &str,
+    rows: &[RejectedRow],
+) -> Result<()> {
+    // 5 columns, so 10000 rows stay under the 65535 parameter limit.
+    const BATCH_SIZE: usize = 10_000;
+
+    for chunk in rows.chunks(BATCH_SIZE) {
+        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO rejected_rows (run_id, loader_key, line_number, reason, record) ",
+        );
+        query_builder.push_values(chunk, |mut b, r| {
+            b.push_bind(run_id)
+                .push_bind(loader_key)
+                .push_bind(r.line)
+                .push_bind(&r.reason)
+                .push_bind(&r.record);
         });
+        query_builder.build().execute(pool).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::{Connection, Row};
 
-        query_builder.push(
-            " ON CONFLICT (cms_certification_number) DO UPDATE SET
-            name = EXCLUDED.name,
-            provider_subtype_id = EXCLUDED.provider_subtype_id,
-            medicaid_vendor_number = EXCLUDED.medicaid_vendor_number,
-            provider_type_id = EXCLUDED.provider_type_id,
-            address_id = EXCLUDED.address_id,
-            original_participation_date = EXCLUDED.original_participation_date,
-            certification_date = EXCLUDED.certification_date,
-            termination_expiration_date = EXCLUDED.termination_expiration_date,
-            change_of_ownership_date = EXCLUDED.change_of_ownership_date,
-            asc_begin_service_date = EXCLUDED.asc_begin_service_date,
-            processing_date = EXCLUDED.processing_date,
-            phone_number = EXCLUDED.phone_number,
-            fax_number = EXCLUDED.fax_number,
-            accreditation_type_code = EXCLUDED.accreditation_type_code,
-            intermediary_carrier_code = EXCLUDED.intermediary_carrier_code,
-            acceptable_poc_switch = EXCLUDED.acceptable_poc_switch,
-            fiscal_year_end_date = EXCLUDED.fiscal_year_end_date,
-            compliance_status_code = EXCLUDED.compliance_status_code,
-            certification_action_type_code = EXCLUDED.certification_action_type_code,
-            bed_count = EXCLUDED.bed_count,
-            certified_bed_count = EXCLUDED.certified_bed_count,
-            hospice_bed_count = EXCLUDED.hospice_bed_count,
-            aids_bed_count = EXCLUDED.aids_bed_count,
-            alzheimer_bed_count = EXCLUDED.alzheimer_bed_count,
-            dialysis_bed_count = EXCLUDED.dialysis_bed_count,
-            disabled_children_bed_count = EXCLUDED.disabled_children_bed_count,
-            head_trauma_bed_count = EXCLUDED.head_trauma_bed_count,
-            huntington_disease_bed_count = EXCLUDED.huntington_disease_bed_count,
-            medicare_medicaid_snf_bed_count = EXCLUDED.medicare_medicaid_snf_bed_count,
-            medicare_snf_bed_count = EXCLUDED.medicare_snf_bed_count,
-            rehab_bed_count = EXCLUDED.rehab_bed_count,
-            ventilator_bed_count = EXCLUDED.ventilator_bed_count,
-            lpn_lvn_count = EXCLUDED.lpn_lvn_count,
-            rn_count = EXCLUDED.rn_count,
-            employee_count = EXCLUDED.employee_count,
-            change_of_ownership_switch = EXCLUDED.change_of_ownership_switch,
-            hospital_based_switch = EXCLUDED.hospital_based_switch,
-            multi_owned_facility_switch = EXCLUDED.multi_owned_facility_switch,
-            clia_lab_number = EXCLUDED.clia_lab_number,
-            facility_category_code = EXCLUDED.facility_category_code,
-            ownership_type_code = EXCLUDED.ownership_type_code",
+    /// Geocodes a few addresses against centroids of its own, in a transaction that is rolled
+    /// back. Needs a database with the migrations applied.
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres database in DATABASE_URL"]
+    async fn addresses_fall_back_from_zcta_to_county() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is set");
+        let mut conn = PgConnection::connect(&url).await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+
+        replace_zcta_centroids(
+            &mut tx,
+            &[ZctaGazetteerRow {
+                zcta: "99901".to_string(),
+                latitude: 10.0,
+                longitude: 20.0,
+            }],
+        )
+        .await
+        .unwrap();
+        replace_county_centroids(
+            &mut tx,
+            &[CountyGazetteerRow {
+                geoid: "09001".to_string(),
+                state_code: Some("CT".to_string()),
+                name: Some("Fairfield County".to_string()),
+                latitude: 30.0,
+                longitude: 40.0,
+            }],
+        )
+        .await
+        .unwrap();
+
+        // (street, ZIP, FIPS state, FIPS county) and where each should be placed.
+        let cases = [
+            ("1 ZCTA ST", "99901", "9", "1", Some("zcta")),
+            ("2 COUNTY ST", "99909", "9", "1", Some("county")),
+            ("3 COUNTY ST", "99909", "09", "001", Some("county")),
+            ("4 COUNTY ST", "99909", "", "09001", Some("county")),
+            ("5 NOWHERE ST", "99909", "09", "002", None),
+            ("6 NOWHERE ST", "99909", "", "", None),
+        ];
+        for (street, zip, state, county) in cases.iter().map(|c| (c.0, c.1, c.2, c.3)) {
+            sqlx::query(
+                "INSERT INTO addresses (street_address, city, zip_code, fips_state_code,
+                     fips_county_code)
+                 VALUES ($1, 'GEOCODE TEST', $2, NULLIF($3, ''), NULLIF($4, ''))",
+            )
+            .bind(street)
+            .bind(zip)
+            .bind(state)
+            .bind(county)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        }
+
+        let counts = geocode_addresses(&mut tx).await.unwrap();
+        assert!(counts.updated >= 4);
+
+        for (street, _, _, _, method) in cases {
+            let row = sqlx::query(
+                "SELECT latitude, longitude, geocode_method FROM addresses
+                 WHERE street_address = $1 AND city = 'GEOCODE TEST'",
+            )
+            .bind(street)
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+            let placed: (Option<f64>, Option<f64>, Option<String>) =
+                (row.get(0), row.get(1), row.get(2));
+            let expected = match method {
+                Some("zcta") => (Some(10.0), Some(20.0), Some("zcta".to_string())),
+                Some(_) => (Some(30.0), Some(40.0), Some("county".to_string())),
+                None => (None, None, None),
+            };
+            assert_eq!(placed, expected, "{}", street);
+        }
+
+        // Nothing changes the second time.
+        assert_eq!(geocode_addresses(&mut tx).await.unwrap().updated, 0);
+        tx.rollback().await.unwrap();
+    }
+
+    /// Starts a transaction in which only `providers` are active, with `staged` in the file.
+    async fn stage_pos(conn: &mut PgConnection, providers: &[&str], staged: &[&str]) {
+        sqlx::query("UPDATE providers SET retired_at = NOW() WHERE retired_at IS NULL")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO providers (cms_certification_number)
+             SELECT * FROM UNNEST($1::TEXT[])
+             ON CONFLICT (cms_certification_number) DO UPDATE SET retired_at = NULL",
+        )
+        .bind(providers)
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+
+        create_pos_staging(conn).await.unwrap();
+        sqlx::query(
+            "INSERT INTO pos_staging (line, cms_certification_number)
+             SELECT line, ccn FROM UNNEST($1::TEXT[]) WITH ORDINALITY AS s(ccn, line)",
+        )
+        .bind(staged)
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres database in DATABASE_URL"]
+    async fn retiring_too_many_providers_fails() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is set");
+        let mut conn = PgConnection::connect(&url).await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+        let providers = ["999001", "999002", "999003", "999004"];
+        stage_pos(&mut tx, &providers, &providers[..3]).await;
+
+        let error = merge_pos_staging(&mut tx, &[], 0.2).await.unwrap_err();
+        assert!(
+            error.to_string().contains("would retire 1 of 4"),
+            "{}",
+            error
         );
 
-        query_builder.build().execute(&mut *tx).await?;
+        let counts = merge_pos_staging(&mut tx, &[], 0.25).await.unwrap();
+        assert_eq!(counts.retired, 1);
+        tx.rollback().await.unwrap();
     }
 
-    tx.commit().await?;
-    Ok(())
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres database in DATABASE_URL"]
+    async fn an_empty_file_retires_nothing() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is set");
+        let mut conn = PgConnection::connect(&url).await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+        stage_pos(&mut tx, &["999001"], &[]).await;
+
+        let error = merge_pos_staging(&mut tx, &[], 1.0).await.unwrap_err();
+        assert!(
+            error.to_string().contains("lists no providers"),
+            "{}",
+            error
+        );
+        tx.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres database in DATABASE_URL"]
+    async fn a_new_providers_column_writes_no_new_versions() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is set");
+        let mut conn = PgConnection::connect(&url).await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+        let providers = ["999001", "999002"];
+        stage_pos(&mut tx, &providers, &providers).await;
+        merge_pos_staging(&mut tx, &[], 0.0).await.unwrap();
+
+        sqlx::query("ALTER TABLE providers ADD COLUMN note TEXT DEFAULT 'added later'")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        sqlx::query("DROP TABLE pos_staging")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        stage_pos(&mut tx, &providers, &providers).await;
+        let counts = merge_pos_staging(&mut tx, &[], 0.0).await.unwrap();
+        assert_eq!(counts.new_versions, 0);
+        tx.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres database in DATABASE_URL"]
+    async fn every_practice_location_of_an_enrollment_is_kept() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is set");
+        let mut conn = PgConnection::connect(&url).await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+
+        let location = |address: &str| HospitalEnrollment {
+            enrollment_id: "O20000000001".to_string(),
+            address_line_1: Some(address.to_string()),
+            city: Some("BOSTON".to_string()),
+            ..Default::default()
+        };
+        let enrollments = [
+            location("1 MAIN ST"),
+            location("2 MAIN ST"),
+            location("1 MAIN ST"),
+        ];
+        let inserted = replace_hospital_enrollments(&mut tx, &enrollments)
+            .await
+            .unwrap();
+        assert_eq!(inserted, 2);
+
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM hospital_enrollments")
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+        assert_eq!(rows, 2);
+        tx.rollback().await.unwrap();
+    }
 }
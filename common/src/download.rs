@@ -0,0 +1,710 @@
+//! Resumable file downloads with retries.
+//!
+//! The body is streamed to `<path>.part` and only renamed to `path` once its length matches
+//! what the server announced, so a file at `path` is always complete. When the connection
+//! drops, the next attempt asks for the rest of the file with a `Range` request, guarded by
+//! `If-Range` so a file that changed in the meantime is downloaded again from the start.
+//! The validators `If-Range` needs are saved to `<path>.part.meta`, so a download that a
+//! previous run left unfinished resumes too.
+//! The SHA-256 of the file is computed from the chunks as they arrive, so it is ready as soon
+//! as the download is, without reading the file back.
+
+use anyhow::{Result, anyhow};
+use rand::Rng;
+use reqwest::StatusCode;
+use reqwest::header::{
+    CONTENT_RANGE, ETAG, HeaderMap, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED,
+    RANGE, RETRY_AFTER,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::{info, warn};
+
+use crate::http::HttpClient;
+use crate::traits::HttpValidators;
+
+/// How often, and how patiently, a download is retried after a transient failure.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first.
+    pub max_attempts: u32,
+    /// The delay before the first retry. It doubles with every further retry.
+    pub initial_backoff: Duration,
+    /// The longest delay between two attempts.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before retry number `retry` (starting at 1): exponential, capped, with jitter
+    /// so that several clients failing together don't retry in lockstep.
+    fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry - 1))
+            .min(self.max_backoff);
+        exponential.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    /// The delay before retrying after `failure`, honoring the server's Retry-After within
+    /// the policy's limit.
+    fn delay(&self, retry: u32, failure: &Failure) -> Duration {
+        self.backoff(retry).max(
+            failure
+                .retry_after
+                .unwrap_or_default()
+                .min(self.max_backoff),
+        )
+    }
+}
+
+/// The outcome of `download`.
+pub enum DownloadOutcome {
+    /// The server answered 304 Not Modified to the conditional request.
+    NotModified,
+    /// The file is complete at the requested path.
+    Downloaded {
+        validators: HttpValidators,
+        /// The hex-encoded SHA-256 of the file.
+        sha256: String,
+    },
+}
+
+/// Downloads `url` to `path`, retrying transient failures and resuming where they left off.
+///
+/// With `cached` validators the first request is conditional, unless an earlier run left a
+/// `.part` file of `url` to resume. A `.part` file without the validators it was started
+/// from is discarded, since nothing tells which version of the file it holds.
+pub async fn download(
+    http: &HttpClient,
+    url: &str,
+    path: &Path,
+    cached: Option<&HttpValidators>,
+) -> Result<DownloadOutcome> {
+    let policy = &http.retry;
+    let part = part_path(path);
+    let meta = meta_path(&part);
+
+    // The validators of the response the `.part` file was started from, and the hash of
+    // what it holds so far.
+    let (mut started, mut hasher) = match resumable_part(url, &part, &meta).await? {
+        Some((validators, hasher)) => (Some(validators), hasher),
+        None => (None, Sha256::new()),
+    };
+    let mut attempt = 1;
+    loop {
+        match try_download(http, url, &part, cached, &mut started, &mut hasher).await {
+            Ok(Some(validators)) => {
+                fs::rename(&part, path).await?;
+                remove_if_exists(&meta).await?;
+                return Ok(DownloadOutcome::Downloaded {
+                    validators,
+                    sha256: hex::encode(hasher.finalize()),
+                });
+            }
+            Ok(None) => {
+                info!("Server reports {} is not modified.", url);
+                return Ok(DownloadOutcome::NotModified);
+            }
+            Err(failure) if failure.transient && attempt < policy.max_attempts => {
+                let delay = policy.delay(attempt, &failure);
+                warn!(
+                    "Download of {} failed (attempt {} of {}): {:#}. Retrying in {:.1}s.",
+                    url,
+                    attempt,
+                    policy.max_attempts,
+                    failure.error,
+                    delay.as_secs_f64()
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(failure) => {
+                // Keep the `.part` file only while it can still be resumed, by the next run.
+                if !failure.transient {
+                    let _ = fs::remove_file(&part).await;
+                    let _ = fs::remove_file(&meta).await;
+                }
+                return Err(failure.error.context(format!(
+                    "Download of {} failed after {} attempts",
+                    url, attempt
+                )));
+            }
+        }
+    }
+}
+
+/// Fetches `url` into memory, with the same timeouts and retries as `download`. Meant for
+/// small documents such as the dataset catalog.
+pub async fn fetch(http: &HttpClient, url: &str) -> Result<Vec<u8>> {
+    let policy = &http.retry;
+    let mut attempt = 1;
+    loop {
+        match try_fetch(http, url).await {
+            Ok(body) => return Ok(body),
+            Err(failure) if failure.transient && attempt < policy.max_attempts => {
+                let delay = policy.delay(attempt, &failure);
+                warn!(
+                    "Request for {} failed (attempt {} of {}): {:#}. Retrying in {:.1}s.",
+                    url,
+                    attempt,
+                    policy.max_attempts,
+                    failure.error,
+                    delay.as_secs_f64()
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(failure) => {
+                return Err(failure.error.context(format!(
+                    "Request for {} failed after {} attempts",
+                    url, attempt
+                )));
+            }
+        }
+    }
+}
+
+async fn try_fetch(http: &HttpClient, url: &str) -> Result<Vec<u8>, Failure> {
+    let mut response = within(http.read_timeout, http.client().get(url).send()).await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(Failure::status(status, url, response.headers()));
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = within(http.read_timeout, response.chunk()).await? {
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// The hex-encoded SHA-256 of a file already on disk.
+pub async fn sha256_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hash_file(path, &mut hasher).await?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+async fn hash_file(path: &Path, hasher: &mut Sha256) -> Result<()> {
+    let mut file = File::open(path).await?;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let count = file.read(&mut buffer).await?;
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buffer[..count]);
+    }
+    Ok(())
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+fn meta_path(part: &Path) -> PathBuf {
+    let mut meta = part.as_os_str().to_owned();
+    meta.push(".meta");
+    PathBuf::from(meta)
+}
+
+async fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// What `<path>.part.meta` holds: where the `.part` file comes from, and the validators of
+/// the response it was started from.
+#[derive(Serialize, Deserialize)]
+struct PartMeta {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    content_length: Option<i64>,
+}
+
+async fn save_meta(meta: &Path, url: &str, validators: &HttpValidators) -> std::io::Result<()> {
+    let saved = PartMeta {
+        url: url.to_string(),
+        etag: validators.etag.clone(),
+        last_modified: validators.last_modified.clone(),
+        content_length: validators.content_length,
+    };
+    fs::write(meta, serde_json::to_vec(&saved)?).await
+}
+
+/// The validators of a `.part` file of `url` that an earlier run left behind, with the hash
+/// of what it holds. Anything else found at `part` is removed.
+async fn resumable_part(
+    url: &str,
+    part: &Path,
+    meta: &Path,
+) -> Result<Option<(HttpValidators, Sha256)>> {
+    let saved = match fs::read(meta).await {
+        Ok(bytes) => serde_json::from_slice::<PartMeta>(&bytes)
+            .ok()
+            .filter(|saved| saved.url == url),
+        Err(_) => None,
+    };
+    match saved {
+        Some(saved) if fs::try_exists(part).await? => {
+            let mut hasher = Sha256::new();
+            hash_file(part, &mut hasher).await?;
+            info!("Found an unfinished download of {}.", url);
+            let validators = HttpValidators {
+                etag: saved.etag,
+                last_modified: saved.last_modified,
+                content_length: saved.content_length,
+            };
+            Ok(Some((validators, hasher)))
+        }
+        _ => {
+            remove_if_exists(part).await?;
+            remove_if_exists(meta).await?;
+            Ok(None)
+        }
+    }
+}
+
+/// A failed attempt, and whether trying again could help.
+struct Failure {
+    error: anyhow::Error,
+    transient: bool,
+    /// How long the server asked us to wait, from `Retry-After`.
+    retry_after: Option<Duration>,
+}
+
+impl Failure {
+    fn transient(error: impl Into<anyhow::Error>) -> Self {
+        Self {
+            error: error.into(),
+            transient: true,
+            retry_after: None,
+        }
+    }
+
+    fn permanent(error: impl Into<anyhow::Error>) -> Self {
+        Self {
+            error: error.into(),
+            transient: false,
+            retry_after: None,
+        }
+    }
+
+    /// An error status. Server errors, timeouts and rate limiting are worth retrying.
+    fn status(status: StatusCode, url: &str, headers: &HeaderMap) -> Self {
+        let transient = status.is_server_error()
+            || status == StatusCode::REQUEST_TIMEOUT
+            || status == StatusCode::TOO_MANY_REQUESTS;
+        Self {
+            error: anyhow!("HTTP {} from {}", status, url),
+            transient,
+            retry_after: retry_after(headers),
+        }
+    }
+}
+
+impl From<std::io::Error> for Failure {
+    fn from(error: std::io::Error) -> Self {
+        Failure::permanent(error)
+    }
+}
+
+/// One request, appending to `part` if it already holds the start of the file, and feeding
+/// the same bytes to `hasher`. Returns the file's validators once `part` is complete, or
+/// `None` for a 304.
+async fn try_download(
+    http: &HttpClient,
+    url: &str,
+    part: &Path,
+    cached: Option<&HttpValidators>,
+    started: &mut Option<HttpValidators>,
+    hasher: &mut Sha256,
+) -> Result<Option<HttpValidators>, Failure> {
+    let offset = match fs::metadata(part).await {
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    };
+
+    let mut request = http.client().get(url);
+    // `If-Range` needs a strong ETag or a date; without either the rest can't be requested safely.
+    let if_range = started.as_ref().and_then(|v| {
+        v.etag
+            .clone()
+            .filter(|etag| !etag.starts_with("W/"))
+            .or_else(|| v.last_modified.clone())
+    });
+    let resume_from = match if_range {
+        Some(if_range) if offset > 0 => {
+            info!("Resuming {} at byte {}...", url, offset);
+            request = request
+                .header(RANGE, format!("bytes={}-", offset))
+                .header(IF_RANGE, if_range);
+            offset
+        }
+        _ => {
+            if started.is_none()
+                && let Some(cached) = cached
+            {
+                if let Some(etag) = &cached.etag {
+                    request = request.header(IF_NONE_MATCH, etag);
+                }
+                if let Some(last_modified) = &cached.last_modified {
+                    request = request.header(IF_MODIFIED_SINCE, last_modified);
+                }
+            }
+            0
+        }
+    };
+
+    let mut response = within(http.read_timeout, request.send()).await?;
+    let status = response.status();
+    if status == StatusCode::NOT_MODIFIED && started.is_none() {
+        return Ok(None);
+    }
+    if !status.is_success() {
+        return Err(Failure::status(status, url, response.headers()));
+    }
+
+    // A 206 continues the part file; anything else is the whole file, from the start.
+    let (append, expected_length) = if status == StatusCode::PARTIAL_CONTENT && resume_from > 0 {
+        let (start, total) = content_range(response.headers())
+            .ok_or_else(|| Failure::transient(anyhow!("Invalid Content-Range from {}", url)))?;
+        if start != resume_from {
+            fs::remove_file(part).await?;
+            return Err(Failure::transient(anyhow!(
+                "Server resumed at byte {} instead of {}",
+                start,
+                resume_from
+            )));
+        }
+        (true, total)
+    } else {
+        let validators = validators(response.headers(), response.content_length());
+        let length = response.content_length();
+        save_meta(&meta_path(part), url, &validators).await?;
+        *started = Some(validators);
+        (false, length)
+    };
+
+    if !append {
+        *hasher = Sha256::new();
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(part)
+        .await?;
+    while let Some(chunk) = within(http.read_timeout, response.chunk()).await? {
+        file.write_all(&chunk).await?;
+        hasher.update(&chunk);
+    }
+    file.flush().await?;
+
+    let length = fs::metadata(part).await?.len();
+    match expected_length {
+        Some(expected) if length < expected => Err(Failure::transient(anyhow!(
+            "Connection closed after {} of {} bytes",
+            length,
+            expected
+        ))),
+        Some(expected) if length > expected => {
+            fs::remove_file(part).await?;
+            Err(Failure::transient(anyhow!(
+                "Received {} bytes, but the server announced {}",
+                length,
+                expected
+            )))
+        }
+        _ => Ok(started.clone()),
+    }
+}
+
+/// Waits for `future` for at most `timeout`, which is a transient failure like any other
+/// stalled connection.
+async fn within<T>(
+    timeout: Duration,
+    future: impl Future<Output = reqwest::Result<T>>,
+) -> Result<T, Failure> {
+    match tokio::time::timeout(timeout, future).await {
+        Ok(result) => result.map_err(classify),
+        Err(_) => Err(Failure::transient(anyhow!(
+            "No data received for {}s",
+            timeout.as_secs()
+        ))),
+    }
+}
+
+/// Connection problems and timeouts are worth retrying; malformed requests are not.
+fn classify(error: reqwest::Error) -> Failure {
+    if error.is_builder() || error.is_redirect() {
+        Failure::permanent(error)
+    } else {
+        Failure::transient(error)
+    }
+}
+
+fn header(headers: &HeaderMap, name: reqwest::header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+fn validators(headers: &HeaderMap, content_length: Option<u64>) -> HttpValidators {
+    HttpValidators {
+        etag: header(headers, ETAG),
+        last_modified: header(headers, LAST_MODIFIED),
+        content_length: content_length.map(|l| l as i64),
+    }
+}
+
+/// Parses `Content-Range: bytes <start>-<end>/<total>` into the start and, if known, the total.
+fn content_range(headers: &HeaderMap) -> Option<(u64, Option<u64>)> {
+    let value = header(headers, CONTENT_RANGE)?;
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let start = range.split_once('-')?.0.parse().ok()?;
+    Some((start, total.parse().ok()))
+}
+
+/// `Retry-After` in seconds. The HTTP-date form is rare enough to ignore.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    header(headers, RETRY_AFTER)?
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::args::HttpArguments;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    /// A client that gives up quickly and retries without waiting.
+    pub(crate) fn client(max_attempts: u32) -> HttpClient {
+        let mut http = HttpClient::new(&HttpArguments {
+            http_proxy: None,
+            connect_timeout: 5,
+            read_timeout: 5,
+            max_attempts,
+            user_agent: "test".to_string(),
+        })
+        .unwrap();
+        http.read_timeout = Duration::from_millis(200);
+        http.retry.initial_backoff = Duration::from_millis(1);
+        http.retry.max_backoff = Duration::from_millis(1);
+        http
+    }
+
+    /// Serves one canned response per connection, in order, and records each request's head.
+    /// `None` accepts the connection and never answers.
+    pub(crate) async fn serve(responses: Vec<Option<String>>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut head = Vec::new();
+                let mut buffer = [0; 1024];
+                while !head.ends_with(b"\r\n\r\n") {
+                    let count = stream.read(&mut buffer).await.unwrap();
+                    if count == 0 {
+                        break;
+                    }
+                    head.extend_from_slice(&buffer[..count]);
+                }
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&head).to_lowercase());
+                match response {
+                    Some(response) => {
+                        let _ = stream.write_all(response.as_bytes()).await;
+                        // Let the client read what was sent before it sees the connection
+                        // close, as it would from a connection that drops mid-body.
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        let _ = stream.shutdown().await;
+                    }
+                    // Hold the connection open while the next one is served.
+                    None => {
+                        tokio::spawn(async move {
+                            tokio::time::sleep(Duration::from_secs(5)).await;
+                            drop(stream);
+                        });
+                    }
+                }
+            }
+        });
+        (url, requests)
+    }
+
+    pub(crate) fn ok(body: &str) -> Option<String> {
+        Some(format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        ))
+    }
+
+    fn status(code: &str) -> Option<String> {
+        Some(format!(
+            "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            code
+        ))
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("download-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn sha256(bytes: &[u8]) -> String {
+        hex::encode(Sha256::digest(bytes))
+    }
+
+    fn downloaded(outcome: DownloadOutcome) -> (HttpValidators, String) {
+        match outcome {
+            DownloadOutcome::Downloaded { validators, sha256 } => (validators, sha256),
+            DownloadOutcome::NotModified => panic!("not downloaded"),
+        }
+    }
+
+    #[tokio::test]
+    async fn download_resumes_a_part_left_by_an_earlier_run() {
+        let dir = temp_dir("resume");
+        let path = dir.join("file.csv");
+        let (url, requests) = serve(vec![
+            Some("HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: 11\r\n\r\nhello ".to_string()),
+            Some("HTTP/1.1 206 Partial Content\r\nETag: \"v1\"\r\nContent-Range: bytes 6-10/11\r\nContent-Length: 5\r\n\r\nworld".to_string()),
+        ])
+        .await;
+
+        // The first run is cut off after "hello " and gives up.
+        assert!(download(&client(1), &url, &path, None).await.is_err());
+        assert_eq!(std::fs::read(part_path(&path)).unwrap(), b"hello ");
+        assert!(meta_path(&part_path(&path)).exists());
+
+        // The next run asks for the rest, as long as it is the same version.
+        let (_, hash) = downloaded(download(&client(1), &url, &path, None).await.unwrap());
+        let request = &requests.lock().unwrap()[1];
+        assert!(request.contains("range: bytes=6-"), "{}", request);
+        assert!(request.contains("if-range: \"v1\""), "{}", request);
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+        assert_eq!(hash, sha256(b"hello world"));
+        assert!(!part_path(&path).exists());
+        assert!(!meta_path(&part_path(&path)).exists());
+    }
+
+    #[tokio::test]
+    async fn download_restarts_a_part_without_validators() {
+        let dir = temp_dir("restart");
+        let path = dir.join("file.csv");
+        std::fs::write(part_path(&path), b"stale").unwrap();
+        let (url, requests) = serve(vec![ok("fresh")]).await;
+
+        let (_, hash) = downloaded(download(&client(1), &url, &path, None).await.unwrap());
+        assert!(!requests.lock().unwrap()[0].contains("range:"));
+        assert_eq!(std::fs::read(&path).unwrap(), b"fresh");
+        assert_eq!(hash, sha256(b"fresh"));
+    }
+
+    #[tokio::test]
+    async fn download_restarts_a_part_of_another_url() {
+        let dir = temp_dir("other-url");
+        let path = dir.join("file.csv");
+        std::fs::write(part_path(&path), b"hello ").unwrap();
+        save_meta(
+            &meta_path(&part_path(&path)),
+            "http://127.0.0.1:1/old",
+            &HttpValidators {
+                etag: Some("\"v1\"".to_string()),
+                ..HttpValidators::default()
+            },
+        )
+        .await
+        .unwrap();
+        let (url, requests) = serve(vec![ok("fresh")]).await;
+
+        downloaded(download(&client(1), &url, &path, None).await.unwrap());
+        assert!(!requests.lock().unwrap()[0].contains("range:"));
+        assert_eq!(std::fs::read(&path).unwrap(), b"fresh");
+    }
+
+    #[tokio::test]
+    async fn download_starts_over_when_the_file_changed() {
+        let dir = temp_dir("changed");
+        let path = dir.join("file.csv");
+        let (url, requests) = serve(vec![ok("brand new")]).await;
+        std::fs::write(part_path(&path), b"hello ").unwrap();
+        save_meta(
+            &meta_path(&part_path(&path)),
+            &url,
+            &HttpValidators {
+                etag: Some("\"v1\"".to_string()),
+                ..HttpValidators::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        // If-Range doesn't match, so the server sends the whole new file.
+        let (_, hash) = downloaded(download(&client(1), &url, &path, None).await.unwrap());
+        assert!(requests.lock().unwrap()[0].contains("if-range: \"v1\""));
+        assert_eq!(std::fs::read(&path).unwrap(), b"brand new");
+        assert_eq!(hash, sha256(b"brand new"));
+    }
+
+    #[tokio::test]
+    async fn fetch_retries_server_errors() {
+        let (url, requests) = serve(vec![status("503 Service Unavailable"), ok("{}")]).await;
+        assert_eq!(fetch(&client(3), &url).await.unwrap(), b"{}");
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn fetch_does_not_retry_client_errors() {
+        let (url, requests) = serve(vec![status("404 Not Found"), ok("{}")]).await;
+        let error = fetch(&client(3), &url).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("HTTP 404"), "{:#}", error);
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn fetch_gives_up_on_a_stalled_server() {
+        let (url, requests) = serve(vec![None, None]).await;
+        let error = fetch(&client(2), &url).await.unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("No data received"),
+            "{:#}",
+            error
+        );
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+}
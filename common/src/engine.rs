@@ -1,59 +1,186 @@
-use anyhow::Result;
-use sqlx::PgPool;
-use std::collections::HashMap;
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use sqlx::{PgConnection, PgPool};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::future::Future;
 use std::path::Path;
-use tracing::{info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tracing::{error, info, warn};
 
-use crate::traits::{CmsDataLoader, FileHash};
+use crate::catalog::Catalog;
+use crate::http::HttpClient;
+use crate::lock::{AdvisoryLock, AlreadyRunning, LockScope};
+use crate::schema::SchemaDiff;
+use crate::traits::{
+    CmsDataLoader, CmsMetadata, DatasetSource, FetchOutcome, FileHash, HttpValidators, LoadCounts,
+    LoaderContext, ResolvedSource,
+};
+use crate::validation::RejectedRow;
 
 #[derive(Debug, Clone)]
 pub struct LoaderRunStatus {
     pub version: i32,
     pub file_hash: String,
+    pub last_run: DateTime<Utc>,
+    pub source_url: Option<String>,
+    pub source_modified: Option<DateTime<Utc>>,
+    pub validators: HttpValidators,
+}
+
+/// What a run has learned so far, written to `loader_run_history` when it ends.
+#[derive(Default)]
+struct RunRecord {
+    source_url: Option<String>,
+    file_hash: Option<String>,
+    counts: Option<LoadCounts>,
+    rejected: Vec<RejectedRow>,
+    schema_diff: Option<SchemaDiff>,
+}
+
+/// Which loaders a run covers, and how.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Only run these loaders. Empty means every registered loader.
+    pub only: Vec<String>,
+    /// Never run these loaders.
+    pub skip: Vec<String>,
+    /// Load even if the file and plugin version are unchanged.
+    pub force: bool,
+    /// Download and load into a transaction that is rolled back, to report what would change.
+    /// Nothing is written, not even the run history.
+    pub dry_run: bool,
+    /// Whether to lock each loader or the whole run against other processes.
+    pub lock: LockScope,
+    /// How long to wait for another process to release a lock before giving up.
+    pub lock_timeout: Duration,
+    /// How many loaders may run at once. Loaders still wait for their dependencies.
+    pub concurrency: usize,
+    /// The largest fraction of a file's rows that may be rejected before the run fails and
+    /// is rolled back. `None` accepts any number of rejections.
+    pub max_rejected_rate: Option<f64>,
+}
+
+/// How a run that did not fail ended.
+enum RunOutcome {
+    /// The file was loaded.
+    Succeeded,
+    /// The file was unchanged, so nothing was loaded.
+    Skipped,
+    /// The file was loaded and rolled back, because this was a dry run.
+    RolledBack,
+}
+
+impl RunOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RunOutcome::Succeeded => "succeeded",
+            RunOutcome::Skipped => "skipped",
+            RunOutcome::RolledBack => "rolled back",
+        }
+    }
 }
 
 pub struct LoaderEngine {
     pool: PgPool,
-    // Cache of known loader states from DB: key -> status
-    loader_states: HashMap<String, LoaderRunStatus>,
+    // Where to fetch the DCAT catalog from, and the catalog once fetched
+    catalog_url: String,
+    catalog: OnceCell<Catalog>,
+    // Shared by the catalog fetch and every loader's downloads
+    http: HttpClient,
+    // Cache of known loader states from DB: key -> status. Loaders running concurrently
+    // update it, but never hold the lock across an await.
+    loader_states: Mutex<HashMap<String, LoaderRunStatus>>,
     // Registry of loaded plugins
     registry: HashMap<String, Box<dyn CmsDataLoader + Send + Sync>>,
+    // Set to stop starting loaders; those already running finish
+    stop: Arc<AtomicBool>,
 }
 
 impl LoaderEngine {
-    pub async fn new(pool: PgPool) -> Result<Self> {
+    pub async fn new(
+        pool: PgPool,
+        catalog_url: impl Into<String>,
+        http: HttpClient,
+    ) -> Result<Self> {
         let mut engine = Self {
             pool,
-            loader_states: HashMap::new(),
+            catalog_url: catalog_url.into(),
+            catalog: OnceCell::new(),
+            http,
+            loader_states: Mutex::new(HashMap::new()),
             registry: HashMap::new(),
+            stop: Arc::new(AtomicBool::new(false)),
         };
         engine.scan().await?;
         Ok(engine)
     }
 
-    /// Scans the loader_runs table and populates the local cache.
+    /// Scans the loader_runs table and replaces the local cache with it.
     pub async fn scan(&mut self) -> Result<()> {
         info!("Scanning loader_runs table...");
-        let records = sqlx::query!("SELECT loader_key, version, file_hash FROM loader_runs")
-            .fetch_all(&self.pool)
-            .await?;
+        let records = self.read_states(None).await?;
 
-        for record in records {
-            self.loader_states.insert(
-                record.loader_key,
-                LoaderRunStatus {
-                    version: record.version,
-                    file_hash: record.file_hash,
-                },
-            );
-        }
+        let loader_states = self
+            .loader_states
+            .get_mut()
+            .expect("Loader states lock poisoned");
+        *loader_states = records.into_iter().collect();
         info!(
             "Loaded {} loader states from database.",
-            self.loader_states.len()
+            loader_states.len()
         );
         Ok(())
     }
 
+    /// Reloads the cached state of `key`. Another process may have loaded it since the cache
+    /// was filled, while this one waited for its lock.
+    async fn refresh_status(&self, key: &str) -> Result<()> {
+        let status = self.read_states(Some(key)).await?.pop();
+        let mut states = self.states();
+        match status {
+            Some((key, status)) => states.insert(key, status),
+            None => states.remove(key),
+        };
+        Ok(())
+    }
+
+    /// The rows of `loader_runs`, or only the row of `key`.
+    async fn read_states(&self, key: Option<&str>) -> Result<Vec<(String, LoaderRunStatus)>> {
+        let records = sqlx::query!(
+            "SELECT loader_key, version, file_hash, last_run, source_url, source_modified,
+                    etag, last_modified, content_length
+             FROM loader_runs
+             WHERE $1::TEXT IS NULL OR loader_key = $1",
+            key
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(records
+            .into_iter()
+            .map(|record| {
+                let status = LoaderRunStatus {
+                    version: record.version,
+                    file_hash: record.file_hash,
+                    last_run: record.last_run,
+                    source_url: record.source_url,
+                    source_modified: record.source_modified,
+                    validators: HttpValidators {
+                        etag: record.etag,
+                        last_modified: record.last_modified,
+                        content_length: record.content_length,
+                    },
+                };
+                (record.loader_key, status)
+            })
+            .collect())
+    }
+
     pub fn register(&mut self, loader: Box<dyn CmsDataLoader + Send + Sync>) {
         let key = loader.key().to_string();
         if self.registry.contains_key(&key) {
@@ -62,69 +189,594 @@ impl LoaderEngine {
         self.registry.insert(key, loader);
     }
 
-    pub async fn run(&mut self, data_dir: &Path) -> Result<()> {
+    /// The registered loaders, ordered by key.
+    pub fn loaders(&self) -> Vec<&(dyn CmsDataLoader + Send + Sync)> {
+        let mut loaders: Vec<_> = self.registry.values().map(|l| l.as_ref()).collect();
+        loaders.sort_by(|a, b| a.key().cmp(b.key()));
+        loaders
+    }
+
+    /// Forgets the fetched catalog, so the next run sees releases published since.
+    pub fn clear_catalog(&mut self) {
+        self.catalog = OnceCell::new();
+    }
+
+    /// A flag that, once set, makes `run` start no further loaders. Loaders already running
+    /// are left to finish, so no load is cut off halfway.
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// The last successful load of `key`, as recorded in `loader_runs`.
+    pub fn status(&self, key: &str) -> Option<LoaderRunStatus> {
+        self.states().get(key).cloned()
+    }
+
+    fn states(&self) -> std::sync::MutexGuard<'_, HashMap<String, LoaderRunStatus>> {
+        self.loader_states
+            .lock()
+            .expect("Loader states lock poisoned")
+    }
+
+    /// Checks that every declared dependency is registered, and that no loader depends on
+    /// itself through a cycle.
+    fn check_dependencies(&self) -> Result<()> {
+        for loader in self.registry.values() {
+            if let Some(missing) = loader
+                .dependencies()
+                .iter()
+                .find(|dependency| !self.registry.contains_key(**dependency))
+            {
+                bail!(
+                    "Loader '{}' depends on '{}', which is not registered",
+                    loader.key(),
+                    missing
+                );
+            }
+        }
+
+        // Peel off loaders whose dependencies are all peeled off; whatever remains is a cycle.
+        let mut remaining: BTreeMap<&str, &[&str]> = self
+            .registry
+            .values()
+            .map(|loader| (loader.key(), loader.dependencies()))
+            .collect();
+        loop {
+            let free: Vec<&str> = remaining
+                .iter()
+                .filter(|(_, dependencies)| dependencies.iter().all(|d| !remaining.contains_key(d)))
+                .map(|(key, _)| *key)
+                .collect();
+            if free.is_empty() {
+                break;
+            }
+            for key in free {
+                remaining.remove(key);
+            }
+        }
+        if !remaining.is_empty() {
+            let keys: Vec<&str> = remaining.into_keys().collect();
+            bail!(
+                "Loader dependencies form a cycle between: {}",
+                keys.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// The registered keys selected by `options`, ordered by key. Naming a loader that is not
+    /// registered is an error, so a typo doesn't silently run nothing.
+    fn selected_keys(&self, options: &RunOptions) -> Result<Vec<String>> {
+        if let Some(unknown) = options
+            .only
+            .iter()
+            .chain(&options.skip)
+            .find(|key| !self.registry.contains_key(*key))
+        {
+            let mut known: Vec<&str> = self.registry.keys().map(String::as_str).collect();
+            known.sort();
+            bail!(
+                "Unknown loader '{}'. Registered loaders: {}",
+                unknown,
+                known.join(", ")
+            );
+        }
+
+        let mut keys: Vec<String> = self
+            .registry
+            .keys()
+            .filter(|key| options.only.is_empty() || options.only.contains(key))
+            .filter(|key| !options.skip.contains(key))
+            .cloned()
+            .collect();
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Runs the selected loaders, up to `options.concurrency` at a time, each one only after
+    /// the loaders it depends on succeeded. When a loader fails, the loaders depending on it
+    /// are skipped and the others carry on; the run fails once they are done.
+    ///
+    /// Dependencies left out of the run by `only` or `skip` are assumed to be loaded already.
+    pub async fn run(&self, data_dir: &Path, options: &RunOptions) -> Result<()> {
+        self.check_dependencies()?;
+        let keys = self.selected_keys(options)?;
         info!(
-            "Starting execution of {} registered loaders...",
-            self.registry.len()
-        );
-
-        // Collect keys to avoid mutable/immutable borrow conflicts with `self`
-        let keys: Vec<String> = self.registry.keys().cloned().collect();
-
-        for key in keys {
-            info!("Processing loader: {}", key);
-
-            // 1. Get metadata (downloads file if needed and computes hash)
-            // We need the loader to get metadata.
-            let (metadata, plugin_version) = {
-                let loader = self
-                    .registry
-                    .get(&key)
-                    .expect("Loader missing from registry");
-                (
-                    loader.get_metadata(data_dir).await?,
-                    loader.version() as i32,
-                )
+            "Starting execution of {} of {} registered loaders{}...",
+            keys.len(),
+            self.registry.len(),
+            if options.dry_run { " (dry run)" } else { "" }
+        );
+
+        // A dry run still downloads into the data directory, so it takes the locks too.
+        let global_lock = match options.lock {
+            LockScope::Global => {
+                Some(AdvisoryLock::acquire(&self.pool, None, options.lock_timeout).await?)
+            }
+            LockScope::Loader => None,
+        };
+
+        let result = self
+            .schedule(&keys, options, |key| self.run_one(key, data_dir, options))
+            .await;
+
+        if let Some(lock) = global_lock {
+            lock.release().await?;
+        }
+        result
+    }
+
+    /// Runs `run_one` for each of `keys`, up to `options.concurrency` at a time, in the
+    /// dependency order `run` describes.
+    async fn schedule<'a, F, Fut>(
+        &'a self,
+        keys: &'a [String],
+        options: &RunOptions,
+        run_one: F,
+    ) -> Result<()>
+    where
+        F: Fn(&'a str) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        // Each selected loader, with the selected loaders it still waits for.
+        let mut waiting: BTreeMap<&str, BTreeSet<&str>> = keys
+            .iter()
+            .map(|key| {
+                let dependencies = self.registry[key]
+                    .dependencies()
+                    .iter()
+                    .copied()
+                    .filter(|dependency| keys.iter().any(|k| k == dependency))
+                    .collect();
+                (key.as_str(), dependencies)
+            })
+            .collect();
+
+        let concurrency = options.concurrency.max(1);
+        let mut running = FuturesUnordered::new();
+        let mut failed: Vec<(&str, anyhow::Error)> = Vec::new();
+        let mut blocked: Vec<&str> = Vec::new();
+
+        loop {
+            while running.len() < concurrency && !self.stop.load(Ordering::Relaxed) {
+                let Some(key) = waiting
+                    .iter()
+                    .find(|(_, dependencies)| dependencies.is_empty())
+                    .map(|(key, _)| *key)
+                else {
+                    break;
+                };
+                waiting.remove(key);
+                let run_one = &run_one;
+                running.push(async move { (key, run_one(key).await) });
+            }
+
+            let Some((key, result)) = running.next().await else {
+                break;
             };
+            match result {
+                Ok(()) => {
+                    for dependencies in waiting.values_mut() {
+                        dependencies.remove(key);
+                    }
+                }
+                Err(e) => {
+                    error!("Loader '{}' failed: {:#}", key, e);
+                    // Skip its dependents, and theirs in turn.
+                    let mut unavailable = vec![key];
+                    while let Some(prerequisite) = unavailable.pop() {
+                        let dependents: Vec<&str> = waiting
+                            .iter()
+                            .filter(|(_, dependencies)| dependencies.contains(prerequisite))
+                            .map(|(dependent, _)| *dependent)
+                            .collect();
+                        for dependent in dependents {
+                            warn!(
+                                "Skipping loader '{}': its prerequisite '{}' did not complete.",
+                                dependent, prerequisite
+                            );
+                            waiting.remove(dependent);
+                            blocked.push(dependent);
+                            unavailable.push(dependent);
+                        }
+                    }
+                    failed.push((key, e));
+                }
+            }
+        }
+
+        if !waiting.is_empty() {
+            let keys: Vec<&str> = waiting.into_keys().collect();
+            info!("Stop requested: not starting {}.", keys.join(", "));
+        }
 
-            let file_hash_str = match &metadata.file_hash {
-                FileHash::Sha256(h) => h.clone(),
-                FileHash::Sha512(h) => h.clone(),
-                FileHash::Md5(h) => h.clone(),
-                FileHash::RustHasher(h) => h.clone(),
+        if failed.is_empty() {
+            return Ok(());
+        }
+        // Keep a lock conflict recognizable, so the caller can report it as such.
+        if failed.iter().all(|(_, e)| e.is::<AlreadyRunning>()) {
+            return Err(failed.remove(0).1);
+        }
+        let failed_keys: Vec<&str> = failed.iter().map(|(key, _)| *key).collect();
+        let mut message = format!(
+            "{} of {} loaders failed: {}",
+            failed.len(),
+            keys.len(),
+            failed_keys.join(", ")
+        );
+        if !blocked.is_empty() {
+            message.push_str(&format!(
+                ". Skipped because a prerequisite failed: {}",
+                blocked.join(", ")
+            ));
+        }
+        bail!(message)
+    }
+
+    /// Runs one loader under its lock, recording the run in the history.
+    async fn run_one(&self, key: &str, data_dir: &Path, options: &RunOptions) -> Result<()> {
+        info!("Processing loader: {}", key);
+
+        let loader_lock = match options.lock {
+            LockScope::Global => None,
+            LockScope::Loader => {
+                Some(AdvisoryLock::acquire(&self.pool, Some(key), options.lock_timeout).await?)
+            }
+        };
+        self.refresh_status(key).await?;
+
+        let plugin_version = self
+            .registry
+            .get(key)
+            .expect("Loader missing from registry")
+            .version() as i32;
+
+        // Record the attempt before doing anything, so even a run that never finishes
+        // leaves a trace in the history. A dry run leaves no trace at all.
+        let run_id = match options.dry_run {
+            true => None,
+            false => Some(self.start_run(key, plugin_version).await?),
+        };
+        let started = Instant::now();
+        let mut record = RunRecord::default();
+
+        let result = self
+            .run_loader(key, plugin_version, data_dir, options, &mut record)
+            .await;
+
+        if let Some(run_id) = run_id
+            && let Err(e) = self
+                .finish_run(run_id, key, started.elapsed(), &record, &result)
+                .await
+        {
+            warn!("Could not record the outcome of run {}: {:#}", run_id, e);
+        }
+        result?;
+
+        if let Some(lock) = loader_lock {
+            lock.release().await?;
+        }
+        Ok(())
+    }
+
+    /// Runs a single loader, filling in `record` as the source, hash and row counts become known.
+    async fn run_loader(
+        &self,
+        key: &str,
+        plugin_version: i32,
+        data_dir: &Path,
+        options: &RunOptions,
+        record: &mut RunRecord,
+    ) -> Result<RunOutcome> {
+        // 1. Resolve where to download from
+        let source = self
+            .registry
+            .get(key)
+            .expect("Loader missing from registry")
+            .source();
+        let source = self.resolve(source).await?;
+        info!(
+            "Resolved '{}' to {} (modified {:?})",
+            key, source.url, source.modified
+        );
+        record.source_url = Some(source.url.clone());
+
+        // 2. Get metadata (downloads file if needed and computes hash)
+        let outcome = {
+            let loader = self
+                .registry
+                .get(key)
+                .expect("Loader missing from registry");
+            // A forced run needs the file even if the server says it is unchanged.
+            let cached = match options.force {
+                true => None,
+                false => self.cached_validators(key, &source, plugin_version),
             };
+            let context = LoaderContext {
+                data_dir,
+                http: &self.http,
+            };
+            loader
+                .get_metadata(&context, &source, cached.as_ref())
+                .await?
+        };
 
-            // 2. Check if loading is needed
-            if self.should_load(&key, &file_hash_str, plugin_version) {
-                info!("Data needs update/loading for '{}'...", key);
+        let metadata = match outcome {
+            FetchOutcome::Fetched(metadata) => metadata,
+            FetchOutcome::NotModified => {
+                info!(
+                    "Loader '{}' unchanged: server returned 304 Not Modified.",
+                    key
+                );
+                record.file_hash = self
+                    .states()
+                    .get(key)
+                    .map(|status| status.file_hash.clone());
+                return Ok(RunOutcome::Skipped);
+            }
+        };
 
-                // 3. Load data (extracts, parses, and inserts)
-                {
-                    let loader = self
-                        .registry
-                        .get(&key)
-                        .expect("Loader missing from registry");
+        // The file goes whatever the outcome: one left behind by a failed load would be
+        // loaded again by the next run, without asking the server whether it changed.
+        let result = self
+            .load_file(key, plugin_version, options, &source, &metadata, record)
+            .await;
+        let cleanup = self
+            .registry
+            .get(key)
+            .expect("Loader missing from registry")
+            .cleanup(&metadata)
+            .await;
+        let outcome = result?;
+        cleanup?;
+        Ok(outcome)
+    }
 
-                    loader.load(&metadata.file, &self.pool).await?;
-                    loader.cleanup(&metadata).await?;
-                }
+    /// Loads a fetched file unless it is unchanged since the last load, in one transaction
+    /// with the `loader_runs` update.
+    async fn load_file(
+        &self,
+        key: &str,
+        plugin_version: i32,
+        options: &RunOptions,
+        source: &ResolvedSource,
+        metadata: &CmsMetadata,
+        record: &mut RunRecord,
+    ) -> Result<RunOutcome> {
+        let file_hash_str = match &metadata.file_hash {
+            FileHash::Sha256(h) => h.clone(),
+            FileHash::Sha512(h) => h.clone(),
+            FileHash::Md5(h) => h.clone(),
+            FileHash::RustHasher(h) => h.clone(),
+        };
+        record.file_hash = Some(file_hash_str.clone());
+
+        // 3. Check if loading is needed
+        if options.force {
+            info!(
+                "Loader '{}' forced: loading regardless of hash and version.",
+                key
+            );
+        } else if !self.should_load(key, &file_hash_str, plugin_version) {
+            info!("Data up to date for key: {}", key);
 
-                // 4. Update status
-                self.update_status(&key, &file_hash_str, plugin_version)
+            // Keep the validators of this download, so next time the server can say so
+            // without sending the file again.
+            if !options.dry_run {
+                self.update_validators(key, source, &metadata.validators)
                     .await?;
-                info!("Loader '{}' completed successfully.", key);
-            } else {
-                info!("Data up to date for key: {}", key);
             }
+            return Ok(RunOutcome::Skipped);
+        }
+        info!("Data needs update/loading for '{}'...", key);
+        self.check_schema(key, &metadata.file, record)?;
+
+        // 4. Load data (extracts, parses, and inserts) and record the run in a single
+        // transaction, so readers see either the old data or all of the new data.
+        // Dropping the transaction on error rolls everything back.
+        let mut tx = self.pool.begin().await?;
+        let counts = {
+            let loader = self
+                .registry
+                .get(key)
+                .expect("Loader missing from registry");
+            loader
+                .load(&metadata.file, &mut tx, &mut record.rejected)
+                .await?
+        };
+        record.counts = Some(counts);
+        check_rejections(key, &counts, &record.rejected, options.max_rejected_rate)?;
+
+        if options.dry_run {
+            tx.rollback().await?;
+            info!(
+                "Dry run: '{}' would load {} rows: {} inserted, {} updated, {} rejected. Nothing was written.",
+                key,
+                counts.rows_parsed,
+                counts.rows_inserted,
+                counts.rows_updated,
+                counts.rows_rejected
+            );
+            return Ok(RunOutcome::RolledBack);
+        }
+
+        // 5. Update status, caching it only once it is committed
+        let status = self
+            .update_status(
+                &mut tx,
+                key,
+                &file_hash_str,
+                plugin_version,
+                source,
+                &metadata.validators,
+            )
+            .await?;
+        tx.commit().await?;
+        self.states().insert(key.to_string(), status);
+
+        info!(
+            "Loader '{}' completed successfully: {} rows parsed, {} inserted, {} updated, {} rejected.",
+            key,
+            counts.rows_parsed,
+            counts.rows_inserted,
+            counts.rows_updated,
+            counts.rows_rejected
+        );
+        Ok(RunOutcome::Succeeded)
+    }
+
+    /// Compares the file's header with the columns the loader reads, keeping any difference
+    /// in `record`. Fails if a required column is missing, or any column is under the
+    /// loader's `MissingColumnPolicy::Fail`.
+    fn check_schema(&self, key: &str, file: &Path, record: &mut RunRecord) -> Result<()> {
+        let loader = self
+            .registry
+            .get(key)
+            .expect("Loader missing from registry");
+        let expected = loader.expected_columns();
+        if expected.is_empty() {
+            return Ok(());
+        }
+        let header = loader
+            .header(file)
+            .context("Could not read the header of the file")?;
+        let diff = SchemaDiff::new(&expected, &header);
+        if diff.is_empty() {
+            return Ok(());
         }
+        warn!(
+            "Columns of the '{}' file differ from those the loader expects: {}",
+            key, diff
+        );
 
+        let result = diff.check(&expected, loader.missing_column_policy());
+        record.schema_diff = Some(diff);
+        result
+    }
+
+    /// Appends a `running` entry to `loader_run_history` and returns its id.
+    async fn start_run(&self, key: &str, plugin_version: i32) -> Result<i64> {
+        let id = sqlx::query_scalar!(
+            "INSERT INTO loader_run_history (loader_key, plugin_version)
+             VALUES ($1, $2)
+             RETURNING id",
+            key,
+            plugin_version
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(id)
+    }
+
+    /// Completes a history entry with the run's outcome. It is written outside the load's
+    /// transaction, so it survives a rollback.
+    async fn finish_run(
+        &self,
+        run_id: i64,
+        key: &str,
+        duration: Duration,
+        record: &RunRecord,
+        result: &Result<RunOutcome>,
+    ) -> Result<()> {
+        let (status, error) = match result {
+            Ok(outcome) => (outcome.as_str(), None),
+            Err(e) => ("failed", Some(format!("{:#}", e))),
+        };
+        let count = |f: fn(&LoadCounts) -> u64| record.counts.as_ref().map(|c| f(c) as i64);
+        let schema_diff = record
+            .schema_diff
+            .as_ref()
+            .map(serde_json::to_value)
+            .transpose()?;
+
+        sqlx::query!(
+            "UPDATE loader_run_history
+             SET status = $2, finished_at = NOW(), duration_ms = $3, source_url = $4, file_hash = $5,
+                 rows_parsed = $6, rows_inserted = $7, rows_updated = $8, rows_rejected = $9, error = $10,
+                 schema_diff = $11
+             WHERE id = $1",
+            run_id,
+            status,
+            duration.as_millis() as i64,
+            record.source_url,
+            record.file_hash,
+            count(|c| c.rows_parsed),
+            count(|c| c.rows_inserted),
+            count(|c| c.rows_updated),
+            count(|c| c.rows_rejected),
+            error,
+            schema_diff
+        )
+        .execute(&self.pool)
+        .await?;
+
+        if !record.rejected.is_empty() {
+            crate::db::insert_rejected_rows(&self.pool, run_id, key, &record.rejected).await?;
+        }
         Ok(())
     }
 
+    /// Turns a loader's source into a download URL, fetching the catalog on first use.
+    pub async fn resolve(&self, source: DatasetSource) -> Result<ResolvedSource> {
+        match source {
+            DatasetSource::Url(url) => Ok(ResolvedSource {
+                url,
+                modified: None,
+            }),
+            DatasetSource::Catalog {
+                dataset,
+                media_type,
+            } => {
+                let catalog = self
+                    .catalog
+                    .get_or_try_init(|| Catalog::fetch(&self.http, &self.catalog_url))
+                    .await?;
+                catalog.resolve(&dataset, &media_type)
+            }
+        }
+    }
+
+    /// The validators to send with a conditional request. They only apply when the last load
+    /// came from the same URL with the same plugin version; otherwise the file must be fetched.
+    fn cached_validators(
+        &self,
+        key: &str,
+        source: &ResolvedSource,
+        plugin_version: i32,
+    ) -> Option<HttpValidators> {
+        self.states()
+            .get(key)
+            .filter(|status| {
+                status.version >= plugin_version
+                    && status.source_url.as_deref() == Some(source.url.as_str())
+            })
+            .map(|status| status.validators.clone())
+    }
+
     /// Checks if the loader should run based on the file hash and version.
     /// Returns true if the data should be loaded (i.e., new version or different hash).
     pub fn should_load(&self, key: &str, current_file_hash: &str, plugin_version: i32) -> bool {
-        match self.loader_states.get(key) {
+        match self.states().get(key) {
             Some(status) => {
                 if plugin_version > status.version {
                     info!(
@@ -155,33 +807,594 @@ impl LoaderEngine {
         }
     }
 
-    /// Updates the loader run status after a successful load.
-    pub async fn update_status(&mut self, key: &str, file_hash: &str, version: i32) -> Result<()> {
+    /// Records where an unchanged file was downloaded from, and its validators unless there
+    /// are none.
+    pub async fn update_validators(
+        &self,
+        key: &str,
+        source: &ResolvedSource,
+        validators: &HttpValidators,
+    ) -> Result<()> {
         sqlx::query!(
-            "INSERT INTO loader_runs (loader_key, version, file_hash, last_run)
-             VALUES ($1, $2, $3, NOW())
-             ON CONFLICT (loader_key) 
-             DO UPDATE SET version = $2, file_hash = $3, last_run = NOW()",
+            "UPDATE loader_runs
+             SET source_url = $2, source_modified = $3,
+                 etag = CASE WHEN $7 THEN etag ELSE $4 END,
+                 last_modified = CASE WHEN $7 THEN last_modified ELSE $5 END,
+                 content_length = CASE WHEN $7 THEN content_length ELSE $6 END
+             WHERE loader_key = $1",
             key,
-            version,
-            file_hash
+            source.url,
+            source.modified,
+            validators.etag,
+            validators.last_modified,
+            validators.content_length,
+            validators.is_empty()
         )
         .execute(&self.pool)
         .await?;
 
-        // Update local cache
-        self.loader_states.insert(
-            key.to_string(),
-            LoaderRunStatus {
-                version,
-                file_hash: file_hash.to_string(),
-            },
-        );
+        if let Some(status) = self.states().get_mut(key) {
+            status.source_url = Some(source.url.clone());
+            status.source_modified = source.modified;
+            if !validators.is_empty() {
+                status.validators = validators.clone();
+            }
+        }
+        Ok(())
+    }
+
+    /// Updates the loader run status after a successful load, in the load's transaction.
+    /// Returns the new status, for the caller to cache once the transaction commits.
+    pub async fn update_status(
+        &self,
+        conn: &mut PgConnection,
+        key: &str,
+        file_hash: &str,
+        version: i32,
+        source: &ResolvedSource,
+        validators: &HttpValidators,
+    ) -> Result<LoaderRunStatus> {
+        let stored = sqlx::query!(
+            "INSERT INTO loader_runs (loader_key, version, file_hash, last_run, source_url, source_modified,
+                                      etag, last_modified, content_length)
+             VALUES ($1, $2, $3, NOW(), $4, $5, $6, $7, $8)
+             ON CONFLICT (loader_key) 
+             DO UPDATE SET version = $2, file_hash = $3, last_run = NOW(), source_url = $4, source_modified = $5,
+                           etag = CASE WHEN $9 THEN loader_runs.etag ELSE $6 END,
+                           last_modified = CASE WHEN $9 THEN loader_runs.last_modified ELSE $7 END,
+                           content_length = CASE WHEN $9 THEN loader_runs.content_length ELSE $8 END
+             RETURNING etag, last_modified, content_length",
+            key,
+            version,
+            file_hash,
+            source.url,
+            source.modified,
+            validators.etag,
+            validators.last_modified,
+            validators.content_length,
+            validators.is_empty()
+        )
+        .fetch_one(conn)
+        .await?;
 
         info!(
             "Updated loader status for '{}' to version {}.",
             key, version
         );
-        Ok(())
+        Ok(LoaderRunStatus {
+            version,
+            file_hash: file_hash.to_string(),
+            last_run: Utc::now(),
+            source_url: Some(source.url.clone()),
+            source_modified: source.modified,
+            validators: HttpValidators {
+                etag: stored.etag,
+                last_modified: stored.last_modified,
+                content_length: stored.content_length,
+            },
+        })
+    }
+}
+
+/// How many rejected rows to log; the rest are only in `rejected_rows`.
+const LOGGED_REJECTIONS: usize = 10;
+
+/// Logs the first rejected rows, and fails the run if they are too large a share of the file.
+fn check_rejections(
+    key: &str,
+    counts: &LoadCounts,
+    rejected: &[RejectedRow],
+    max_rate: Option<f64>,
+) -> Result<()> {
+    for row in rejected.iter().take(LOGGED_REJECTIONS) {
+        warn!(
+            "Loader '{}' rejected line {}: {}",
+            key, row.line, row.reason
+        );
+    }
+    if rejected.len() > LOGGED_REJECTIONS {
+        warn!(
+            "Loader '{}' rejected {} more rows.",
+            key,
+            rejected.len() - LOGGED_REJECTIONS
+        );
+    }
+
+    if let Some(max_rate) = max_rate
+        && counts.rows_parsed > 0
+    {
+        let rate = counts.rows_rejected as f64 / counts.rows_parsed as f64;
+        if rate > max_rate {
+            bail!(
+                "Rejected {} of {} rows ({:.2}%), more than the {:.2}% allowed",
+                counts.rows_rejected,
+                counts.rows_parsed,
+                rate * 100.0,
+                max_rate * 100.0
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::HttpArguments;
+    use async_trait::async_trait;
+    use std::sync::atomic::AtomicUsize;
+
+    /// A loader that only has a key and dependencies; the scheduler never calls the rest.
+    struct FakeLoader {
+        key: &'static str,
+        dependencies: &'static [&'static str],
+    }
+
+    #[async_trait]
+    impl CmsDataLoader for FakeLoader {
+        fn key(&self) -> &str {
+            self.key
+        }
+
+        fn source(&self) -> DatasetSource {
+            DatasetSource::Url(String::new())
+        }
+
+        fn version(&self) -> usize {
+            1
+        }
+
+        fn dependencies(&self) -> &[&str] {
+            self.dependencies
+        }
+
+        async fn get_metadata(
+            &self,
+            _context: &LoaderContext<'_>,
+            _source: &ResolvedSource,
+            _cached: Option<&HttpValidators>,
+        ) -> Result<FetchOutcome> {
+            unreachable!("the scheduler tests never fetch")
+        }
+
+        async fn load(
+            &self,
+            _file: &Path,
+            _conn: &mut PgConnection,
+            _rejected: &mut Vec<RejectedRow>,
+        ) -> Result<LoadCounts> {
+            unreachable!("the scheduler tests never load")
+        }
+
+        async fn cleanup(&self, _metadata: &CmsMetadata) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A loader whose file downloads fine but does not load.
+    struct FailingLoader {
+        file: std::path::PathBuf,
+    }
+
+    #[async_trait]
+    impl CmsDataLoader for FailingLoader {
+        fn key(&self) -> &str {
+            "failing"
+        }
+
+        fn source(&self) -> DatasetSource {
+            DatasetSource::Url("https://example.org/failing.csv".to_string())
+        }
+
+        fn version(&self) -> usize {
+            1
+        }
+
+        async fn get_metadata(
+            &self,
+            _context: &LoaderContext<'_>,
+            _source: &ResolvedSource,
+            _cached: Option<&HttpValidators>,
+        ) -> Result<FetchOutcome> {
+            std::fs::write(&self.file, "a,b\n1,2\n")?;
+            Ok(FetchOutcome::Fetched(CmsMetadata {
+                file: self.file.clone().into(),
+                file_hash: FileHash::Sha256("hash".to_string()),
+                validators: HttpValidators::default(),
+            }))
+        }
+
+        async fn load(
+            &self,
+            _file: &Path,
+            _conn: &mut PgConnection,
+            _rejected: &mut Vec<RejectedRow>,
+        ) -> Result<LoadCounts> {
+            bail!("the file is corrupt")
+        }
+    }
+
+    async fn database() -> PgPool {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is set");
+        PgPool::connect(&url).await.unwrap()
+    }
+
+    /// An engine with the given loaders and their dependencies. Its pool never connects.
+    fn engine(loaders: &[(&'static str, &'static [&'static str])]) -> LoaderEngine {
+        let http = HttpClient::new(&HttpArguments {
+            http_proxy: None,
+            connect_timeout: 1,
+            read_timeout: 1,
+            max_attempts: 1,
+            user_agent: "test".to_string(),
+        })
+        .unwrap();
+        let mut engine = LoaderEngine {
+            pool: PgPool::connect_lazy("postgres://localhost/unused").unwrap(),
+            catalog_url: String::new(),
+            catalog: OnceCell::new(),
+            http,
+            loader_states: Mutex::new(HashMap::new()),
+            registry: HashMap::new(),
+            stop: Arc::new(AtomicBool::new(false)),
+        };
+        for &(key, dependencies) in loaders {
+            engine.register(Box::new(FakeLoader { key, dependencies }));
+        }
+        engine
+    }
+
+    /// Schedules every selected loader with `run_one`, recording the order they start and
+    /// finish in: `+key` and `-key`.
+    async fn schedule(
+        engine: &LoaderEngine,
+        options: &RunOptions,
+        failing: &[&str],
+    ) -> (Result<()>, Vec<String>) {
+        let keys = engine.selected_keys(options).unwrap();
+        let events = Mutex::new(Vec::new());
+        let result = engine
+            .schedule(&keys, options, |key| {
+                let events = &events;
+                async move {
+                    events.lock().unwrap().push(format!("+{}", key));
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    events.lock().unwrap().push(format!("-{}", key));
+                    match failing.contains(&key) {
+                        true => bail!("{} failed", key),
+                        false => Ok(()),
+                    }
+                }
+            })
+            .await;
+        (result, events.into_inner().unwrap())
+    }
+
+    fn position(events: &[String], event: &str) -> usize {
+        events
+            .iter()
+            .position(|e| e == event)
+            .unwrap_or_else(|| panic!("no {} in {:?}", event, events))
+    }
+
+    #[tokio::test]
+    async fn dependency_cycle_is_rejected() {
+        let cycle = engine(&[("a", &["c"]), ("b", &["a"]), ("c", &["b"]), ("d", &["a"])]);
+        let error = cycle.check_dependencies().unwrap_err();
+        // "d" depends on the cycle but is not part of it; it can't be peeled off either.
+        assert_eq!(
+            error.to_string(),
+            "Loader dependencies form a cycle between: a, b, c, d"
+        );
+
+        let error = engine(&[("a", &["a"])]).check_dependencies().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Loader dependencies form a cycle between: a"
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_dependency_is_rejected() {
+        let engine = engine(&[("a", &[]), ("b", &["a", "missing"])]);
+        let error = engine.check_dependencies().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Loader 'b' depends on 'missing', which is not registered"
+        );
+    }
+
+    #[tokio::test]
+    async fn acyclic_dependencies_are_accepted() {
+        let engine = engine(&[("a", &[]), ("b", &["a"]), ("c", &["a", "b"])]);
+        assert!(engine.check_dependencies().is_ok());
+    }
+
+    #[tokio::test]
+    async fn unknown_selection_is_rejected() {
+        let engine = engine(&[("a", &[])]);
+        let options = RunOptions {
+            only: vec!["b".to_string()],
+            ..RunOptions::default()
+        };
+        let error = engine.selected_keys(&options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unknown loader 'b'. Registered loaders: a"
+        );
+    }
+
+    #[tokio::test]
+    async fn loaders_start_after_their_dependencies() {
+        let engine = engine(&[("a", &[]), ("b", &["a"]), ("c", &["b"]), ("d", &[])]);
+        let options = RunOptions {
+            concurrency: 4,
+            ..RunOptions::default()
+        };
+        let (result, events) = schedule(&engine, &options, &[]).await;
+        result.unwrap();
+        assert_eq!(events.len(), 8);
+        assert!(position(&events, "-a") < position(&events, "+b"));
+        assert!(position(&events, "-b") < position(&events, "+c"));
+        // "d" doesn't wait for anything.
+        assert!(position(&events, "+d") < position(&events, "-a"));
+    }
+
+    #[tokio::test]
+    async fn dependencies_left_out_of_the_run_are_assumed_loaded() {
+        let engine = engine(&[("a", &[]), ("b", &["a"])]);
+        let options = RunOptions {
+            skip: vec!["a".to_string()],
+            ..RunOptions::default()
+        };
+        let (result, events) = schedule(&engine, &options, &[]).await;
+        result.unwrap();
+        assert_eq!(events, ["+b", "-b"]);
+    }
+
+    #[tokio::test]
+    async fn dependents_of_a_failed_loader_are_skipped() {
+        let engine = engine(&[
+            ("a", &[]),
+            ("b", &["a"]),
+            ("c", &["b"]),
+            ("d", &[]),
+            ("e", &["d"]),
+        ]);
+        let options = RunOptions {
+            concurrency: 4,
+            ..RunOptions::default()
+        };
+        let (result, events) = schedule(&engine, &options, &["a"]).await;
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "1 of 5 loaders failed: a. Skipped because a prerequisite failed: b, c"
+        );
+        assert!(!events.iter().any(|e| e == "+b" || e == "+c"));
+        // The others carry on.
+        assert!(events.iter().any(|e| e == "-e"));
+    }
+
+    #[tokio::test]
+    async fn concurrency_is_limited() {
+        let engine = engine(&[("a", &[]), ("b", &[]), ("c", &[]), ("d", &[]), ("e", &[])]);
+        for concurrency in [1, 2, 5] {
+            let options = RunOptions {
+                concurrency,
+                ..RunOptions::default()
+            };
+            let keys = engine.selected_keys(&options).unwrap();
+            let running = AtomicUsize::new(0);
+            let most = AtomicUsize::new(0);
+            engine
+                .schedule(&keys, &options, |_| {
+                    let (running, most) = (&running, &most);
+                    async move {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        most.fetch_max(now, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        Ok(())
+                    }
+                })
+                .await
+                .unwrap();
+            assert_eq!(most.load(Ordering::SeqCst), concurrency);
+        }
+    }
+
+    #[tokio::test]
+    async fn stop_starts_no_further_loaders() {
+        let engine = engine(&[("a", &[]), ("b", &[])]);
+        engine.stop_handle().store(true, Ordering::Relaxed);
+        let (result, events) = schedule(&engine, &RunOptions::default(), &[]).await;
+        result.unwrap();
+        assert!(events.is_empty());
+    }
+
+    fn counts(rows_parsed: u64, rows_rejected: u64) -> LoadCounts {
+        LoadCounts {
+            rows_parsed,
+            rows_rejected,
+            ..LoadCounts::default()
+        }
+    }
+
+    #[test]
+    fn rejections_within_the_limit_pass() {
+        assert!(check_rejections("pos", &counts(100, 0), &[], Some(0.0)).is_ok());
+        assert!(check_rejections("pos", &counts(100, 5), &[], Some(0.05)).is_ok());
+    }
+
+    #[test]
+    fn rejections_over_the_limit_fail() {
+        let error = check_rejections("pos", &counts(100, 6), &[], Some(0.05)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Rejected 6 of 100 rows (6.00%), more than the 5.00% allowed"
+        );
+        assert!(check_rejections("pos", &counts(100, 1), &[], Some(0.0)).is_err());
+    }
+
+    #[test]
+    fn rejections_without_a_limit_pass() {
+        assert!(check_rejections("pos", &counts(100, 100), &[], None).is_ok());
+    }
+
+    #[test]
+    fn empty_file_passes() {
+        assert!(check_rejections("pos", &counts(0, 0), &[], Some(0.0)).is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres database in DATABASE_URL"]
+    async fn a_failed_load_removes_its_file() {
+        let mut engine = engine(&[]);
+        engine.pool = database().await;
+        let file = std::env::temp_dir().join(format!("failing-{}.csv", std::process::id()));
+        engine.register(Box::new(FailingLoader { file: file.clone() }));
+
+        let options = RunOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let result = engine
+            .run_loader(
+                "failing",
+                1,
+                &std::env::temp_dir(),
+                &options,
+                &mut RunRecord::default(),
+            )
+            .await;
+        assert!(result.is_err());
+        assert!(!file.exists(), "{:?} was left behind", file);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres database in DATABASE_URL"]
+    async fn empty_validators_keep_the_stored_ones() {
+        let mut engine = engine(&[]);
+        engine.pool = database().await;
+        let mut tx = engine.pool.begin().await.unwrap();
+        let source = ResolvedSource {
+            url: "https://example.org/data.csv".to_string(),
+            modified: None,
+        };
+        let validators = HttpValidators {
+            etag: Some("\"v1\"".to_string()),
+            last_modified: Some("Wed, 01 Oct 2025 00:00:00 GMT".to_string()),
+            content_length: Some(3),
+        };
+
+        engine
+            .update_status(&mut tx, "validators-test", "a", 1, &source, &validators)
+            .await
+            .unwrap();
+        let status = engine
+            .update_status(
+                &mut tx,
+                "validators-test",
+                "b",
+                1,
+                &source,
+                &HttpValidators::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(status.validators.etag, validators.etag);
+
+        let (file_hash, etag, content_length): (String, Option<String>, Option<i64>) =
+            sqlx::query_as(
+                "SELECT file_hash, etag, content_length FROM loader_runs WHERE loader_key = $1",
+            )
+            .bind("validators-test")
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+        assert_eq!(file_hash, "b");
+        assert_eq!(etag, validators.etag);
+        assert_eq!(content_length, Some(3));
+        tx.rollback().await.unwrap();
+        // Nothing was committed, so nothing was cached.
+        assert!(engine.status("validators-test").is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres database in DATABASE_URL"]
+    async fn status_is_reloaded_from_the_database() {
+        let mut engine = engine(&[]);
+        engine.pool = database().await;
+        let key = format!("refresh-test-{}", std::process::id());
+        let status = |file_hash: &str| LoaderRunStatus {
+            version: 1,
+            file_hash: file_hash.to_string(),
+            last_run: Utc::now(),
+            source_url: None,
+            source_modified: None,
+            validators: HttpValidators::default(),
+        };
+
+        // Another process loaded the file after this one filled its cache.
+        engine.states().insert(key.clone(), status("old"));
+        sqlx::query(
+            "INSERT INTO loader_runs (loader_key, version, file_hash, last_run)
+             VALUES ($1, 1, 'new', NOW())",
+        )
+        .bind(&key)
+        .execute(&engine.pool)
+        .await
+        .unwrap();
+        engine.refresh_status(&key).await.unwrap();
+        assert!(!engine.should_load(&key, "new", 1));
+
+        sqlx::query("DELETE FROM loader_runs WHERE loader_key = $1")
+            .bind(&key)
+            .execute(&engine.pool)
+            .await
+            .unwrap();
+        engine.refresh_status(&key).await.unwrap();
+        assert!(engine.status(&key).is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres database in DATABASE_URL"]
+    async fn scan_forgets_states_missing_from_the_database() {
+        let mut engine = engine(&[]);
+        engine.pool = database().await;
+        engine.states().insert(
+            "scan-test".to_string(),
+            LoaderRunStatus {
+                version: 1,
+                file_hash: "never committed".to_string(),
+                last_run: Utc::now(),
+                source_url: None,
+                source_modified: None,
+                validators: HttpValidators::default(),
+            },
+        );
+
+        engine.scan().await.unwrap();
+        assert!(engine.status("scan-test").is_none());
     }
 }
@@ -0,0 +1,408 @@
+//! FHIR R4 views of Provider of Services data.
+//!
+//! Each `Provider` becomes an `Organization`, and its `Address` a `Location` managed by that
+//! organization. Both resources use the CMS Certification Number as their logical id.
+//! Only the elements we can fill from the POS file are modelled.
+
+use crate::model::{Address, ProviderDetail};
+use crate::queries::Page;
+use chrono::Utc;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// The identifier system for CMS Certification Numbers.
+pub const CCN_SYSTEM: &str = "http://terminology.hl7.org/NamingSystem/CMSCertificationNumber";
+
+const ORGANIZATION_TYPE_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/organization-type";
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Organization {
+    /// Always "Organization".
+    pub resource_type: &'static str,
+    pub id: String,
+    pub identifier: Vec<Identifier>,
+    pub active: bool,
+    #[serde(rename = "type")]
+    pub kind: Vec<CodeableConcept>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub telecom: Vec<ContactPoint>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub address: Vec<FhirAddress>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Location {
+    /// Always "Location".
+    pub resource_type: &'static str,
+    pub id: String,
+    pub identifier: Vec<Identifier>,
+    /// "active", or "inactive" once the provider no longer participates.
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub telecom: Vec<ContactPoint>,
+    pub address: FhirAddress,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<Position>,
+    pub managing_organization: Reference,
+}
+
+/// Where a location is, in decimal degrees. Ours is the centroid of the address's ZCTA or
+/// county, not the site itself.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Position {
+    pub longitude: f64,
+    pub latitude: f64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Identifier {
+    pub system: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CodeableConcept {
+    pub coding: Vec<Coding>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Coding {
+    pub system: String,
+    pub code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ContactPoint {
+    /// "phone" or "fax".
+    pub system: &'static str,
+    pub value: String,
+    #[serde(rename = "use")]
+    pub purpose: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FhirAddress {
+    #[serde(rename = "use")]
+    pub purpose: &'static str,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub line: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub postal_code: Option<String>,
+    pub country: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Reference {
+    pub reference: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Bundle {
+    /// Always "Bundle".
+    pub resource_type: &'static str,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub total: i64,
+    pub link: Vec<BundleLink>,
+    pub entry: Vec<BundleEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BundleLink {
+    /// "self" or "next".
+    pub relation: &'static str,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleEntry {
+    pub full_url: String,
+    pub resource: Resource,
+    pub search: BundleEntrySearch,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BundleEntrySearch {
+    /// "match" for resources matching the search, "include" for resources pulled in alongside them.
+    pub mode: &'static str,
+}
+
+/// The body of a failed FHIR interaction.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationOutcome {
+    /// Always "OperationOutcome".
+    pub resource_type: &'static str,
+    pub issue: Vec<OperationOutcomeIssue>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct OperationOutcomeIssue {
+    /// "fatal", "error", "warning" or "information".
+    pub severity: &'static str,
+    /// An `IssueType` code, such as "invalid", "not-found" or "exception".
+    pub code: &'static str,
+    pub diagnostics: String,
+}
+
+impl OperationOutcome {
+    /// An outcome with a single error-severity issue.
+    pub fn error(code: &'static str, diagnostics: String) -> Self {
+        OperationOutcome {
+            resource_type: "OperationOutcome",
+            issue: vec![OperationOutcomeIssue {
+                severity: "error",
+                code,
+                diagnostics,
+            }],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum Resource {
+    Organization(Organization),
+    Location(Location),
+}
+
+fn telecom(detail: &ProviderDetail) -> Vec<ContactPoint> {
+    let p = &detail.provider;
+    let phone = p.phone_number.iter().map(|value| ContactPoint {
+        system: "phone",
+        value: value.clone(),
+        purpose: "work",
+    });
+    let fax = p.fax_number.iter().map(|value| ContactPoint {
+        system: "fax",
+        value: value.clone(),
+        purpose: "work",
+    });
+    phone.chain(fax).collect()
+}
+
+fn ccn_identifier(detail: &ProviderDetail) -> Identifier {
+    Identifier {
+        system: CCN_SYSTEM.to_string(),
+        value: detail.provider.cms_certification_number.to_string(),
+    }
+}
+
+impl From<&Address> for FhirAddress {
+    fn from(address: &Address) -> Self {
+        FhirAddress {
+            purpose: "work",
+            kind: "physical",
+            line: address.street_address.iter().cloned().collect(),
+            city: address.city.clone(),
+            state: address.state_code.clone(),
+            postal_code: address.zip_code.clone(),
+            country: "US",
+        }
+    }
+}
+
+/// Whether the provider still participates. A termination or expiration date in the past means
+/// it doesn't, as does being dropped from the latest POS file.
+fn is_active(detail: &ProviderDetail) -> bool {
+    let p = &detail.provider;
+    p.retired_at.is_none()
+        && p.termination_expiration_date
+            .is_none_or(|d| d > Utc::now().date_naive())
+}
+
+impl From<&ProviderDetail> for Organization {
+    fn from(detail: &ProviderDetail) -> Self {
+        let p = &detail.provider;
+        Organization {
+            resource_type: "Organization",
+            id: p.cms_certification_number.to_string(),
+            identifier: vec![ccn_identifier(detail)],
+            active: is_active(detail),
+            kind: vec![CodeableConcept {
+                coding: vec![Coding {
+                    system: ORGANIZATION_TYPE_SYSTEM.to_string(),
+                    code: "prov".to_string(),
+                    display: Some("Healthcare Provider".to_string()),
+                }],
+                text: detail.labels.provider_type.clone(),
+            }],
+            name: p.name.clone(),
+            telecom: telecom(detail),
+            address: detail.address.iter().map(FhirAddress::from).collect(),
+        }
+    }
+}
+
+impl Location {
+    /// The provider's site, or `None` if the provider has no address on file.
+    pub fn from_provider(detail: &ProviderDetail) -> Option<Self> {
+        let address = detail.address.as_ref()?;
+        let p = &detail.provider;
+
+        Some(Location {
+            resource_type: "Location",
+            id: p.cms_certification_number.to_string(),
+            identifier: vec![ccn_identifier(detail)],
+            status: if is_active(detail) {
+                "active"
+            } else {
+                "inactive"
+            },
+            name: p.name.clone(),
+            telecom: telecom(detail),
+            address: FhirAddress::from(address),
+            position: address
+                .latitude
+                .zip(address.longitude)
+                .map(|(latitude, longitude)| Position {
+                    longitude,
+                    latitude,
+                }),
+            managing_organization: Reference {
+                reference: format!("Organization/{}", p.cms_certification_number),
+                display: p.name.clone(),
+            },
+        })
+    }
+}
+
+impl Bundle {
+    /// Builds a `searchset` bundle from a page of providers. Each provider contributes its
+    /// `Organization` as a match, followed by its `Location` as an include.
+    ///
+    /// `base_url` is the FHIR base (e.g. "https://example.org/fhir"); `self_url` and `next_url`
+    /// are the absolute URLs of this page and the next one, if any.
+    pub fn search_set(
+        page: &Page<ProviderDetail>,
+        base_url: &str,
+        self_url: String,
+        next_url: Option<String>,
+    ) -> Self {
+        let mut entry = Vec::with_capacity(page.items.len() * 2);
+        for detail in &page.items {
+            let organization = Organization::from(detail);
+            entry.push(BundleEntry {
+                full_url: format!("{}/Organization/{}", base_url, organization.id),
+                resource: Resource::Organization(organization),
+                search: BundleEntrySearch { mode: "match" },
+            });
+
+            if let Some(location) = Location::from_provider(detail) {
+                entry.push(BundleEntry {
+                    full_url: format!("{}/Location/{}", base_url, location.id),
+                    resource: Resource::Location(location),
+                    search: BundleEntrySearch { mode: "include" },
+                });
+            }
+        }
+
+        let mut link = vec![BundleLink {
+            relation: "self",
+            url: self_url,
+        }];
+        if let Some(url) = next_url {
+            link.push(BundleLink {
+                relation: "next",
+                url,
+            });
+        }
+
+        Bundle {
+            resource_type: "Bundle",
+            kind: "searchset",
+            total: page.total_count,
+            link,
+            entry,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Provider, ProviderLabels, ProviderOfServiceRow};
+    use chrono::{Days, NaiveDate};
+
+    fn detail(termination: Option<NaiveDate>, retired: bool) -> ProviderDetail {
+        let row = ProviderOfServiceRow {
+            cms_certification_number: "220001".to_string(),
+            name: Some("GENERAL HOSPITAL".to_string()),
+            street_address: Some("55 FRUIT ST".to_string()),
+            city: Some("BOSTON".to_string()),
+            state_code: Some("MA".to_string()),
+            zip_code: Some("02114".to_string()),
+            termination_expiration_date: termination,
+            ..Default::default()
+        };
+        let mut provider = Provider::try_from(row.clone()).unwrap();
+        if retired {
+            provider.retired_at = Some(Utc::now());
+        }
+        ProviderDetail {
+            ccn: provider.cms_certification_number.parts(),
+            provider,
+            address: Some(Address::from(row)),
+            labels: ProviderLabels::default(),
+        }
+    }
+
+    fn statuses(detail: &ProviderDetail) -> (bool, &'static str) {
+        let location = Location::from_provider(detail).expect("provider has an address");
+        (Organization::from(detail).active, location.status)
+    }
+
+    #[test]
+    fn participating_providers_are_active() {
+        let tomorrow = Utc::now().date_naive() + Days::new(1);
+        assert_eq!(statuses(&detail(None, false)), (true, "active"));
+        assert_eq!(statuses(&detail(Some(tomorrow), false)), (true, "active"));
+    }
+
+    #[test]
+    fn terminated_or_retired_providers_are_inactive() {
+        let yesterday = Utc::now().date_naive() - Days::new(1);
+        assert_eq!(
+            statuses(&detail(Some(yesterday), false)),
+            (false, "inactive")
+        );
+        assert_eq!(statuses(&detail(None, true)), (false, "inactive"));
+    }
+
+    #[test]
+    fn operation_outcome_has_one_error_issue() {
+        let outcome = OperationOutcome::error("not-found", "Location/1 not found.".to_string());
+        assert_eq!(
+            serde_json::to_value(outcome).unwrap(),
+            serde_json::json!({
+                "resourceType": "OperationOutcome",
+                "issue": [{
+                    "severity": "error",
+                    "code": "not-found",
+                    "diagnostics": "Location/1 not found.",
+                }],
+            })
+        );
+    }
+}
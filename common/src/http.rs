@@ -0,0 +1,50 @@
+//! The HTTP client the loader engine owns and lends to its loaders.
+
+use anyhow::{Context, Result};
+use reqwest::{Client, Proxy};
+use std::time::Duration;
+
+use crate::args::HttpArguments;
+use crate::download::RetryPolicy;
+
+/// Sent as `User-Agent` unless `--user-agent` says otherwise.
+pub const DEFAULT_USER_AGENT: &str =
+    concat!("healthcare-data-rest-loader/", env!("CARGO_PKG_VERSION"));
+
+/// An async HTTP client with the proxy, timeouts and retry policy every download shares.
+/// Cloning it is cheap and shares the connection pool.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: Client,
+    /// How long to wait for response headers, or for the next chunk of a body, before the
+    /// attempt fails. Unlike a timeout on the whole request, this never cuts off a large
+    /// download that is still making progress.
+    pub read_timeout: Duration,
+    pub retry: RetryPolicy,
+}
+
+impl HttpClient {
+    pub fn new(args: &HttpArguments) -> Result<Self> {
+        let mut builder = Client::builder()
+            .user_agent(&args.user_agent)
+            .connect_timeout(Duration::from_secs(args.connect_timeout));
+        // Without an explicit proxy, reqwest still honors HTTP_PROXY, HTTPS_PROXY and NO_PROXY.
+        if let Some(proxy) = &args.http_proxy {
+            builder = builder.proxy(
+                Proxy::all(proxy).with_context(|| format!("Invalid HTTP proxy '{}'", proxy))?,
+            );
+        }
+        Ok(Self {
+            client: builder.build()?,
+            read_timeout: Duration::from_secs(args.read_timeout),
+            retry: RetryPolicy {
+                max_attempts: args.max_attempts,
+                ..RetryPolicy::default()
+            },
+        })
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+}
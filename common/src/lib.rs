@@ -1,6 +1,16 @@
+pub mod address;
 pub mod args;
+pub mod catalog;
+pub mod copy;
 pub mod db;
+pub mod download;
 pub mod engine;
+pub mod fhir;
+pub mod http;
+pub mod lock;
 pub mod model;
+pub mod queries;
+pub mod schema;
 pub mod state;
 pub mod traits;
+pub mod validation;
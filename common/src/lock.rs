@@ -0,0 +1,191 @@
+//! Postgres advisory locks that keep two loader processes from loading the same data at once.
+//!
+//! Locks are held by a dedicated connection, not one borrowed from the pool: a session-level
+//! advisory lock lives as long as its session, so returning the connection to the pool would
+//! leak the lock. Closing the connection ends the session and releases every lock it holds,
+//! which is also what happens when the guard is dropped on an error or panic, or when the
+//! process dies.
+//!
+//! A global lock is taken exclusively. A loader lock is taken exclusively on the loader's key,
+//! plus the global lock in shared mode, so a global run excludes every other run while runs
+//! of different loaders can proceed side by side.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use sqlx::{Connection, PgConnection, PgPool};
+use std::fmt;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::info;
+
+/// How often a waiting process checks whether the lock was released.
+const RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Lock names are hashed into the 64-bit advisory lock space, under this prefix so they don't
+/// collide with locks taken by other applications sharing the database.
+const LOCK_PREFIX: &str = "healthcare-data-rest/loader";
+
+/// What a run locks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LockScope {
+    /// One lock for the whole run: no other run can start until it is done.
+    Global,
+    /// A lock per loader key, taken just before the loader runs.
+    #[default]
+    Loader,
+}
+
+/// Another process holds the lock, and it wasn't released within the timeout.
+#[derive(Debug)]
+pub struct AlreadyRunning {
+    /// The locked loader, or `None` if a global run holds the lock.
+    pub key: Option<String>,
+}
+
+impl fmt::Display for AlreadyRunning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "Loader '{}' is already running in another process", key),
+            None => write!(
+                f,
+                "A global loader run is already running in another process"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AlreadyRunning {}
+
+/// A held advisory lock. Dropping it releases the lock.
+pub struct AdvisoryLock {
+    conn: PgConnection,
+}
+
+impl AdvisoryLock {
+    /// Takes the global lock (`key` is `None`) or the lock of a single loader, waiting up to
+    /// `timeout` for another process to release it. Fails with `AlreadyRunning` if it doesn't.
+    pub async fn acquire(pool: &PgPool, key: Option<&str>, timeout: Duration) -> Result<Self> {
+        let mut conn = PgConnection::connect_with(&pool.connect_options()).await?;
+        let deadline = Instant::now() + timeout;
+        let mut logged = false;
+
+        loop {
+            let held = match try_lock(&mut conn, key).await? {
+                Ok(()) => return Ok(Self { conn }),
+                Err(held) => held,
+            };
+            // The last check happens at the deadline, not a retry interval short of it.
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(held.into());
+            }
+            if !logged {
+                info!(
+                    "Waiting up to {:?} for another process to release the {} lock...",
+                    timeout,
+                    key.unwrap_or("global")
+                );
+                logged = true;
+            }
+            tokio::time::sleep_until(deadline.min(now + RETRY_INTERVAL)).await;
+        }
+    }
+
+    /// Releases the lock by closing its session.
+    pub async fn release(self) -> Result<()> {
+        self.conn.close().await?;
+        Ok(())
+    }
+}
+
+/// Tries to take every lock `key` needs. Either all of them are taken, or none are held
+/// afterwards, so a waiting process never blocks others with half its locks. On failure,
+/// returns which lock another process holds.
+async fn try_lock(
+    conn: &mut PgConnection,
+    key: Option<&str>,
+) -> Result<Result<(), AlreadyRunning>> {
+    let global_mode = match key {
+        None => "pg_try_advisory_lock",
+        Some(_) => "pg_try_advisory_lock_shared",
+    };
+    let global: bool =
+        sqlx::query_scalar(&format!("SELECT {global_mode}(hashtextextended($1, 0))"))
+            .bind(LOCK_PREFIX)
+            .fetch_one(&mut *conn)
+            .await?;
+    if !global {
+        return Ok(Err(AlreadyRunning { key: None }));
+    }
+
+    if let Some(key) = key {
+        let locked: bool =
+            sqlx::query_scalar("SELECT pg_try_advisory_lock(hashtextextended($1 || '/' || $2, 0))")
+                .bind(LOCK_PREFIX)
+                .bind(key)
+                .fetch_one(&mut *conn)
+                .await?;
+        if !locked {
+            sqlx::query("SELECT pg_advisory_unlock_all()")
+                .execute(&mut *conn)
+                .await?;
+            return Ok(Err(AlreadyRunning {
+                key: Some(key.to_string()),
+            }));
+        }
+    }
+    Ok(Ok(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn pool() -> PgPool {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is set");
+        PgPool::connect(&url).await.unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn a_lock_released_just_before_the_deadline_is_taken() {
+        let pool = pool().await;
+        let holder = AdvisoryLock::acquire(&pool, Some("lock-test/deadline"), Duration::ZERO)
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(700)).await;
+            holder.release().await.unwrap();
+        });
+
+        // The retry at 500ms still finds it held; only the one at the deadline gets it.
+        let lock = AdvisoryLock::acquire(
+            &pool,
+            Some("lock-test/deadline"),
+            Duration::from_millis(900),
+        )
+        .await
+        .unwrap();
+        lock.release().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn a_lock_held_past_the_deadline_is_already_running() {
+        let pool = pool().await;
+        let holder = AdvisoryLock::acquire(&pool, Some("lock-test/held"), Duration::ZERO)
+            .await
+            .unwrap();
+
+        let started = Instant::now();
+        let error =
+            AdvisoryLock::acquire(&pool, Some("lock-test/held"), Duration::from_millis(700))
+                .await
+                .err()
+                .expect("the lock is held");
+        assert!(started.elapsed() >= Duration::from_millis(700));
+        let running = error.downcast_ref::<AlreadyRunning>().unwrap();
+        assert_eq!(running.key.as_deref(), Some("lock-test/held"));
+        holder.release().await.unwrap();
+    }
+}
@@ -1,192 +1,710 @@
-use chrono::NaiveDate;
-use serde::{Deserialize, Deserializer};
+use crate::address::StandardAddress;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgArgumentBuffer, PgRow, PgTypeInfo, PgValueRef, Postgres};
+use sqlx::{FromRow, Row};
+use utoipa::ToSchema;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, FromRow, ToSchema)]
 pub struct Address {
-    #[serde(skip)]
+    #[serde(skip_deserializing)]
     pub id: Option<i32>, // Database ID, populated later
 
-    #[serde(rename = "st_adr", deserialize_with = "deserialize_na_string")]
+    #[serde(
+        rename(deserialize = "st_adr"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub street_address: Option<String>,
-    #[serde(rename = "city_name", deserialize_with = "deserialize_na_string")]
+    #[serde(
+        rename(deserialize = "city_name"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub city: Option<String>,
-    #[serde(rename = "state_cd", deserialize_with = "deserialize_na_string")]
+    #[serde(
+        rename(deserialize = "state_cd"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub state_code: Option<String>,
-    #[serde(rename = "zip_cd", deserialize_with = "deserialize_na_string")]
+    #[serde(
+        rename(deserialize = "zip_cd"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub zip_code: Option<String>,
+    /// The ZIP+4 add-on, when the source had one. `zip_code` holds the five-digit ZIP.
+    #[serde(skip_deserializing)]
+    pub zip4: Option<String>,
 
     // Geographic Codes
-    #[serde(rename = "ssa_cnty_cd", deserialize_with = "deserialize_na_string")]
+    #[serde(
+        rename(deserialize = "ssa_cnty_cd"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub ssa_county_code: Option<String>,
-    #[serde(rename = "ssa_state_cd", deserialize_with = "deserialize_na_string")]
+    #[serde(
+        rename(deserialize = "ssa_state_cd"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub ssa_state_code: Option<String>,
-    #[serde(rename = "state_rgn_cd", deserialize_with = "deserialize_na_string")]
+    #[serde(
+        rename(deserialize = "state_rgn_cd"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub state_region_code: Option<String>,
-    #[serde(rename = "rgn_cd", deserialize_with = "deserialize_na_string")]
+    #[serde(
+        rename(deserialize = "rgn_cd"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub region_code: Option<String>,
-    #[serde(rename = "fips_state_cd", deserialize_with = "deserialize_na_string")]
+    #[serde(
+        rename(deserialize = "fips_state_cd"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub fips_state_code: Option<String>,
-    #[serde(rename = "fips_cnty_cd", deserialize_with = "deserialize_na_string")]
+    #[serde(
+        rename(deserialize = "fips_cnty_cd"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub fips_county_code: Option<String>,
-    #[serde(rename = "cbsa_cd", deserialize_with = "deserialize_na_string")]
+    #[serde(
+        rename(deserialize = "cbsa_cd"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub cbsa_code: Option<String>,
     #[serde(
-        rename = "cbsa_urbn_rrl_ind",
+        rename(deserialize = "cbsa_urbn_rrl_ind"),
         deserialize_with = "deserialize_na_string"
     )]
     pub cbsa_urban_rural_indicator: Option<String>,
+
+    // Coordinates from the Census gazetteers, filled in after loading (see `geocode_addresses`).
+    #[serde(skip_deserializing)]
+    pub latitude: Option<f64>,
+    #[serde(skip_deserializing)]
+    pub longitude: Option<f64>,
+    #[serde(skip_deserializing)]
+    pub geocode_method: Option<GeocodeMethod>,
+}
+
+/// Which centroid an address's coordinates are: they are approximate either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GeocodeMethod {
+    /// The centroid of the ZIP Code Tabulation Area matching the address's ZIP.
+    Zcta,
+    /// The centroid of the address's county, when its ZIP has no ZCTA.
+    County,
+}
+
+impl sqlx::Type<Postgres> for GeocodeMethod {
+    fn type_info() -> PgTypeInfo {
+        <String as sqlx::Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <String as sqlx::Type<Postgres>>::compatible(ty)
+    }
+}
+
+impl<'r> sqlx::Decode<'r, Postgres> for GeocodeMethod {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        match <&str as sqlx::Decode<Postgres>>::decode(value)? {
+            "zcta" => Ok(GeocodeMethod::Zcta),
+            "county" => Ok(GeocodeMethod::County),
+            other => Err(format!("unknown geocode method '{}'", other).into()),
+        }
+    }
+}
+
+/// A CMS Certification Number (CCN), the identifier Medicare assigns to a certified provider.
+///
+/// Most CCNs are six characters: two digits for the state, then a four-digit number whose
+/// range says what kind of facility it is (`220071` is a Massachusetts short-term hospital).
+/// A letter in the third position marks a special unit or a Medicaid-only facility instead
+/// (`22S071` is that hospital's psychiatric unit). Ambulatory surgical centers and
+/// laboratories get ten characters, with `C` or `D` in the third position.
+///
+/// The format is enforced where CCNs come in: parsing, deserializing (which includes path
+/// parameters) and row validation. A CCN read back from the database is taken as stored,
+/// so a row loaded before the rules existed can still be served; its parts are then `None`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Ccn(String);
+
+/// The parts of a CCN, as served by the API.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CcnParts {
+    /// The USPS code of the state the CCN was issued in, if the prefix is a known one.
+    pub state_code: Option<&'static str>,
+    /// The kind of facility the number range or letter stands for, if it is a known one.
+    pub facility_type: Option<FacilityType>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FacilityType {
+    ShortTermHospital,
+    MedicalAssistanceFacility,
+    CriticalAccessHospital,
+    CommunityMentalHealthCenter,
+    Hospice,
+    FederallyQualifiedHealthCenter,
+    ReligiousNonmedicalHealthCareInstitution,
+    LongTermCareHospital,
+    DialysisFacility,
+    RehabilitationHospital,
+    HomeHealthAgency,
+    ComprehensiveOutpatientRehabilitationFacility,
+    ChildrensHospital,
+    RuralHealthClinic,
+    PsychiatricHospital,
+    SkilledNursingFacility,
+    OutpatientPhysicalTherapy,
+    TransplantCenter,
+    PsychiatricUnit,
+    RehabilitationUnit,
+    SwingBeds,
+    MedicaidNursingFacility,
+    IntermediateCareFacility,
+    AmbulatorySurgicalCenter,
+    ClinicalLaboratory,
+}
+
+/// SSA state codes, the CCN prefix, by USPS code. Some states have more than one.
+/// From the State Operations Manual, chapter 2, section 2779A.
+const CCN_STATES: &[(&str, &str)] = &[
+    ("01", "AL"),
+    ("02", "AK"),
+    ("03", "AZ"),
+    ("04", "AR"),
+    ("05", "CA"),
+    ("06", "CO"),
+    ("07", "CT"),
+    ("08", "DE"),
+    ("09", "DC"),
+    ("10", "FL"),
+    ("11", "GA"),
+    ("12", "HI"),
+    ("13", "ID"),
+    ("14", "IL"),
+    ("15", "IN"),
+    ("16", "IA"),
+    ("17", "KS"),
+    ("18", "KY"),
+    ("19", "LA"),
+    ("20", "ME"),
+    ("21", "MD"),
+    ("22", "MA"),
+    ("23", "MI"),
+    ("24", "MN"),
+    ("25", "MS"),
+    ("26", "MO"),
+    ("27", "MT"),
+    ("28", "NE"),
+    ("29", "NV"),
+    ("30", "NH"),
+    ("31", "NJ"),
+    ("32", "NM"),
+    ("33", "NY"),
+    ("34", "NC"),
+    ("35", "ND"),
+    ("36", "OH"),
+    ("37", "OK"),
+    ("38", "OR"),
+    ("39", "PA"),
+    ("40", "PR"),
+    ("41", "RI"),
+    ("42", "SC"),
+    ("43", "SD"),
+    ("44", "TN"),
+    ("45", "TX"),
+    ("46", "UT"),
+    ("47", "VT"),
+    ("48", "VI"),
+    ("49", "VA"),
+    ("50", "WA"),
+    ("51", "WV"),
+    ("52", "WI"),
+    ("53", "WY"),
+    ("55", "CA"),
+    ("64", "AS"),
+    ("65", "GU"),
+    ("66", "MP"),
+    ("67", "TX"),
+    ("68", "FL"),
+];
+
+/// Facility types by the range of the last four digits of a six-digit CCN. Ranges that are
+/// reserved or retired are left out.
+const CCN_RANGES: &[(u16, u16, FacilityType)] = &[
+    (1, 879, FacilityType::ShortTermHospital),
+    (1225, 1299, FacilityType::MedicalAssistanceFacility),
+    (1300, 1399, FacilityType::CriticalAccessHospital),
+    (1400, 1499, FacilityType::CommunityMentalHealthCenter),
+    (1500, 1799, FacilityType::Hospice),
+    (1800, 1989, FacilityType::FederallyQualifiedHealthCenter),
+    (
+        1990,
+        1999,
+        FacilityType::ReligiousNonmedicalHealthCareInstitution,
+    ),
+    (2000, 2299, FacilityType::LongTermCareHospital),
+    (2300, 2999, FacilityType::DialysisFacility),
+    (3025, 3099, FacilityType::RehabilitationHospital),
+    (3100, 3199, FacilityType::HomeHealthAgency),
+    (
+        3200,
+        3299,
+        FacilityType::ComprehensiveOutpatientRehabilitationFacility,
+    ),
+    (3300, 3399, FacilityType::ChildrensHospital),
+    (3400, 3499, FacilityType::RuralHealthClinic),
+    (3500, 3799, FacilityType::DialysisFacility),
+    (3800, 3999, FacilityType::RuralHealthClinic),
+    (4000, 4499, FacilityType::PsychiatricHospital),
+    (
+        4500,
+        4599,
+        FacilityType::ComprehensiveOutpatientRehabilitationFacility,
+    ),
+    (4600, 4799, FacilityType::CommunityMentalHealthCenter),
+    (
+        4800,
+        4899,
+        FacilityType::ComprehensiveOutpatientRehabilitationFacility,
+    ),
+    (4900, 4999, FacilityType::CommunityMentalHealthCenter),
+    (5000, 6499, FacilityType::SkilledNursingFacility),
+    (6500, 6989, FacilityType::OutpatientPhysicalTherapy),
+    (7000, 8499, FacilityType::HomeHealthAgency),
+    (8500, 8999, FacilityType::RuralHealthClinic),
+    (9000, 9799, FacilityType::HomeHealthAgency),
+    (9800, 9899, FacilityType::TransplantCenter),
+];
+
+impl Ccn {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The USPS code of the state the CCN was issued in, if its prefix is a known one. `None`
+    /// for a stored CCN that is not well-formed.
+    pub fn state_code(&self) -> Option<&'static str> {
+        if !is_well_formed(&self.0) {
+            return None;
+        }
+        let prefix = &self.0[..2];
+        CCN_STATES
+            .iter()
+            .find(|(code, _)| *code == prefix)
+            .map(|(_, state)| *state)
+    }
+
+    /// The kind of facility the CCN's number range, or letter, stands for. `None` for a
+    /// stored CCN that is not well-formed.
+    pub fn facility_type(&self) -> Option<FacilityType> {
+        if !is_well_formed(&self.0) {
+            return None;
+        }
+        let bytes = self.0.as_bytes();
+        match (bytes.len(), bytes[2]) {
+            (10, b'C') => Some(FacilityType::AmbulatorySurgicalCenter),
+            (10, b'D') => Some(FacilityType::ClinicalLaboratory),
+            (6, b'S' | b'M') => Some(FacilityType::PsychiatricUnit),
+            (6, b'T' | b'R') => Some(FacilityType::RehabilitationUnit),
+            (6, b'U' | b'W' | b'Y' | b'Z') => Some(FacilityType::SwingBeds),
+            (6, b'E' | b'F') => Some(FacilityType::MedicaidNursingFacility),
+            (6, b'G' | b'H') => Some(FacilityType::IntermediateCareFacility),
+            (6, b'0'..=b'9') => {
+                let number: u16 = self.0[2..].parse().ok()?;
+                CCN_RANGES
+                    .iter()
+                    .find(|(start, end, _)| (*start..=*end).contains(&number))
+                    .map(|(_, _, kind)| *kind)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn parts(&self) -> CcnParts {
+        CcnParts {
+            state_code: self.state_code(),
+            facility_type: self.facility_type(),
+        }
+    }
+}
+
+fn is_well_formed(s: &str) -> bool {
+    let bytes = s.as_bytes();
+    let digits = |range: &[u8]| range.iter().all(u8::is_ascii_digit);
+    match bytes.len() {
+        6 => {
+            digits(&bytes[..2])
+                && (bytes[2].is_ascii_digit() || bytes[2].is_ascii_uppercase())
+                && digits(&bytes[3..])
+        }
+        10 => digits(&bytes[..2]) && bytes[2].is_ascii_uppercase() && digits(&bytes[3..]),
+        _ => false,
+    }
+}
+
+/// The error for a string that is not a well-formed CCN.
+#[derive(Debug, Clone)]
+pub struct InvalidCcn(String);
+
+impl std::fmt::Display for InvalidCcn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'{}' is not a valid CMS Certification Number", self.0)
+    }
+}
+
+impl std::error::Error for InvalidCcn {}
+
+impl std::str::FromStr for Ccn {
+    type Err = InvalidCcn;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match is_well_formed(s) {
+            true => Ok(Ccn(s.to_string())),
+            false => Err(InvalidCcn(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Ccn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+impl Serialize for Ccn {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ccn {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.trim().parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl sqlx::Type<Postgres> for Ccn {
+    fn type_info() -> PgTypeInfo {
+        <String as sqlx::Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <String as sqlx::Type<Postgres>>::compatible(ty)
+    }
+}
+
+impl sqlx::Encode<'_, Postgres> for Ccn {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+        <&str as sqlx::Encode<Postgres>>::encode(self.as_str(), buf)
+    }
+}
+
+/// Stored CCNs are not checked again; see `Ccn`.
+impl<'r> sqlx::Decode<'r, Postgres> for Ccn {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        Ok(Ccn(<String as sqlx::Decode<Postgres>>::decode(value)?))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, FromRow, ToSchema)]
 pub struct Provider {
     // --- Identification ---
-    #[serde(rename = "prvdr_num")]
-    pub cms_certification_number: String, // PK
-    #[serde(rename = "fac_name", deserialize_with = "deserialize_na_string")]
+    #[serde(rename(deserialize = "prvdr_num"))]
+    #[schema(value_type = String, example = "220071")]
+    pub cms_certification_number: Ccn, // PK
+    #[serde(
+        rename(deserialize = "fac_name"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub name: Option<String>,
-    #[serde(rename = "prvdr_sbtyp_id", deserialize_with = "deserialize_na_option")]
+    #[serde(
+        rename(deserialize = "prvdr_sbtyp_id"),
+        deserialize_with = "deserialize_na_option"
+    )]
     pub provider_subtype_id: Option<i32>,
-    #[serde(rename = "mdcd_vndr_num", deserialize_with = "deserialize_na_string")]
+    #[serde(
+        rename(deserialize = "mdcd_vndr_num"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub medicaid_vendor_number: Option<String>,
-    #[serde(rename = "prvdr_type_id", deserialize_with = "deserialize_na_option")]
+    #[serde(
+        rename(deserialize = "prvdr_type_id"),
+        deserialize_with = "deserialize_na_option"
+    )]
     pub provider_type_id: Option<i32>,
 
     // Location FK (Populated after normalization)
-    #[serde(skip)]
+    #[serde(skip_deserializing)]
     pub address_id: Option<i32>,
 
     // --- Dates ---
     #[serde(
-        rename = "orgnl_prtcptn_dt",
+        rename(deserialize = "orgnl_prtcptn_dt"),
         deserialize_with = "deserialize_optional_date"
     )]
     pub original_participation_date: Option<NaiveDate>,
-    #[serde(rename = "crtfctn_dt", deserialize_with = "deserialize_optional_date")]
+    #[serde(
+        rename(deserialize = "crtfctn_dt"),
+        deserialize_with = "deserialize_optional_date"
+    )]
     pub certification_date: Option<NaiveDate>,
     #[serde(
-        rename = "trmntn_exprtn_dt",
+        rename(deserialize = "trmntn_exprtn_dt"),
         deserialize_with = "deserialize_optional_date"
     )]
     pub termination_expiration_date: Option<NaiveDate>,
-    #[serde(rename = "chow_dt", deserialize_with = "deserialize_optional_date")]
+    #[serde(
+        rename(deserialize = "chow_dt"),
+        deserialize_with = "deserialize_optional_date"
+    )]
     pub change_of_ownership_date: Option<NaiveDate>,
     #[serde(
-        rename = "asc_bgn_srvc_dt",
+        rename(deserialize = "asc_bgn_srvc_dt"),
         deserialize_with = "deserialize_optional_date"
     )]
     pub asc_begin_service_date: Option<NaiveDate>,
     #[serde(
-        rename = "processing_date",
+        rename(deserialize = "processing_date"),
         deserialize_with = "deserialize_optional_date"
     )]
     pub processing_date: Option<NaiveDate>,
 
     // --- Contact ---
-    #[serde(rename = "phne_num", deserialize_with = "deserialize_na_string")]
+    #[serde(
+        rename(deserialize = "phne_num"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub phone_number: Option<String>,
-    #[serde(rename = "fax_phne_num", deserialize_with = "deserialize_na_string")]
+    #[serde(
+        rename(deserialize = "fax_phne_num"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub fax_number: Option<String>,
 
     // --- Characteristics & flags ---
-    #[serde(rename = "acrdtn_type_cd", deserialize_with = "deserialize_na_string")]
+    #[serde(
+        rename(deserialize = "acrdtn_type_cd"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub accreditation_type_code: Option<String>,
     #[serde(
-        rename = "intrmdry_carr_cd",
+        rename(deserialize = "intrmdry_carr_cd"),
         deserialize_with = "deserialize_na_string"
     )]
     pub intermediary_carrier_code: Option<String>,
-    #[serde(rename = "acptbl_poc_sw", deserialize_with = "deserialize_yes_no")]
+    #[serde(
+        rename(deserialize = "acptbl_poc_sw"),
+        deserialize_with = "deserialize_yes_no"
+    )]
     pub acceptable_poc_switch: Option<bool>,
 
     #[serde(
-        rename = "fy_end_mo_day_cd",
+        rename(deserialize = "fy_end_mo_day_cd"),
         deserialize_with = "deserialize_na_string"
     )]
     pub fiscal_year_end_date: Option<String>,
-    #[serde(rename = "cmplnc_stus_cd", deserialize_with = "deserialize_na_string")]
+    #[serde(
+        rename(deserialize = "cmplnc_stus_cd"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub compliance_status_code: Option<String>,
     #[serde(
-        rename = "crtfctn_actn_type_cd",
+        rename(deserialize = "crtfctn_actn_type_cd"),
         deserialize_with = "deserialize_na_string"
     )]
     pub certification_action_type_code: Option<String>,
 
     // --- Bed Counts (Integers) ---
-    #[serde(rename = "bed_cnt", deserialize_with = "deserialize_na_option")]
+    #[serde(
+        rename(deserialize = "bed_cnt"),
+        deserialize_with = "deserialize_na_option"
+    )]
     pub bed_count: Option<i32>,
-    #[serde(rename = "crtfd_bed_cnt", deserialize_with = "deserialize_na_option")]
+    #[serde(
+        rename(deserialize = "crtfd_bed_cnt"),
+        deserialize_with = "deserialize_na_option"
+    )]
     pub certified_bed_count: Option<i32>,
-    #[serde(rename = "hospc_bed_cnt", deserialize_with = "deserialize_na_option")]
+    #[serde(
+        rename(deserialize = "hospc_bed_cnt"),
+        deserialize_with = "deserialize_na_option"
+    )]
     pub hospice_bed_count: Option<i32>,
-    #[serde(rename = "aids_bed_cnt", deserialize_with = "deserialize_na_option")]
+    #[serde(
+        rename(deserialize = "aids_bed_cnt"),
+        deserialize_with = "deserialize_na_option"
+    )]
     pub aids_bed_count: Option<i32>,
-    #[serde(rename = "alzhmr_bed_cnt", deserialize_with = "deserialize_na_option")]
+    #[serde(
+        rename(deserialize = "alzhmr_bed_cnt"),
+        deserialize_with = "deserialize_na_option"
+    )]
     pub alzheimer_bed_count: Option<i32>,
-    #[serde(rename = "dlys_bed_cnt", deserialize_with = "deserialize_na_option")]
+    #[serde(
+        rename(deserialize = "dlys_bed_cnt"),
+        deserialize_with = "deserialize_na_option"
+    )]
     pub dialysis_bed_count: Option<i32>,
     #[serde(
-        rename = "dsbl_chldrn_bed_cnt",
+        rename(deserialize = "dsbl_chldrn_bed_cnt"),
         deserialize_with = "deserialize_na_option"
     )]
     pub disabled_children_bed_count: Option<i32>,
     #[serde(
-        rename = "head_trma_bed_cnt",
+        rename(deserialize = "head_trma_bed_cnt"),
         deserialize_with = "deserialize_na_option"
     )]
     pub head_trauma_bed_count: Option<i32>,
     #[serde(
-        rename = "hntgtn_dease_bed_cnt",
+        rename(deserialize = "hntgtn_dease_bed_cnt"),
         deserialize_with = "deserialize_na_option"
     )]
     pub huntington_disease_bed_count: Option<i32>,
     #[serde(
-        rename = "mdcr_mdcd_snf_bed_cnt",
+        rename(deserialize = "mdcr_mdcd_snf_bed_cnt"),
         deserialize_with = "deserialize_na_option"
     )]
     pub medicare_medicaid_snf_bed_count: Option<i32>,
     #[serde(
-        rename = "mdcr_snf_bed_cnt",
+        rename(deserialize = "mdcr_snf_bed_cnt"),
         deserialize_with = "deserialize_na_option"
     )]
     pub medicare_snf_bed_count: Option<i32>,
-    #[serde(rename = "rehab_bed_cnt", deserialize_with = "deserialize_na_option")]
+    #[serde(
+        rename(deserialize = "rehab_bed_cnt"),
+        deserialize_with = "deserialize_na_option"
+    )]
     pub rehab_bed_count: Option<i32>,
-    #[serde(rename = "vntltr_bed_cnt", deserialize_with = "deserialize_na_option")]
+    #[serde(
+        rename(deserialize = "vntltr_bed_cnt"),
+        deserialize_with = "deserialize_na_option"
+    )]
     pub ventilator_bed_count: Option<i32>,
 
     // --- Staffing Counts (Floats/Doubles based on dict usually, but here listed as counts) ---
-    #[serde(rename = "lpn_lvn_cnt", deserialize_with = "deserialize_na_option")]
+    #[serde(
+        rename(deserialize = "lpn_lvn_cnt"),
+        deserialize_with = "deserialize_na_option"
+    )]
     pub lpn_lvn_count: Option<f64>,
-    #[serde(rename = "rn_cnt", deserialize_with = "deserialize_na_option")]
+    #[serde(
+        rename(deserialize = "rn_cnt"),
+        deserialize_with = "deserialize_na_option"
+    )]
     pub rn_count: Option<f64>,
-    #[serde(rename = "emplee_cnt", deserialize_with = "deserialize_na_option")]
+    #[serde(
+        rename(deserialize = "emplee_cnt"),
+        deserialize_with = "deserialize_na_option"
+    )]
     pub employee_count: Option<f64>,
 
     // --- Services (Boolean Switches or Codes) ---
-    #[serde(rename = "chow_sw", deserialize_with = "deserialize_yes_no")]
+    #[serde(
+        rename(deserialize = "chow_sw"),
+        deserialize_with = "deserialize_yes_no"
+    )]
     pub change_of_ownership_switch: Option<bool>,
-    #[serde(rename = "hosp_bsd_sw", deserialize_with = "deserialize_yes_no")]
+    #[serde(
+        rename(deserialize = "hosp_bsd_sw"),
+        deserialize_with = "deserialize_yes_no"
+    )]
     pub hospital_based_switch: Option<bool>,
     #[serde(
-        rename = "mlt_ownd_fac_org_sw",
+        rename(deserialize = "mlt_ownd_fac_org_sw"),
         deserialize_with = "deserialize_yes_no"
     )]
     pub multi_owned_facility_switch: Option<bool>,
 
-    #[serde(rename = "clia_lb_nb", deserialize_with = "deserialize_na_string")]
+    #[serde(
+        rename(deserialize = "clia_lb_nb"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub clia_lab_number: Option<String>,
 
     // --- Category / Types ---
     #[serde(
-        rename = "gnrl_fac_type_cd",
+        rename(deserialize = "gnrl_fac_type_cd"),
         deserialize_with = "deserialize_na_string"
     )]
     pub facility_category_code: Option<String>,
-    #[serde(rename = "control_type", deserialize_with = "deserialize_na_string")]
+    #[serde(
+        rename(deserialize = "control_type"),
+        deserialize_with = "deserialize_na_string"
+    )]
     pub ownership_type_code: Option<String>,
+
+    // --- Load tracking ---
+    /// When a POS load last included this provider.
+    #[serde(skip_deserializing)]
+    pub last_seen_at: Option<DateTime<Utc>>,
+    /// When a POS load first left this provider out. `None` while it is still listed.
+    #[serde(skip_deserializing)]
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+/// Human-readable labels for a provider's code columns, from the POS data dictionary.
+/// A label is `None` when the code is missing or the lookup table doesn't describe it yet.
+#[derive(Debug, Serialize, Clone, Default, FromRow, ToSchema)]
+pub struct ProviderLabels {
+    #[sqlx(rename = "provider_type_label")]
+    pub provider_type: Option<String>,
+    #[sqlx(rename = "provider_subtype_label")]
+    pub provider_subtype: Option<String>,
+    #[sqlx(rename = "ownership_type_label")]
+    pub ownership_type: Option<String>,
+    #[sqlx(rename = "accreditation_type_label")]
+    pub accreditation_type: Option<String>,
+    #[sqlx(rename = "compliance_status_label")]
+    pub compliance_status: Option<String>,
+    #[sqlx(rename = "certification_action_type_label")]
+    pub certification_action_type: Option<String>,
+    #[sqlx(rename = "facility_category_label")]
+    pub facility_category: Option<String>,
+    #[sqlx(rename = "cbsa_urban_rural_indicator_label")]
+    pub cbsa_urban_rural_indicator: Option<String>,
+}
+
+/// A provider joined with its address and code labels, as served by the API.
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct ProviderDetail {
+    #[serde(flatten)]
+    pub provider: Provider,
+    /// The state and facility type encoded in the CCN.
+    pub ccn: CcnParts,
+    pub address: Option<Address>,
+    pub labels: ProviderLabels,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+impl FromRow<'_, PgRow> for ProviderDetail {
+    fn from_row(row: &PgRow) -> Result<Self, sqlx::Error> {
+        let provider = Provider::from_row(row)?;
+
+        // The address columns come from a LEFT JOIN, so a missing address shows up as a NULL id.
+        let address = match row.try_get::<Option<i32>, _>("id")? {
+            Some(_) => Some(Address::from_row(row)?),
+            None => None,
+        };
+
+        Ok(Self {
+            ccn: provider.cms_certification_number.parts(),
+            provider,
+            address,
+            labels: ProviderLabels::from_row(row)?,
+        })
+    }
+}
+
+// Columns missing from the file are left empty; the engine's schema check decides whether
+// the file can be loaded without them.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
 pub struct ProviderOfServiceRow {
     // --- Address Fields ---
     #[serde(rename = "st_adr", deserialize_with = "deserialize_na_string")]
@@ -366,14 +884,242 @@ pub struct ProviderOfServiceRow {
     pub ownership_type_code: Option<String>,
 }
 
+/// One row of the Census ZCTA gazetteer: a ZIP Code Tabulation Area and its internal point.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ZctaGazetteerRow {
+    #[serde(rename = "GEOID")]
+    pub zcta: String,
+    #[serde(rename = "INTPTLAT")]
+    pub latitude: f64,
+    #[serde(rename = "INTPTLONG")]
+    pub longitude: f64,
+}
+
+/// One row of the Census county gazetteer: a county and its internal point.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct CountyGazetteerRow {
+    /// The county's five-digit FIPS code.
+    #[serde(rename = "GEOID")]
+    pub geoid: String,
+    #[serde(rename = "USPS")]
+    pub state_code: Option<String>,
+    #[serde(rename = "NAME")]
+    pub name: Option<String>,
+    #[serde(rename = "INTPTLAT")]
+    pub latitude: f64,
+    #[serde(rename = "INTPTLONG")]
+    pub longitude: f64,
+}
+
+/// One row of the Hospital Enrollments dataset: a hospital's Medicare enrollment,
+/// including Critical Access Hospitals and Rural Emergency Hospitals.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct HospitalEnrollment {
+    #[serde(rename = "ENROLLMENT ID")]
+    pub enrollment_id: String,
+    #[serde(
+        rename = "ENROLLMENT STATE",
+        deserialize_with = "deserialize_na_string"
+    )]
+    pub enrollment_state: Option<String>,
+    #[serde(
+        rename = "PROVIDER TYPE CODE",
+        deserialize_with = "deserialize_na_string"
+    )]
+    pub provider_type_code: Option<String>,
+    #[serde(
+        rename = "PROVIDER TYPE TEXT",
+        deserialize_with = "deserialize_na_string"
+    )]
+    pub provider_type_text: Option<String>,
+    #[serde(rename = "NPI", deserialize_with = "deserialize_na_string")]
+    pub npi: Option<String>,
+    #[serde(rename = "MULTIPLE NPI FLAG", deserialize_with = "deserialize_yes_no")]
+    pub multiple_npi_flag: Option<bool>,
+    #[serde(rename = "CCN", deserialize_with = "deserialize_na_string")]
+    pub cms_certification_number: Option<String>,
+    #[serde(rename = "ASSOCIATE ID", deserialize_with = "deserialize_na_string")]
+    pub associate_id: Option<String>,
+    #[serde(
+        rename = "ORGANIZATION NAME",
+        deserialize_with = "deserialize_na_string"
+    )]
+    pub organization_name: Option<String>,
+    #[serde(
+        rename = "DOING BUSINESS AS NAME",
+        deserialize_with = "deserialize_na_string"
+    )]
+    pub doing_business_as_name: Option<String>,
+    #[serde(
+        rename = "INCORPORATION DATE",
+        deserialize_with = "deserialize_optional_date"
+    )]
+    pub incorporation_date: Option<NaiveDate>,
+    #[serde(
+        rename = "INCORPORATION STATE",
+        deserialize_with = "deserialize_na_string"
+    )]
+    pub incorporation_state: Option<String>,
+    #[serde(
+        rename = "ORGANIZATION TYPE STRUCTURE",
+        deserialize_with = "deserialize_na_string"
+    )]
+    pub organization_type_structure: Option<String>,
+    #[serde(
+        rename = "ORGANIZATION OTHER TYPE TEXT",
+        deserialize_with = "deserialize_na_string"
+    )]
+    pub organization_other_type_text: Option<String>,
+    #[serde(
+        rename = "PROPRIETARY_NONPROFIT",
+        deserialize_with = "deserialize_na_string"
+    )]
+    pub proprietary_nonprofit: Option<String>,
+
+    // --- Practice location ---
+    #[serde(rename = "ADDRESS LINE 1", deserialize_with = "deserialize_na_string")]
+    pub address_line_1: Option<String>,
+    #[serde(rename = "ADDRESS LINE 2", deserialize_with = "deserialize_na_string")]
+    pub address_line_2: Option<String>,
+    #[serde(rename = "CITY", deserialize_with = "deserialize_na_string")]
+    pub city: Option<String>,
+    #[serde(rename = "STATE", deserialize_with = "deserialize_na_string")]
+    pub state_code: Option<String>,
+    #[serde(rename = "ZIP CODE", deserialize_with = "deserialize_na_string")]
+    pub zip_code: Option<String>,
+    #[serde(
+        rename = "PRACTICE LOCATION TYPE",
+        deserialize_with = "deserialize_na_string"
+    )]
+    pub practice_location_type: Option<String>,
+    #[serde(
+        rename = "LOCATION OTHER TYPE TEXT",
+        deserialize_with = "deserialize_na_string"
+    )]
+    pub location_other_type_text: Option<String>,
+
+    // --- Subgroups (Y/N) ---
+    #[serde(rename = "SUBGROUP - GENERAL", deserialize_with = "deserialize_yes_no")]
+    pub subgroup_general: Option<bool>,
+    #[serde(
+        rename = "SUBGROUP - ACUTE CARE",
+        deserialize_with = "deserialize_yes_no"
+    )]
+    pub subgroup_acute_care: Option<bool>,
+    #[serde(
+        rename = "SUBGROUP - ALCOHOL DRUG",
+        deserialize_with = "deserialize_yes_no"
+    )]
+    pub subgroup_alcohol_drug: Option<bool>,
+    #[serde(
+        rename = "SUBGROUP - CHILDRENS",
+        deserialize_with = "deserialize_yes_no"
+    )]
+    pub subgroup_childrens: Option<bool>,
+    #[serde(
+        rename = "SUBGROUP - LONG-TERM",
+        deserialize_with = "deserialize_yes_no"
+    )]
+    pub subgroup_long_term: Option<bool>,
+    #[serde(
+        rename = "SUBGROUP - PSYCHIATRIC",
+        deserialize_with = "deserialize_yes_no"
+    )]
+    pub subgroup_psychiatric: Option<bool>,
+    #[serde(
+        rename = "SUBGROUP - REHABILITATION",
+        deserialize_with = "deserialize_yes_no"
+    )]
+    pub subgroup_rehabilitation: Option<bool>,
+    #[serde(
+        rename = "SUBGROUP - SHORT-TERM",
+        deserialize_with = "deserialize_yes_no"
+    )]
+    pub subgroup_short_term: Option<bool>,
+    #[serde(
+        rename = "SUBGROUP - SWING-BED APPROVED",
+        deserialize_with = "deserialize_yes_no"
+    )]
+    pub subgroup_swing_bed_approved: Option<bool>,
+    #[serde(
+        rename = "SUBGROUP - PSYCHIATRIC UNIT",
+        deserialize_with = "deserialize_yes_no"
+    )]
+    pub subgroup_psychiatric_unit: Option<bool>,
+    #[serde(
+        rename = "SUBGROUP - REHABILITATION UNIT",
+        deserialize_with = "deserialize_yes_no"
+    )]
+    pub subgroup_rehabilitation_unit: Option<bool>,
+    #[serde(
+        rename = "SUBGROUP - SPECIALTY HOSPITAL",
+        deserialize_with = "deserialize_yes_no"
+    )]
+    pub subgroup_specialty_hospital: Option<bool>,
+    #[serde(rename = "SUBGROUP - OTHER", deserialize_with = "deserialize_yes_no")]
+    pub subgroup_other: Option<bool>,
+    #[serde(
+        rename = "SUBGROUP - OTHER TEXT",
+        deserialize_with = "deserialize_na_string"
+    )]
+    pub subgroup_other_text: Option<String>,
+
+    // --- Rural Emergency Hospital conversion ---
+    #[serde(
+        rename = "REH CONVERSION FLAG",
+        deserialize_with = "deserialize_yes_no"
+    )]
+    pub reh_conversion_flag: Option<bool>,
+    #[serde(
+        rename = "REH CONVERSION DATE",
+        deserialize_with = "deserialize_optional_date"
+    )]
+    pub reh_conversion_date: Option<NaiveDate>,
+    #[serde(
+        rename = "CAH OR HOSPITAL CCN",
+        deserialize_with = "deserialize_na_string"
+    )]
+    pub cah_or_hospital_ccn: Option<String>,
+}
+
+impl HospitalEnrollment {
+    /// PECOS provider type code for Critical Access Hospitals.
+    const CRITICAL_ACCESS_HOSPITAL_TYPE: &'static str = "00-85";
+
+    fn provider_type_text_contains(&self, needle: &str) -> bool {
+        self.provider_type_text
+            .as_deref()
+            .is_some_and(|t| t.to_ascii_uppercase().contains(needle))
+    }
+
+    pub fn is_critical_access_hospital(&self) -> bool {
+        self.provider_type_code.as_deref() == Some(Self::CRITICAL_ACCESS_HOSPITAL_TYPE)
+            || self.provider_type_text_contains("CRITICAL ACCESS")
+    }
+
+    pub fn is_rural_emergency_hospital(&self) -> bool {
+        self.provider_type_text_contains("RURAL EMERGENCY")
+    }
+}
+
 impl From<ProviderOfServiceRow> for Address {
     fn from(row: ProviderOfServiceRow) -> Self {
+        let standard = StandardAddress::new(
+            row.street_address.as_deref(),
+            row.city.as_deref(),
+            row.state_code.as_deref(),
+            row.zip_code.as_deref(),
+        );
         Address {
             id: None,
-            street_address: row.street_address,
-            city: row.city,
-            state_code: row.state_code,
-            zip_code: row.zip_code,
+            street_address: standard.street_address,
+            city: standard.city,
+            state_code: standard.state_code,
+            zip_code: standard.zip_code,
+            zip4: standard.zip4,
             ssa_county_code: row.ssa_county_code,
             ssa_state_code: row.ssa_state_code,
             state_region_code: row.state_region_code,
@@ -382,14 +1128,19 @@ impl From<ProviderOfServiceRow> for Address {
             fips_county_code: row.fips_county_code,
             cbsa_code: row.cbsa_code,
             cbsa_urban_rural_indicator: row.cbsa_urban_rural_indicator,
+            latitude: None,
+            longitude: None,
+            geocode_method: None,
         }
     }
 }
 
-impl From<ProviderOfServiceRow> for Provider {
-    fn from(row: ProviderOfServiceRow) -> Self {
-        Provider {
-            cms_certification_number: row.cms_certification_number,
+impl TryFrom<ProviderOfServiceRow> for Provider {
+    type Error = InvalidCcn;
+
+    fn try_from(row: ProviderOfServiceRow) -> Result<Self, Self::Error> {
+        Ok(Provider {
+            cms_certification_number: row.cms_certification_number.parse()?,
             name: row.name,
             provider_subtype_id: row.provider_subtype_id,
             medicaid_vendor_number: row.medicaid_vendor_number,
@@ -431,7 +1182,9 @@ impl From<ProviderOfServiceRow> for Provider {
             clia_lab_number: row.clia_lab_number,
             facility_category_code: row.facility_category_code,
             ownership_type_code: row.ownership_type_code,
-        }
+            last_seen_at: None,
+            retired_at: None,
+        })
     }
 }
 
@@ -450,13 +1203,13 @@ where
         {
             Ok(None)
         }
-        Some(v) => {
-            // Try to parse the value into target type T
-            match v.parse::<T>() {
-                Ok(val) => Ok(Some(val)),
-                Err(_) => Ok(None), // Treat parsing errors as None for now
-            }
-        }
+        // A value that is neither missing nor a number is an error, so the row is rejected
+        // rather than loaded with the value silently dropped.
+        Some(v) => v
+            .trim()
+            .parse::<T>()
+            .map(Some)
+            .map_err(|_| serde::de::Error::custom(format!("'{}' is not a valid number", v))),
         None => Ok(None),
     }
 }
@@ -487,13 +1240,17 @@ where
 {
     let s: Option<String> = Option::deserialize(deserializer)?;
     match s {
-        Some(ref v) if v.eq_ignore_ascii_case("Yes") => Ok(Some(true)),
-        Some(ref v) if v.eq_ignore_ascii_case("No") => Ok(Some(false)),
+        Some(ref v) if v.eq_ignore_ascii_case("Yes") || v.eq_ignore_ascii_case("Y") => {
+            Ok(Some(true))
+        }
+        Some(ref v) if v.eq_ignore_ascii_case("No") || v.eq_ignore_ascii_case("N") => {
+            Ok(Some(false))
+        }
         _ => Ok(None),
     }
 }
 
-// Helper for Date YYYY-MM-DD/YYYYMMDD
+// Helper for Date YYYY-MM-DD/YYYYMMDD/MM/DD/YYYY
 fn deserialize_optional_date<'de, D>(deserializer: D) -> Result<Option<NaiveDate>, D::Error>
 where
     D: Deserializer<'de>,
@@ -514,8 +1271,125 @@ where
             if let Ok(d) = NaiveDate::parse_from_str(&date_str, "%Y%m%d") {
                 return Ok(Some(d));
             }
+            if let Ok(d) = NaiveDate::parse_from_str(&date_str, "%m/%d/%Y") {
+                return Ok(Some(d));
+            }
             Ok(None)
         }
         None => Ok(None),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ccn(s: &str) -> Ccn {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn well_formed_ccns_parse() {
+        for s in ["220071", "22S071", "05C0001234", "45D0000001"] {
+            assert_eq!(ccn(s).as_str(), s);
+        }
+    }
+
+    #[test]
+    fn malformed_ccns_do_not_parse() {
+        for s in [
+            "",
+            "22007",
+            "2200711",
+            "22s071",
+            "AB0071",
+            "22-071",
+            "2200A1",
+            " 220071",
+            "0500001234",
+            "05C000123X",
+            "２２0071",
+        ] {
+            assert!(s.parse::<Ccn>().is_err(), "{:?} parsed", s);
+        }
+    }
+
+    #[test]
+    fn deserializing_checks_the_format() {
+        assert_eq!(
+            serde_json::from_value::<Ccn>(" 220071 ".into()).unwrap(),
+            ccn("220071")
+        );
+        assert!(serde_json::from_value::<Ccn>("22007".into()).is_err());
+    }
+
+    #[test]
+    fn state_comes_from_the_prefix() {
+        assert_eq!(ccn("220071").state_code(), Some("MA"));
+        assert_eq!(ccn("550001").state_code(), Some("CA"));
+        assert_eq!(ccn("05C0001234").state_code(), Some("CA"));
+        assert_eq!(ccn("540001").state_code(), None);
+        assert_eq!(ccn("990001").state_code(), None);
+    }
+
+    #[test]
+    fn facility_type_comes_from_the_letter() {
+        assert_eq!(
+            ccn("22S071").facility_type(),
+            Some(FacilityType::PsychiatricUnit)
+        );
+        assert_eq!(
+            ccn("22T071").facility_type(),
+            Some(FacilityType::RehabilitationUnit)
+        );
+        assert_eq!(ccn("22Z071").facility_type(), Some(FacilityType::SwingBeds));
+        assert_eq!(
+            ccn("05C0001234").facility_type(),
+            Some(FacilityType::AmbulatorySurgicalCenter)
+        );
+        assert_eq!(
+            ccn("05D0001234").facility_type(),
+            Some(FacilityType::ClinicalLaboratory)
+        );
+        assert_eq!(ccn("22A071").facility_type(), None);
+    }
+
+    #[test]
+    fn facility_type_ranges_are_inclusive() {
+        let kind = |number: u16| ccn(&format!("22{:04}", number)).facility_type();
+        assert_eq!(kind(1), Some(FacilityType::ShortTermHospital));
+        assert_eq!(kind(879), Some(FacilityType::ShortTermHospital));
+        assert_eq!(kind(1299), Some(FacilityType::MedicalAssistanceFacility));
+        assert_eq!(kind(1300), Some(FacilityType::CriticalAccessHospital));
+        assert_eq!(kind(1399), Some(FacilityType::CriticalAccessHospital));
+        assert_eq!(kind(1400), Some(FacilityType::CommunityMentalHealthCenter));
+        assert_eq!(kind(4999), Some(FacilityType::CommunityMentalHealthCenter));
+        assert_eq!(kind(5000), Some(FacilityType::SkilledNursingFacility));
+        assert_eq!(kind(6499), Some(FacilityType::SkilledNursingFacility));
+        assert_eq!(kind(6500), Some(FacilityType::OutpatientPhysicalTherapy));
+        assert_eq!(kind(0), None);
+        assert_eq!(kind(880), None);
+    }
+
+    #[test]
+    fn ranges_do_not_overlap() {
+        for pair in CCN_RANGES.windows(2) {
+            assert!(pair[0].0 <= pair[0].1, "{:?}", pair[0]);
+            assert!(
+                pair[0].1 < pair[1].0,
+                "{:?} overlaps {:?}",
+                pair[0],
+                pair[1]
+            );
+        }
+    }
+
+    #[test]
+    fn stored_ccns_that_do_not_conform_have_no_parts() {
+        for stored in ["", "2", "22", "ÿÿ0071", "ABCDEF"] {
+            let stored = Ccn(stored.to_string());
+            assert_eq!(stored.state_code(), None, "{:?}", stored);
+            assert_eq!(stored.facility_type(), None, "{:?}", stored);
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use crate::address::split_zip;
+use crate::model::{Ccn, ProviderDetail};
+use crate::schema::SchemaDiff;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::postgres::{PgPool, PgRow};
+use sqlx::{FromRow, Postgres, QueryBuilder, Row};
+use utoipa::{IntoParams, ToSchema};
+
+/// The columns `ProviderDetail` reads. Provider and address column names do not overlap,
+/// and the lookup labels are aliased, so every column can be read by name.
+const PROVIDER_DETAIL_COLUMNS: &str = "p.*, a.*,
+    pt.label AS provider_type_label,
+    pst.label AS provider_subtype_label,
+    ot.label AS ownership_type_label,
+    act.label AS accreditation_type_label,
+    cs.label AS compliance_status_label,
+    cat.label AS certification_action_type_label,
+    fc.label AS facility_category_label,
+    cur.label AS cbsa_urban_rural_indicator_label";
+
+/// Joins every provider to its address and code lookups.
+const PROVIDER_DETAIL_FROM: &str = "FROM providers p
+    LEFT JOIN addresses a ON a.id = p.address_id
+    LEFT JOIN provider_types pt ON pt.id = p.provider_type_id
+    LEFT JOIN provider_subtypes pst
+        ON pst.provider_type_id = p.provider_type_id AND pst.id = p.provider_subtype_id
+    LEFT JOIN ownership_types ot ON ot.code = p.ownership_type_code
+    LEFT JOIN accreditation_types act ON act.code = p.accreditation_type_code
+    LEFT JOIN compliance_statuses cs ON cs.code = p.compliance_status_code
+    LEFT JOIN certification_action_types cat ON cat.code = p.certification_action_type_code
+    LEFT JOIN facility_categories fc ON fc.code = p.facility_category_code
+    LEFT JOIN cbsa_urban_rural_indicators cur ON cur.code = a.cbsa_urban_rural_indicator";
+
+/// Filters for searching providers. Every filter is optional and they are combined with AND.
+#[derive(Debug, Default, Clone, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ProviderFilter {
+    pub state_code: Option<String>,
+    /// A five-digit ZIP; a ZIP+4 matches its five-digit ZIP.
+    pub zip_code: Option<String>,
+    pub provider_type_id: Option<i32>,
+    pub provider_subtype_id: Option<i32>,
+    pub facility_category_code: Option<String>,
+    pub ownership_type_code: Option<String>,
+    pub cbsa_code: Option<String>,
+    pub min_bed_count: Option<i32>,
+    pub max_bed_count: Option<i32>,
+    pub min_certified_bed_count: Option<i32>,
+    pub max_certified_bed_count: Option<i32>,
+    /// Also return providers that the latest POS file no longer lists.
+    pub include_retired: Option<bool>,
+}
+
+impl ProviderFilter {
+    /// Appends the filter conditions to a query that already contains a `WHERE` clause.
+    fn push_conditions(&self, qb: &mut QueryBuilder<'_, Postgres>) {
+        if !self.include_retired.unwrap_or(false) {
+            qb.push(" AND p.retired_at IS NULL");
+        }
+        if let Some(v) = &self.state_code {
+            qb.push(" AND a.state_code = ").push_bind(v.clone());
+        }
+        if let Some(v) = &self.zip_code {
+            let (zip5, _) = split_zip(v);
+            qb.push(" AND a.zip_code = ").push_bind(zip5);
+        }
+        if let Some(v) = self.provider_type_id {
+            qb.push(" AND p.provider_type_id = ").push_bind(v);
+        }
+        if let Some(v) = self.provider_subtype_id {
+            qb.push(" AND p.provider_subtype_id = ").push_bind(v);
+        }
+        if let Some(v) = &self.facility_category_code {
+            qb.push(" AND p.facility_category_code = ")
+                .push_bind(v.clone());
+        }
+        if let Some(v) = &self.ownership_type_code {
+            qb.push(" AND p.ownership_type_code = ")
+                .push_bind(v.clone());
+        }
+        if let Some(v) = &self.cbsa_code {
+            qb.push(" AND a.cbsa_code = ").push_bind(v.clone());
+        }
+        if let Some(v) = self.min_bed_count {
+            qb.push(" AND p.bed_count >= ").push_bind(v);
+        }
+        if let Some(v) = self.max_bed_count {
+            qb.push(" AND p.bed_count <= ").push_bind(v);
+        }
+        if let Some(v) = self.min_certified_bed_count {
+            qb.push(" AND p.certified_bed_count >= ").push_bind(v);
+        }
+        if let Some(v) = self.max_certified_bed_count {
+            qb.push(" AND p.certified_bed_count <= ").push_bind(v);
+        }
+    }
+}
+
+/// A page of results, paginated by keyset on the CMS Certification Number.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// The number of rows matching the filters, across all pages.
+    pub total_count: i64,
+    /// Pass this as `cursor` to fetch the next page. `None` on the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// A provider matched by a fuzzy name search.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProviderMatch {
+    /// How closely the query matches the facility name or city, from 0 to 1.
+    pub score: f32,
+    #[serde(flatten)]
+    pub provider: ProviderDetail,
+}
+
+impl FromRow<'_, PgRow> for ProviderMatch {
+    fn from_row(row: &PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            score: row.try_get("score")?,
+            provider: ProviderDetail::from_row(row)?,
+        })
+    }
+}
+
+/// A field whose value differs from the provider's previous version.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FieldChange {
+    pub field: String,
+    /// `null` in the first version, or when the field was empty before.
+    pub previous: Value,
+    pub current: Value,
+}
+
+/// One version of a provider, as loaded from the POS file.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProviderVersion {
+    /// When the POS load that first saw this version ran.
+    pub valid_from: DateTime<Utc>,
+    /// When the POS load that changed or retired the provider ran. `None` for the current version.
+    pub valid_to: Option<DateTime<Utc>>,
+    /// The fields that changed since the previous version. The first version lists every
+    /// field that has a value.
+    pub changes: Vec<FieldChange>,
+}
+
+/// Every version of a provider, oldest first.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProviderHistory {
+    #[schema(value_type = String, example = "220071")]
+    pub cms_certification_number: Ccn,
+    pub versions: Vec<ProviderVersion>,
+}
+
+/// Lists the fields that differ between two versions of a provider. A field missing from
+/// either version is treated as `null`.
+fn diff_versions(previous: &Map<String, Value>, current: &Map<String, Value>) -> Vec<FieldChange> {
+    let fields: std::collections::BTreeSet<&String> =
+        previous.keys().chain(current.keys()).collect();
+    fields
+        .into_iter()
+        .filter_map(|field| {
+            let before = previous.get(field).unwrap_or(&Value::Null);
+            let after = current.get(field).unwrap_or(&Value::Null);
+            (before != after).then(|| FieldChange {
+                field: field.clone(),
+                previous: before.clone(),
+                current: after.clone(),
+            })
+        })
+        .collect()
+}
+
+#[derive(FromRow)]
+struct HistoryRow {
+    valid_from: DateTime<Utc>,
+    valid_to: Option<DateTime<Utc>>,
+    record: sqlx::types::Json<Map<String, Value>>,
+}
+
+/// Returns the field-level history of a provider, or `None` if it has never been loaded.
+pub async fn get_provider_history(pool: &PgPool, ccn: &Ccn) -> Result<Option<ProviderHistory>> {
+    let rows: Vec<HistoryRow> = sqlx::query_as(
+        "SELECT valid_from, valid_to, record FROM provider_history
+         WHERE cms_certification_number = $1
+         ORDER BY valid_from",
+    )
+    .bind(ccn)
+    .fetch_all(pool)
+    .await?;
+
+    if rows.is_empty() {
+        return Ok(None);
+    }
+
+    let mut previous = Map::new();
+    let versions = rows
+        .into_iter()
+        .map(|row| {
+            let changes = diff_versions(&previous, &row.record);
+            previous = row.record.0;
+            ProviderVersion {
+                valid_from: row.valid_from,
+                valid_to: row.valid_to,
+                changes,
+            }
+        })
+        .collect();
+
+    Ok(Some(ProviderHistory {
+        cms_certification_number: ccn.clone(),
+        versions,
+    }))
+}
+
+/// Looks up a provider by CCN. Retired providers are only returned with `include_retired`.
+pub async fn get_provider(
+    pool: &PgPool,
+    ccn: &Ccn,
+    include_retired: bool,
+) -> Result<Option<ProviderDetail>> {
+    let provider = sqlx::query_as::<_, ProviderDetail>(&format!(
+        "SELECT {PROVIDER_DETAIL_COLUMNS} {PROVIDER_DETAIL_FROM}
+         WHERE p.cms_certification_number = $1 AND ($2 OR p.retired_at IS NULL)"
+    ))
+    .bind(ccn)
+    .bind(include_retired)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(provider)
+}
+
+/// Lists providers matching `filter`, ordered by CMS Certification Number.
+///
+/// Paging uses the last CCN of the previous page (`cursor`) rather than an OFFSET,
+/// so deep pages stay as cheap as the first one.
+pub async fn list_providers(
+    pool: &PgPool,
+    filter: &ProviderFilter,
+    cursor: Option<&str>,
+    limit: i64,
+) -> Result<Page<ProviderDetail>> {
+    let mut count_query: QueryBuilder<Postgres> =
+        QueryBuilder::new(format!("SELECT COUNT(*) {PROVIDER_DETAIL_FROM} WHERE TRUE"));
+    filter.push_conditions(&mut count_query);
+    let total_count: i64 = count_query.build_query_scalar().fetch_one(pool).await?;
+
+    let mut page_query: QueryBuilder<Postgres> = QueryBuilder::new(format!(
+        "SELECT {PROVIDER_DETAIL_COLUMNS} {PROVIDER_DETAIL_FROM} WHERE TRUE"
+    ));
+    filter.push_conditions(&mut page_query);
+    if let Some(cursor) = cursor {
+        page_query
+            .push(" AND p.cms_certification_number > ")
+            .push_bind(cursor.to_string());
+    }
+    // Fetch one extra row to find out whether there is a next page.
+    page_query
+        .push(" ORDER BY p.cms_certification_number LIMIT ")
+        .push_bind(limit + 1);
+
+    let mut items: Vec<ProviderDetail> = page_query.build_query_as().fetch_all(pool).await?;
+
+    let next_cursor = if items.len() as i64 > limit {
+        items.truncate(limit as usize);
+        items
+            .last()
+            .map(|p| p.provider.cms_certification_number.to_string())
+    } else {
+        None
+    };
+
+    Ok(Page {
+        items,
+        total_count,
+        next_cursor,
+    })
+}
+
+/// Finds providers whose name (or city) resembles `query`, best matches first.
+///
+/// Matching uses `pg_trgm` word similarity, so a query only has to resemble part of the
+/// name: "St Marys Regional" finds "ST. MARY'S REGIONAL MEDICAL CENTER".
+pub async fn search_providers(
+    pool: &PgPool,
+    query: &str,
+    state_code: Option<&str>,
+    include_retired: bool,
+    limit: i64,
+) -> Result<Vec<ProviderMatch>> {
+    // Each branch of the UNION can use its own trigram index, which a single
+    // `name <% $1 OR city <% $1` across the join could not.
+    let matches = sqlx::query_as::<_, ProviderMatch>(&format!(
+        "WITH matches AS (
+            SELECT p.cms_certification_number, word_similarity($1, p.name) AS score
+            FROM providers p
+            WHERE $1 <% p.name
+            UNION ALL
+            SELECT p.cms_certification_number, word_similarity($1, a.city) AS score
+            FROM addresses a
+            JOIN providers p ON p.address_id = a.id
+            WHERE $1 <% a.city
+        ),
+        ranked AS (
+            SELECT cms_certification_number, MAX(score) AS score
+            FROM matches
+            GROUP BY cms_certification_number
+        )
+        SELECT {PROVIDER_DETAIL_COLUMNS}, r.score
+        {PROVIDER_DETAIL_FROM}
+        JOIN ranked r ON r.cms_certification_number = p.cms_certification_number
+        WHERE ($2::TEXT IS NULL OR a.state_code = $2) AND ($3 OR p.retired_at IS NULL)
+        ORDER BY r.score DESC, p.cms_certification_number
+        LIMIT $4"
+    ))
+    .bind(query)
+    .bind(state_code)
+    .bind(include_retired)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(matches)
+}
+
+/// One entry of `loader_run_history`.
+#[derive(Debug, Serialize, FromRow, ToSchema)]
+pub struct LoaderRun {
+    pub id: i64,
+    pub loader_key: String,
+    pub plugin_version: i32,
+    /// `running`, `succeeded`, `skipped` (the file was unchanged) or `failed`. A run still
+    /// `running` after it should have finished was interrupted.
+    pub status: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub duration_ms: Option<i64>,
+    pub source_url: Option<String>,
+    pub file_hash: Option<String>,
+    pub rows_parsed: Option<i64>,
+    pub rows_inserted: Option<i64>,
+    pub rows_updated: Option<i64>,
+    pub rows_rejected: Option<i64>,
+    pub error: Option<String>,
+    /// How the file's header differed from the columns the loader expects, if it did.
+    #[schema(value_type = Option<SchemaDiff>)]
+    pub schema_diff: Option<sqlx::types::Json<SchemaDiff>>,
+}
+
+/// Lists loader runs, most recent first, optionally for a single loader.
+pub async fn list_loader_runs(
+    pool: &PgPool,
+    loader_key: Option<&str>,
+    limit: i64,
+) -> Result<Vec<LoaderRun>> {
+    let runs = sqlx::query_as::<_, LoaderRun>(
+        "SELECT * FROM loader_run_history
+         WHERE ($1::TEXT IS NULL OR loader_key = $1)
+         ORDER BY started_at DESC, id DESC
+         LIMIT $2",
+    )
+    .bind(loader_key)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(runs)
+}
@@ -0,0 +1,381 @@
+//! Schema drift detection: compares a file's header row with the columns its loader expects.
+//!
+//! CMS renames and adds columns between releases. Checking the header before loading turns
+//! a missing column into a clear error, or a warning, instead of a parse error on every row,
+//! and the differences are kept in the run history.
+
+use anyhow::{Result, bail};
+use serde::de::{self, DeserializeOwned, Deserializer, Visitor};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::Read;
+use utoipa::ToSchema;
+
+/// A column a loader reads.
+#[derive(Debug, Clone)]
+pub struct ExpectedColumn {
+    pub name: &'static str,
+    /// Without a required column no row can be loaded, so a file missing one always fails.
+    pub required: bool,
+}
+
+/// What to do when a file lacks a column that is not required.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissingColumnPolicy {
+    /// Load anyway, leaving the column's values empty, and log a warning.
+    #[default]
+    Warn,
+    /// Fail the run.
+    Fail,
+}
+
+/// A column that seems to have been renamed: the expected one is missing and a similarly
+/// named one appeared.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RenamedColumn {
+    pub from: String,
+    pub to: String,
+}
+
+/// How a file's header differs from the columns its loader expects.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct SchemaDiff {
+    /// Columns in the file that the loader doesn't read.
+    pub added: Vec<String>,
+    /// Expected columns missing from the file.
+    pub removed: Vec<String>,
+    /// Expected columns missing from the file under their old name, but present under a new one.
+    pub renamed: Vec<RenamedColumn>,
+}
+
+impl SchemaDiff {
+    /// Compares `header` with `expected`.
+    pub fn new(expected: &[ExpectedColumn], header: &[String]) -> Self {
+        let expected_names: BTreeSet<&str> = expected.iter().map(|c| c.name).collect();
+        let header_names: BTreeSet<&str> = header.iter().map(String::as_str).collect();
+
+        let mut added: Vec<String> = header
+            .iter()
+            .filter(|name| !expected_names.contains(name.as_str()))
+            .cloned()
+            .collect();
+        let mut removed = Vec::new();
+        let mut renamed = Vec::new();
+        for column in expected {
+            if header_names.contains(column.name) {
+                continue;
+            }
+            let closest = added
+                .iter()
+                .enumerate()
+                .filter_map(|(i, name)| similarity(column.name, name).map(|d| (d, i)))
+                .min();
+            match closest {
+                Some((_, i)) => renamed.push(RenamedColumn {
+                    from: column.name.to_string(),
+                    to: added.remove(i),
+                }),
+                None => removed.push(column.name.to_string()),
+            }
+        }
+
+        Self {
+            added,
+            removed,
+            renamed,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.renamed.is_empty()
+    }
+
+    /// Expected columns the file lacks, whether removed or renamed.
+    pub fn missing(&self) -> impl Iterator<Item = &str> {
+        self.removed
+            .iter()
+            .map(String::as_str)
+            .chain(self.renamed.iter().map(|r| r.from.as_str()))
+    }
+
+    /// Whether a file with this diff can be loaded: not if it lacks a required column, nor
+    /// any other expected column under `MissingColumnPolicy::Fail`.
+    pub fn check(&self, expected: &[ExpectedColumn], policy: MissingColumnPolicy) -> Result<()> {
+        let missing: Vec<&str> = self.missing().collect();
+        let required: Vec<String> = expected
+            .iter()
+            .filter(|c| c.required && missing.contains(&c.name))
+            .map(|c| match self.renamed.iter().find(|r| r.from == c.name) {
+                Some(renamed) => format!("{} (renamed to {}?)", c.name, renamed.to),
+                None => c.name.to_string(),
+            })
+            .collect();
+        if !required.is_empty() {
+            bail!(
+                "The file is missing required columns: {}",
+                required.join(", ")
+            );
+        }
+        if !missing.is_empty() && policy == MissingColumnPolicy::Fail {
+            bail!("The file is missing columns: {}", missing.join(", "));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SchemaDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if !self.removed.is_empty() {
+            parts.push(format!("removed {}", self.removed.join(", ")));
+        }
+        if !self.renamed.is_empty() {
+            let renamed: Vec<String> = self
+                .renamed
+                .iter()
+                .map(|r| format!("{} -> {}", r.from, r.to))
+                .collect();
+            parts.push(format!("renamed {}", renamed.join(", ")));
+        }
+        if !self.added.is_empty() {
+            parts.push(format!("added {}", self.added.join(", ")));
+        }
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+/// How far apart two column names are, if close enough to be the same column renamed:
+/// equal but for case and punctuation, or within a few edits of each other.
+fn similarity(expected: &str, found: &str) -> Option<usize> {
+    let normalize = |s: &str| -> Vec<char> {
+        s.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+    let (a, b) = (normalize(expected), normalize(found));
+    let distance = edit_distance(&a, &b);
+    (distance <= (a.len().max(b.len()) / 4).max(1)).then_some(distance)
+}
+
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The header row of a CSV file.
+pub fn csv_header(reader: impl Read) -> Result<Vec<String>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(reader);
+    Ok(rdr.headers()?.iter().map(str::to_string).collect())
+}
+
+/// The columns a row type reads, as named in the file: the serde field names, after renames.
+/// `required` lists the ones a file must have.
+pub fn columns_of<T: DeserializeOwned>(required: &[&str]) -> Vec<ExpectedColumn> {
+    let mut collector = FieldNames(None);
+    // The collector always fails; it only records the fields it was asked for.
+    let _ = T::deserialize(&mut collector);
+    collector
+        .0
+        .unwrap_or_default()
+        .iter()
+        .map(|&name| ExpectedColumn {
+            name,
+            required: required.contains(&name),
+        })
+        .collect()
+}
+
+/// A deserializer that records the field names of the struct it is asked to deserialize.
+struct FieldNames(Option<&'static [&'static str]>);
+
+impl<'de> Deserializer<'de> for &mut FieldNames {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("expected a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0 = Some(fields);
+        Err(de::Error::custom("field names collected"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+        ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expected(columns: &[(&'static str, bool)]) -> Vec<ExpectedColumn> {
+        columns
+            .iter()
+            .map(|&(name, required)| ExpectedColumn { name, required })
+            .collect()
+    }
+
+    fn header(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    const COLUMNS: &[(&str, bool)] =
+        &[("prvdr_num", true), ("fac_name", false), ("bed_cnt", false)];
+
+    #[test]
+    fn matching_header_has_no_diff() {
+        let diff = SchemaDiff::new(
+            &expected(COLUMNS),
+            &header(&["prvdr_num", "fac_name", "bed_cnt"]),
+        );
+        assert!(diff.is_empty());
+        assert!(
+            diff.check(&expected(COLUMNS), MissingColumnPolicy::Fail)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn reordered_columns_are_not_a_change() {
+        let diff = SchemaDiff::new(
+            &expected(COLUMNS),
+            &header(&["bed_cnt", "prvdr_num", "fac_name"]),
+        );
+        assert!(diff.is_empty(), "{}", diff);
+    }
+
+    #[test]
+    fn extra_columns_are_added() {
+        let diff = SchemaDiff::new(
+            &expected(COLUMNS),
+            &header(&["prvdr_num", "new_flag", "fac_name", "bed_cnt", "other"]),
+        );
+        assert_eq!(diff.added, ["new_flag", "other"]);
+        assert!(diff.removed.is_empty() && diff.renamed.is_empty());
+        assert_eq!(diff.to_string(), "added new_flag, other");
+        // Extra columns never stop a load.
+        assert!(
+            diff.check(&expected(COLUMNS), MissingColumnPolicy::Fail)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn missing_columns_are_removed() {
+        let diff = SchemaDiff::new(&expected(COLUMNS), &header(&["prvdr_num", "zip_cd"]));
+        assert_eq!(diff.removed, ["fac_name", "bed_cnt"]);
+        assert_eq!(diff.added, ["zip_cd"]);
+        assert_eq!(diff.to_string(), "removed fac_name, bed_cnt; added zip_cd");
+    }
+
+    #[test]
+    fn similar_columns_are_renamed() {
+        let diff = SchemaDiff::new(
+            &expected(COLUMNS),
+            &header(&["PRVDR_NUM", "fac-name", "bed_count"]),
+        );
+        assert!(diff.removed.is_empty() && diff.added.is_empty(), "{}", diff);
+        let renamed: Vec<(&str, &str)> = diff
+            .renamed
+            .iter()
+            .map(|r| (r.from.as_str(), r.to.as_str()))
+            .collect();
+        assert_eq!(
+            renamed,
+            [
+                ("prvdr_num", "PRVDR_NUM"),
+                ("fac_name", "fac-name"),
+                ("bed_cnt", "bed_count")
+            ]
+        );
+        assert_eq!(
+            diff.missing().collect::<Vec<_>>(),
+            ["prvdr_num", "fac_name", "bed_cnt"]
+        );
+    }
+
+    #[test]
+    fn dissimilar_columns_are_not_renamed() {
+        let diff = SchemaDiff::new(&expected(&[("bed_cnt", false)]), &header(&["zip_cd"]));
+        assert_eq!(diff.removed, ["bed_cnt"]);
+        assert_eq!(diff.added, ["zip_cd"]);
+    }
+
+    #[test]
+    fn missing_required_column_always_fails() {
+        let expected = expected(COLUMNS);
+        let diff = SchemaDiff::new(&expected, &header(&["fac_name", "bed_cnt"]));
+        for policy in [MissingColumnPolicy::Warn, MissingColumnPolicy::Fail] {
+            let error = diff.check(&expected, policy).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "The file is missing required columns: prvdr_num"
+            );
+        }
+    }
+
+    #[test]
+    fn renamed_required_column_fails_with_a_hint() {
+        let expected = expected(COLUMNS);
+        let diff = SchemaDiff::new(&expected, &header(&["prvdr_nbr", "fac_name", "bed_cnt"]));
+        let error = diff
+            .check(&expected, MissingColumnPolicy::Warn)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The file is missing required columns: prvdr_num (renamed to prvdr_nbr?)"
+        );
+    }
+
+    #[test]
+    fn missing_optional_column_follows_the_policy() {
+        let expected = expected(COLUMNS);
+        let diff = SchemaDiff::new(&expected, &header(&["prvdr_num", "fac_name"]));
+        assert!(diff.check(&expected, MissingColumnPolicy::Warn).is_ok());
+        let error = diff
+            .check(&expected, MissingColumnPolicy::Fail)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "The file is missing columns: bed_cnt");
+    }
+
+    #[test]
+    fn csv_header_is_read() {
+        let header = csv_header("prvdr_num,fac_name\n220071,GENERAL\n".as_bytes()).unwrap();
+        assert_eq!(header, ["prvdr_num", "fac_name"]);
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Row {
+        #[serde(rename = "prvdr_num")]
+        ccn: String,
+        fac_name: Option<String>,
+    }
+
+    #[test]
+    fn columns_are_the_serde_names() {
+        let columns = columns_of::<Row>(&["prvdr_num"]);
+        let columns: Vec<(&str, bool)> = columns.iter().map(|c| (c.name, c.required)).collect();
+        assert_eq!(columns, [("prvdr_num", true), ("fac_name", false)]);
+    }
+}
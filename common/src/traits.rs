@@ -1,6 +1,13 @@
 use anyhow::Result;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::fmt;
 use std::path::Path;
+use std::time::Duration;
+
+use crate::http::HttpClient;
+use crate::schema::{ExpectedColumn, MissingColumnPolicy};
+use crate::validation::RejectedRow;
 
 pub enum FileHash {
     Sha256(String),
@@ -12,6 +19,99 @@ pub enum FileHash {
 pub struct CmsMetadata {
     pub file: Box<Path>,
     pub file_hash: FileHash,
+    pub validators: HttpValidators,
+}
+
+/// HTTP cache validators from the response a file was downloaded with, kept so the next run
+/// can ask the server whether the file has changed instead of downloading it again.
+#[derive(Debug, Clone, Default)]
+pub struct HttpValidators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_length: Option<i64>,
+}
+
+impl HttpValidators {
+    /// Whether there are none, as for a file that was copied or already on disk rather than
+    /// downloaded. Empty validators never replace stored ones.
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none() && self.content_length.is_none()
+    }
+}
+
+/// The outcome of `CmsDataLoader::get_metadata`.
+pub enum FetchOutcome {
+    /// The server answered 304 Not Modified, so nothing was downloaded.
+    NotModified,
+    Fetched(CmsMetadata),
+}
+
+/// Where a loader's data comes from.
+pub enum DatasetSource {
+    /// A fixed download URL.
+    Url(String),
+    /// A dataset in the CMS DCAT catalog, resolved to its latest distribution of `media_type`
+    /// each time the engine runs.
+    Catalog {
+        dataset: CatalogDataset,
+        media_type: String,
+    },
+}
+
+/// How a dataset is looked up in the catalog.
+pub enum CatalogDataset {
+    Identifier(String),
+    Title(String),
+}
+
+impl fmt::Display for CatalogDataset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CatalogDataset::Identifier(id) => write!(f, "with identifier '{}'", id),
+            CatalogDataset::Title(title) => write!(f, "titled '{}'", title),
+        }
+    }
+}
+
+impl fmt::Display for DatasetSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatasetSource::Url(url) => write!(f, "{}", url),
+            DatasetSource::Catalog {
+                dataset,
+                media_type,
+            } => write!(f, "catalog dataset {} ({})", dataset, media_type),
+        }
+    }
+}
+
+/// The concrete download URL for a run, and when the catalog says it was last modified.
+#[derive(Debug, Clone)]
+pub struct ResolvedSource {
+    pub url: String,
+    pub modified: Option<DateTime<Utc>>,
+}
+
+/// Row counts reported by `CmsDataLoader::load`, recorded in the run history.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadCounts {
+    /// Records read from the file, including rejected ones.
+    pub rows_parsed: u64,
+    /// Rows added to the target tables.
+    pub rows_inserted: u64,
+    /// Existing rows overwritten with the file's values.
+    pub rows_updated: u64,
+    /// Records left out because they failed to parse or validate.
+    pub rows_rejected: u64,
+}
+
+/// What the engine lends a loader for a run.
+pub struct LoaderContext<'a> {
+    /// Where to keep downloaded files.
+    pub data_dir: &'a Path,
+    /// The engine's HTTP client. Downloads go through it so they share its proxy, timeouts
+    /// and retry policy.
+    pub http: &'a HttpClient,
 }
 
 pub struct CmsDataResult {
@@ -24,22 +124,70 @@ pub trait CmsDataLoader: Send + Sync {
     /// A unique key to identify this dataset (e.g., "pos_iqies").
     fn key(&self) -> &str;
 
-    /// The source URL for the dataset.
-    fn url(&self) -> &str;
+    /// Where to download the dataset from.
+    fn source(&self) -> DatasetSource;
 
     /// The version of the plugin
     /// Each time the *logic* of the plugin changes, this version should be incremented.
     /// This forces an update to the db, even if the file hash is the same.
     fn version(&self) -> usize;
 
-    async fn get_metadata(&self, data_dir: &Path) -> Result<CmsMetadata>;
+    /// The keys of the loaders whose data this one needs loaded first, such as reference
+    /// tables it joins against. The engine runs this loader only after they succeed.
+    fn dependencies(&self) -> &[&str] {
+        &[]
+    }
+
+    /// How often `loader daemon` checks the dataset for a new release. A check that finds the
+    /// file unchanged is cheap: usually a `304 Not Modified`, at worst a download and hash.
+    fn refresh_interval(&self) -> Duration {
+        Duration::from_secs(24 * 60 * 60)
+    }
+
+    /// The columns `load` reads, which the engine checks the file's header against before
+    /// loading it. Empty, the default, skips the check.
+    fn expected_columns(&self) -> Vec<ExpectedColumn> {
+        Vec::new()
+    }
+
+    /// Whether a file missing a column that is not required still loads, with a warning.
+    fn missing_column_policy(&self) -> MissingColumnPolicy {
+        MissingColumnPolicy::Warn
+    }
+
+    /// The header row of a downloaded file. The default reads it as a CSV.
+    fn header(&self, file: &Path) -> Result<Vec<String>> {
+        crate::schema::csv_header(std::fs::File::open(file)?)
+    }
+
+    /// Downloads the dataset from the resolved `source` into the context's `data_dir` and
+    /// hashes it. `cached` holds the validators of the last successful load from the same URL,
+    /// if any, for a conditional request.
+    async fn get_metadata(
+        &self,
+        context: &LoaderContext<'_>,
+        source: &ResolvedSource,
+        cached: Option<&HttpValidators>,
+    ) -> Result<FetchOutcome>;
 
     /// Orchestrates the loading process: download, extract, and parse.
     ///
     /// # Arguments
     /// * `file` - The opened file containing the dataset.
-    /// * `pool` - The database connection pool.
-    async fn load(&self, file: &Box<Path>, pool: &sqlx::PgPool) -> Result<()>;
+    /// * `conn` - A connection inside the transaction the engine opened for this load. The
+    ///   engine commits it together with the `loader_runs` update, so a loader must not commit
+    ///   on its own.
+    /// * `rejected` - Where to report records that fail to parse or validate, instead of
+    ///   failing the load. The engine stores them in `rejected_rows`, and fails the run if
+    ///   there are too many.
+    ///
+    /// Returns how many rows were parsed, inserted, updated and rejected.
+    async fn load(
+        &self,
+        file: &Path,
+        conn: &mut sqlx::PgConnection,
+        rejected: &mut Vec<RejectedRow>,
+    ) -> Result<LoadCounts>;
 
     async fn cleanup(&self, metadata: &CmsMetadata) -> Result<()> {
         std::fs::remove_file(&metadata.file)?;
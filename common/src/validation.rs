@@ -0,0 +1,526 @@
+//! Row-level validation for loaded files.
+//!
+//! A row that cannot be parsed, or breaks one of the rules below, is not loaded. It is
+//! reported as a `RejectedRow` instead, which the engine stores in `rejected_rows` so the
+//! rest of the file can still load. Too many rejections fail the run (see
+//! `RunOptions::max_rejected_rate`).
+
+use chrono::NaiveDate;
+use csv::{ByteRecord, StringRecord};
+use serde_json::{Map, Value};
+use std::fmt;
+
+use crate::model::{
+    Ccn, CountyGazetteerRow, HospitalEnrollment, ProviderOfServiceRow, ZctaGazetteerRow,
+};
+
+/// USPS state and territory codes, including the freely associated states and the armed
+/// forces codes, which show up in provider addresses.
+const STATE_CODES: &[&str] = &[
+    "AL", "AK", "AZ", "AR", "CA", "CO", "CT", "DE", "DC", "FL", "GA", "HI", "ID", "IL", "IN", "IA",
+    "KS", "KY", "LA", "ME", "MD", "MA", "MI", "MN", "MS", "MO", "MT", "NE", "NV", "NH", "NJ", "NM",
+    "NY", "NC", "ND", "OH", "OK", "OR", "PA", "RI", "SC", "SD", "TN", "TX", "UT", "VT", "VA", "WA",
+    "WV", "WI", "WY", "AS", "GU", "MP", "PR", "VI", "FM", "MH", "PW", "AA", "AE", "AP",
+];
+
+/// A rule a row breaks.
+#[derive(Debug, Clone)]
+pub struct Violation {
+    /// The column, as named in the file.
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Rows that can check themselves before they are loaded.
+pub trait Validate {
+    /// Every rule the row breaks; empty if it can be loaded.
+    fn validate(&self) -> Vec<Violation>;
+}
+
+/// A record left out of a load, with enough context to find and fix it.
+#[derive(Debug, Clone)]
+pub struct RejectedRow {
+    /// The line of the file the record starts on, counting the header as line 1.
+    pub line: i64,
+    pub reason: String,
+    /// The record as read, before parsing: an object of column name to raw value.
+    pub record: Value,
+}
+
+impl RejectedRow {
+    pub fn new(headers: &StringRecord, record: &StringRecord, reason: impl Into<String>) -> Self {
+        let mut raw = Map::new();
+        for (i, value) in record.iter().enumerate() {
+            let name = headers
+                .get(i)
+                .map_or_else(|| format!("column_{}", i + 1), str::to_string);
+            raw.insert(name, Value::String(value.to_string()));
+        }
+        Self {
+            // The csv crate's line numbers lag by one in files with CRLF line endings, as CMS
+            // publishes them, so count records instead, assuming one line each.
+            line: record.position().map_or(0, |p| p.record() + 1) as i64,
+            reason: reason.into(),
+            record: Value::Object(raw),
+        }
+    }
+
+    /// Rejects a record for the rules it breaks.
+    pub fn from_violations(
+        headers: &StringRecord,
+        record: &StringRecord,
+        violations: &[Violation],
+    ) -> Self {
+        let reason = violations
+            .iter()
+            .map(Violation::to_string)
+            .collect::<Vec<_>>()
+            .join("; ");
+        Self::new(headers, record, reason)
+    }
+}
+
+/// Parses a record and checks it, or explains why it can't be loaded.
+pub fn parse_record<T>(headers: &StringRecord, record: &StringRecord) -> Result<T, RejectedRow>
+where
+    T: serde::de::DeserializeOwned + Validate,
+{
+    let row: T = record
+        .deserialize(Some(headers))
+        .map_err(|e| RejectedRow::new(headers, record, parse_error(headers, &e)))?;
+    let violations = row.validate();
+    if !violations.is_empty() {
+        return Err(RejectedRow::from_violations(headers, record, &violations));
+    }
+    Ok(row)
+}
+
+/// Parses a record read as bytes, from a reader built with `flexible(true)`. A record the
+/// reader would otherwise fail on, one with more or fewer fields than the header or with a
+/// field that is not UTF-8, is rejected like any other bad row instead of ending the load.
+pub fn parse_byte_record<T>(headers: &StringRecord, record: &ByteRecord) -> Result<T, RejectedRow>
+where
+    T: serde::de::DeserializeOwned + Validate,
+{
+    let record = match StringRecord::from_byte_record(record.clone()) {
+        Ok(record) => record,
+        Err(e) => {
+            let field = e.utf8_error().field();
+            let column = headers
+                .get(field)
+                .map_or_else(|| format!("column_{}", field + 1), str::to_string);
+            let mut lossy = StringRecord::from_byte_record_lossy(record.clone());
+            lossy.set_position(record.position().cloned());
+            return Err(RejectedRow::new(
+                headers,
+                &lossy,
+                format!("{}: not valid UTF-8", column),
+            ));
+        }
+    };
+    if record.len() != headers.len() {
+        return Err(RejectedRow::new(
+            headers,
+            &record,
+            format!(
+                "the record has {} fields, but the header has {}",
+                record.len(),
+                headers.len()
+            ),
+        ));
+    }
+    parse_record(headers, &record)
+}
+
+/// A parse error naming the column rather than the position, which `RejectedRow::line`
+/// already records.
+fn parse_error(headers: &StringRecord, error: &csv::Error) -> String {
+    match error.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => {
+            match err.field().and_then(|i| headers.get(i as usize)) {
+                Some(column) => format!("{}: {}", column, err.kind()),
+                None => err.kind().to_string(),
+            }
+        }
+        _ => error.to_string(),
+    }
+}
+
+/// A well-formed CMS Certification Number (see `Ccn`), returned for further checks.
+pub fn ccn(field: &'static str, value: &str, violations: &mut Vec<Violation>) -> Option<Ccn> {
+    match value.parse() {
+        Ok(ccn) => Some(ccn),
+        Err(e) => {
+            violations.push(Violation {
+                field,
+                message: format!("{}", e),
+            });
+            None
+        }
+    }
+}
+
+/// The state a CCN was issued in must be the provider's state, when both are known.
+pub fn ccn_matches_state(
+    field: &'static str,
+    ccn: Option<&Ccn>,
+    state_field: &'static str,
+    state: Option<&str>,
+    violations: &mut Vec<Violation>,
+) {
+    if let Some(ccn) = ccn
+        && let Some(issued_in) = ccn.state_code()
+        && let Some(state) = state
+        && issued_in != state
+    {
+        violations.push(Violation {
+            field,
+            message: format!(
+                "{} was issued in {}, but {} is {}",
+                ccn, issued_in, state_field, state
+            ),
+        });
+    }
+}
+
+pub fn state_code(field: &'static str, value: Option<&str>, violations: &mut Vec<Violation>) {
+    if let Some(value) = value
+        && !STATE_CODES.contains(&value)
+    {
+        violations.push(Violation {
+            field,
+            message: format!("'{}' is not a state code", value),
+        });
+    }
+}
+
+pub fn non_negative(field: &'static str, value: Option<i32>, violations: &mut Vec<Violation>) {
+    if let Some(value) = value
+        && value < 0
+    {
+        violations.push(Violation {
+            field,
+            message: format!("{} is negative", value),
+        });
+    }
+}
+
+/// A code of exactly `len` digits, such as a ZCTA or a FIPS code.
+pub fn digits(field: &'static str, value: &str, len: usize, violations: &mut Vec<Violation>) {
+    if value.len() != len || !value.bytes().all(|b| b.is_ascii_digit()) {
+        violations.push(Violation {
+            field,
+            message: format!("'{}' is not a {}-digit code", value, len),
+        });
+    }
+}
+
+/// A latitude and longitude in degrees, within their ranges.
+pub fn coordinates(
+    latitude_field: &'static str,
+    latitude: f64,
+    longitude_field: &'static str,
+    longitude: f64,
+    violations: &mut Vec<Violation>,
+) {
+    if !(-90.0..=90.0).contains(&latitude) {
+        violations.push(Violation {
+            field: latitude_field,
+            message: format!("{} is not a latitude", latitude),
+        });
+    }
+    if !(-180.0..=180.0).contains(&longitude) {
+        violations.push(Violation {
+            field: longitude_field,
+            message: format!("{} is not a longitude", longitude),
+        });
+    }
+}
+
+/// `date` must not be before `earlier`, when both are known.
+pub fn not_before(
+    field: &'static str,
+    date: Option<NaiveDate>,
+    earlier_field: &'static str,
+    earlier: Option<NaiveDate>,
+    violations: &mut Vec<Violation>,
+) {
+    if let (Some(date), Some(earlier)) = (date, earlier)
+        && date < earlier
+    {
+        violations.push(Violation {
+            field,
+            message: format!("{} is before {} ({})", date, earlier_field, earlier),
+        });
+    }
+}
+
+impl Validate for ProviderOfServiceRow {
+    fn validate(&self) -> Vec<Violation> {
+        let mut v = Vec::new();
+        let number = ccn("prvdr_num", &self.cms_certification_number, &mut v);
+        state_code("state_cd", self.state_code.as_deref(), &mut v);
+        ccn_matches_state(
+            "prvdr_num",
+            number.as_ref(),
+            "state_cd",
+            self.state_code.as_deref(),
+            &mut v,
+        );
+        for (field, count) in [
+            ("bed_cnt", self.bed_count),
+            ("crtfd_bed_cnt", self.certified_bed_count),
+            ("hospc_bed_cnt", self.hospice_bed_count),
+            ("aids_bed_cnt", self.aids_bed_count),
+            ("alzhmr_bed_cnt", self.alzheimer_bed_count),
+            ("dlys_bed_cnt", self.dialysis_bed_count),
+            ("dsbl_chldrn_bed_cnt", self.disabled_children_bed_count),
+            ("head_trma_bed_cnt", self.head_trauma_bed_count),
+            ("hntgtn_dease_bed_cnt", self.huntington_disease_bed_count),
+            (
+                "mdcr_mdcd_snf_bed_cnt",
+                self.medicare_medicaid_snf_bed_count,
+            ),
+            ("mdcr_snf_bed_cnt", self.medicare_snf_bed_count),
+            ("rehab_bed_cnt", self.rehab_bed_count),
+            ("vntltr_bed_cnt", self.ventilator_bed_count),
+        ] {
+            non_negative(field, count, &mut v);
+        }
+        not_before(
+            "crtfctn_dt",
+            self.certification_date,
+            "orgnl_prtcptn_dt",
+            self.original_participation_date,
+            &mut v,
+        );
+        v
+    }
+}
+
+impl Validate for HospitalEnrollment {
+    fn validate(&self) -> Vec<Violation> {
+        let mut v = Vec::new();
+        if let Some(number) = &self.cms_certification_number {
+            ccn("CCN", number, &mut v);
+        }
+        state_code("ENROLLMENT STATE", self.enrollment_state.as_deref(), &mut v);
+        v
+    }
+}
+
+impl Validate for ZctaGazetteerRow {
+    fn validate(&self) -> Vec<Violation> {
+        let mut v = Vec::new();
+        digits("GEOID", &self.zcta, 5, &mut v);
+        coordinates(
+            "INTPTLAT",
+            self.latitude,
+            "INTPTLONG",
+            self.longitude,
+            &mut v,
+        );
+        v
+    }
+}
+
+impl Validate for CountyGazetteerRow {
+    fn validate(&self) -> Vec<Violation> {
+        let mut v = Vec::new();
+        digits("GEOID", &self.geoid, 5, &mut v);
+        coordinates(
+            "INTPTLAT",
+            self.latitude,
+            "INTPTLONG",
+            self.longitude,
+            &mut v,
+        );
+        v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a POS row with only the given columns.
+    fn pos(columns: &[(&str, &str)]) -> Result<ProviderOfServiceRow, RejectedRow> {
+        let headers = StringRecord::from(columns.iter().map(|(h, _)| *h).collect::<Vec<_>>());
+        let record = StringRecord::from(columns.iter().map(|(_, v)| *v).collect::<Vec<_>>());
+        parse_record(&headers, &record)
+    }
+
+    fn reason(columns: &[(&str, &str)]) -> String {
+        pos(columns).expect_err("row is rejected").reason
+    }
+
+    #[test]
+    fn valid_row_parses() {
+        let row = pos(&[
+            ("prvdr_num", "220071"),
+            ("state_cd", "MA"),
+            ("bed_cnt", "120"),
+            ("orgnl_prtcptn_dt", "19660701"),
+            ("crtfctn_dt", "20200115"),
+        ])
+        .expect("row is valid");
+        assert_eq!(row.cms_certification_number, "220071");
+        assert_eq!(row.bed_count, Some(120));
+    }
+
+    #[test]
+    fn unparseable_value_is_rejected_with_the_raw_record() {
+        let rejected = pos(&[("prvdr_num", "220071"), ("bed_cnt", "many")]).unwrap_err();
+        assert_eq!(rejected.reason, "'many' is not a valid number");
+        assert_eq!(rejected.record["bed_cnt"], "many");
+        assert_eq!(rejected.record["prvdr_num"], "220071");
+    }
+
+    #[test]
+    fn parse_error_names_its_column() {
+        let headers = StringRecord::from(vec!["GEOID", "INTPTLAT", "INTPTLONG"]);
+        let record = StringRecord::from(vec!["02115", "north", "-71.1"]);
+        let rejected = parse_record::<ZctaGazetteerRow>(&headers, &record).unwrap_err();
+        assert!(
+            rejected.reason.starts_with("INTPTLAT: "),
+            "{}",
+            rejected.reason
+        );
+    }
+
+    #[test]
+    fn every_violation_is_reported() {
+        let reason = reason(&[
+            ("prvdr_num", "22007"),
+            ("state_cd", "ZZ"),
+            ("bed_cnt", "-1"),
+        ]);
+        assert!(reason.contains("prvdr_num: '22007'"), "{}", reason);
+        assert!(
+            reason.contains("state_cd: 'ZZ' is not a state code"),
+            "{}",
+            reason
+        );
+        assert!(reason.contains("bed_cnt: -1 is negative"), "{}", reason);
+        assert_eq!(reason.matches("; ").count(), 2, "{}", reason);
+    }
+
+    #[test]
+    fn ccn_must_be_well_formed() {
+        for number in ["2200711", "22-071", "AB0071", ""] {
+            let reason = reason(&[("prvdr_num", number)]);
+            assert!(reason.starts_with("prvdr_num: "), "{}", reason);
+        }
+        assert!(pos(&[("prvdr_num", "22S071")]).is_ok());
+    }
+
+    #[test]
+    fn ccn_must_be_issued_in_the_provider_state() {
+        let reason = reason(&[("prvdr_num", "220071"), ("state_cd", "NY")]);
+        assert_eq!(
+            reason,
+            "prvdr_num: 220071 was issued in MA, but state_cd is NY"
+        );
+        assert!(pos(&[("prvdr_num", "220071"), ("state_cd", "MA")]).is_ok());
+    }
+
+    #[test]
+    fn state_code_must_be_known() {
+        assert!(reason(&[("prvdr_num", "220071"), ("state_cd", "ma")]).contains("'ma'"));
+        for state in ["PR", "GU", "AE"] {
+            let mut v = Vec::new();
+            state_code("state_cd", Some(state), &mut v);
+            assert!(v.is_empty(), "{} is rejected", state);
+        }
+        let mut v = Vec::new();
+        state_code("state_cd", None, &mut v);
+        assert!(v.is_empty());
+    }
+
+    #[test]
+    fn bed_counts_must_not_be_negative() {
+        for column in ["bed_cnt", "crtfd_bed_cnt", "vntltr_bed_cnt"] {
+            let reason = reason(&[("prvdr_num", "220071"), (column, "-5")]);
+            assert_eq!(reason, format!("{}: -5 is negative", column));
+        }
+        assert!(pos(&[("prvdr_num", "220071"), ("bed_cnt", "0")]).is_ok());
+    }
+
+    #[test]
+    fn certification_must_not_precede_participation() {
+        let reason = reason(&[
+            ("prvdr_num", "220071"),
+            ("orgnl_prtcptn_dt", "20200115"),
+            ("crtfctn_dt", "20200114"),
+        ]);
+        assert_eq!(
+            reason,
+            "crtfctn_dt: 2020-01-14 is before orgnl_prtcptn_dt (2020-01-15)"
+        );
+        assert!(
+            pos(&[
+                ("prvdr_num", "220071"),
+                ("orgnl_prtcptn_dt", "20200115"),
+                ("crtfctn_dt", "20200115"),
+            ])
+            .is_ok()
+        );
+        assert!(pos(&[("prvdr_num", "220071"), ("crtfctn_dt", "20200114")]).is_ok());
+    }
+
+    #[test]
+    fn rejected_row_counts_the_header_as_line_one() {
+        let data = "prvdr_num,bed_cnt\n220071,1\n220072,-1\n";
+        let mut rdr = csv::Reader::from_reader(data.as_bytes());
+        let headers = rdr.headers().unwrap().clone();
+        let rejected: Vec<_> = rdr
+            .records()
+            .map(|record| parse_record::<ProviderOfServiceRow>(&headers, &record.unwrap()))
+            .filter_map(Result::err)
+            .collect();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].line, 3);
+    }
+
+    /// Parses every record of `data` as bytes, as the loaders read their files.
+    fn read_bytes(data: &[u8]) -> Vec<Result<ProviderOfServiceRow, RejectedRow>> {
+        let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(data);
+        let headers = rdr.headers().unwrap().clone();
+        rdr.byte_records()
+            .map(|record| parse_byte_record(&headers, &record.unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn record_with_the_wrong_field_count_is_rejected() {
+        let rows = read_bytes(b"prvdr_num,bed_cnt\n220071,1\n220072\n220073,1,extra\n220074,2\n");
+        assert!(rows[0].is_ok());
+        assert!(rows[3].is_ok());
+
+        let short = rows[1].as_ref().unwrap_err();
+        assert_eq!(short.line, 3);
+        assert_eq!(
+            short.reason,
+            "the record has 1 fields, but the header has 2"
+        );
+        let long = rows[2].as_ref().unwrap_err();
+        assert_eq!(long.line, 4);
+        assert_eq!(long.record["column_3"], "extra");
+    }
+
+    #[test]
+    fn record_that_is_not_utf8_is_rejected() {
+        let rows = read_bytes(b"prvdr_num,fac_name\n220071,CAF\xc9\n220072,CAFE\n");
+        let rejected = rows[0].as_ref().unwrap_err();
+        assert_eq!(rejected.line, 2);
+        assert_eq!(rejected.reason, "fac_name: not valid UTF-8");
+        assert_eq!(rejected.record["fac_name"], "CAF\u{fffd}");
+        assert!(rows[1].is_ok());
+    }
+}
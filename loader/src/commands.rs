@@ -0,0 +1,123 @@
+use anyhow::Result;
+use common::engine::LoaderEngine;
+use sqlx::PgPool;
+
+/// Prints every registered loader with its version, source, dependencies and the URL it
+/// currently resolves to.
+pub async fn list(engine: &LoaderEngine) {
+    let loaders: Vec<_> = engine
+        .loaders()
+        .into_iter()
+        .map(|loader| {
+            (
+                loader.key(),
+                loader.version(),
+                loader.source(),
+                loader.dependencies(),
+            )
+        })
+        .collect();
+
+    for (key, version, source, dependencies) in loaders {
+        println!("{} (v{})", key, version);
+        println!("    source: {}", source);
+        if !dependencies.is_empty() {
+            println!("    needs:  {}", dependencies.join(", "));
+        }
+        // An unreachable catalog shouldn't hide the other loaders.
+        match engine.resolve(source).await {
+            Ok(resolved) => println!("    url:    {}", resolved.url),
+            Err(e) => println!("    url:    could not resolve: {:#}", e),
+        }
+    }
+}
+
+/// Compares each registered loader with its `loader_runs` row, then prints the most recent runs.
+pub async fn status(
+    engine: &LoaderEngine,
+    pool: &PgPool,
+    loader_key: Option<&str>,
+    limit: i64,
+) -> Result<()> {
+    println!(
+        "{:<22} {:>6} {:>6} {:<20} STATE",
+        "LOADER", "PLUGIN", "LOADED", "LAST LOAD"
+    );
+    for loader in engine.loaders() {
+        let key = loader.key();
+        if loader_key.is_some_and(|k| k != key) {
+            continue;
+        }
+
+        let plugin_version = loader.version() as i32;
+        let (loaded, last_load, state) = match engine.status(key) {
+            None => ("-".to_string(), "-".to_string(), "never loaded".to_string()),
+            Some(status) => (
+                status.version.to_string(),
+                status.last_run.format("%Y-%m-%d %H:%M:%S").to_string(),
+                if plugin_version > status.version {
+                    "plugin upgraded, next run reloads".to_string()
+                } else if plugin_version < status.version {
+                    "loaded by a newer plugin".to_string()
+                } else {
+                    format!("loaded, file {}", short_hash(&status.file_hash))
+                },
+            ),
+        };
+        println!(
+            "{:<22} {:>6} {:>6} {:<20} {}",
+            key, plugin_version, loaded, last_load, state
+        );
+    }
+
+    println!();
+    print_runs(pool, loader_key, limit).await
+}
+
+fn short_hash(hash: &str) -> &str {
+    &hash[..hash.len().min(12)]
+}
+
+/// Prints the run history as a table, most recent run first.
+async fn print_runs(pool: &PgPool, loader_key: Option<&str>, limit: i64) -> Result<()> {
+    let runs = common::queries::list_loader_runs(pool, loader_key, limit).await?;
+    if runs.is_empty() {
+        println!("No loader runs recorded yet.");
+        return Ok(());
+    }
+
+    let count = |n: Option<i64>| n.map_or("-".to_string(), |n| n.to_string());
+    println!(
+        "{:<6} {:<22} {:<4} {:<10} {:<20} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "ID",
+        "LOADER",
+        "VER",
+        "STATUS",
+        "STARTED",
+        "SECONDS",
+        "PARSED",
+        "INSERTED",
+        "UPDATED",
+        "REJECTED"
+    );
+    for run in &runs {
+        println!(
+            "{:<6} {:<22} {:<4} {:<10} {:<20} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            run.id,
+            run.loader_key,
+            run.plugin_version,
+            run.status,
+            run.started_at.format("%Y-%m-%d %H:%M:%S"),
+            run.duration_ms
+                .map_or("-".to_string(), |ms| format!("{:.1}", ms as f64 / 1000.0)),
+            count(run.rows_parsed),
+            count(run.rows_inserted),
+            count(run.rows_updated),
+            count(run.rows_rejected),
+        );
+        if let Some(error) = &run.error {
+            println!("       error: {}", error);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,212 @@
+//! `loader daemon`: keeps running, checking each dataset on its own schedule.
+
+use anyhow::Result;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use common::engine::{LoaderEngine, RunOptions};
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+/// When each loader was last checked, and when it is next due.
+#[derive(Debug, Clone, Serialize)]
+struct Schedule {
+    loader_key: String,
+    #[serde(skip)]
+    interval: Duration,
+    last_check: Option<DateTime<Utc>>,
+    next_check: DateTime<Utc>,
+}
+
+/// What `/health` reports.
+#[derive(Debug, Serialize)]
+struct Health {
+    /// `ok`, or `stopping` once a shutdown signal arrived.
+    status: &'static str,
+    started_at: DateTime<Utc>,
+    /// The error of the most recent pass, if it failed. Cleared by the next successful pass.
+    last_error: Option<String>,
+    loaders: Vec<Schedule>,
+}
+
+type SharedHealth = Arc<Mutex<Health>>;
+
+/// Runs due loaders until SIGTERM or Ctrl-C. `options` applies to every pass; its `only` is
+/// replaced by the loaders that are due.
+pub async fn run(
+    engine: &mut LoaderEngine,
+    pool: &sqlx::PgPool,
+    data_dir: &Path,
+    options: RunOptions,
+    health_address: SocketAddr,
+) -> Result<()> {
+    let mut schedule = initial_schedule(engine, pool).await?;
+    let health = Arc::new(Mutex::new(Health {
+        status: "ok",
+        started_at: Utc::now(),
+        last_error: None,
+        loaders: schedule.clone(),
+    }));
+
+    let listener = tokio::net::TcpListener::bind(health_address).await?;
+    info!("Health endpoint listening on {}", listener.local_addr()?);
+    let app = Router::new()
+        .route("/health", get(health_check))
+        .with_state(health.clone());
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("Health endpoint stopped: {}", e);
+        }
+    });
+
+    // On a signal, stop starting loaders, but let the ones running finish their transaction.
+    let (stop_sender, mut stop_receiver) = watch::channel(false);
+    let stop = engine.stop_handle();
+    {
+        let health = health.clone();
+        tokio::spawn(async move {
+            shutdown_signal().await;
+            info!("Shutdown requested; stopping after the running loaders finish.");
+            stop.store(true, Ordering::Relaxed);
+            health.lock().expect("Health lock poisoned").status = "stopping";
+            let _ = stop_sender.send(true);
+        });
+    }
+
+    loop {
+        let now = Utc::now();
+        let due: Vec<String> = schedule
+            .iter()
+            .filter(|s| s.next_check <= now)
+            .map(|s| s.loader_key.clone())
+            .collect();
+
+        if !due.is_empty() {
+            info!("Checking {} due loaders: {}", due.len(), due.join(", "));
+            // New releases may have been published, and another process may have loaded data,
+            // since the last pass.
+            engine.clear_catalog();
+            if let Err(e) = engine.scan().await {
+                warn!("Could not refresh loader states: {:#}", e);
+            }
+
+            let pass = RunOptions {
+                only: due.clone(),
+                ..options.clone()
+            };
+            let result = engine.run(data_dir, &pass).await;
+            if let Err(e) = &result {
+                error!("Loader pass failed: {:#}", e);
+            }
+
+            // A failed loader is retried at its next interval, like an unchanged one.
+            let checked = Utc::now();
+            for entry in schedule.iter_mut().filter(|s| due.contains(&s.loader_key)) {
+                entry.last_check = Some(checked);
+                entry.next_check = checked + entry.interval;
+            }
+
+            let mut health = health.lock().expect("Health lock poisoned");
+            health.last_error = result.err().map(|e| format!("{:#}", e));
+            health.loaders = schedule.clone();
+        }
+
+        if *stop_receiver.borrow() {
+            break;
+        }
+
+        let next = schedule
+            .iter()
+            .map(|s| s.next_check)
+            .min()
+            .unwrap_or(now + chrono::Duration::hours(1));
+        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+        info!("Next check at {}.", next.format("%Y-%m-%d %H:%M:%S UTC"));
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = stop_receiver.changed() => break,
+        }
+    }
+
+    info!("Loader daemon stopped.");
+    Ok(())
+}
+
+/// Schedules each loader from its last recorded run, so a restarted daemon doesn't check
+/// every dataset again at once. Loaders that never ran are due immediately.
+async fn initial_schedule(engine: &LoaderEngine, pool: &sqlx::PgPool) -> Result<Vec<Schedule>> {
+    let now = Utc::now();
+    let mut schedule = Vec::new();
+    for loader in engine.loaders() {
+        let interval = loader.refresh_interval();
+        let last_check = common::queries::list_loader_runs(pool, Some(loader.key()), 1)
+            .await?
+            .into_iter()
+            .next()
+            .map(|run| run.started_at);
+        let next_check = last_check.map_or(now, |last| last + interval);
+        info!(
+            "Loader '{}' checks every {:?}; next check at {}.",
+            loader.key(),
+            interval,
+            next_check.format("%Y-%m-%d %H:%M:%S UTC")
+        );
+        schedule.push(Schedule {
+            loader_key: loader.key().to_string(),
+            interval,
+            last_check,
+            next_check,
+        });
+    }
+    Ok(schedule)
+}
+
+async fn health_check(
+    axum::extract::State(health): axum::extract::State<SharedHealth>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let health = health.lock().expect("Health lock poisoned");
+    let code = match health.status {
+        "ok" => StatusCode::OK,
+        _ => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (
+        code,
+        Json(serde_json::to_value(&*health).expect("Health serializes")),
+    )
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
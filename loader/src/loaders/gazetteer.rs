@@ -0,0 +1,376 @@
+//! Loaders for the Census Bureau's national gazetteer files, which give the internal point
+//! of every ZIP Code Tabulation Area and county. Addresses are geocoded from them offline:
+//! each load places every address again, so new centroids apply to existing addresses too.
+//!
+//! The files are tab-delimited text, published zipped. Either can be given as a local file
+//! instead of a URL, for hosts that cannot reach census.gov.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use common::model::{CountyGazetteerRow, ZctaGazetteerRow};
+use common::schema::{ExpectedColumn, MissingColumnPolicy, columns_of};
+use common::traits::{
+    CmsDataLoader, DatasetSource, FetchOutcome, HttpValidators, LoadCounts, LoaderContext,
+    ResolvedSource,
+};
+use common::validation::{RejectedRow, Validate, parse_byte_record};
+use csv::{ReaderBuilder, Trim};
+use serde::de::DeserializeOwned;
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+use tracing::info;
+
+pub const ZCTA_GAZETTEER_URL: &str = "https://www2.census.gov/geo/docs/maps-data/data/gazetteer/2024_Gazetteer/2024_Gaz_zcta_national.zip";
+
+pub const COUNTY_GAZETTEER_URL: &str = "https://www2.census.gov/geo/docs/maps-data/data/gazetteer/2024_Gazetteer/2024_Gaz_counties_national.zip";
+
+/// The gazetteers are published once a year.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Columns without which a row cannot be placed.
+const REQUIRED_COLUMNS: &[&str] = &["GEOID", "INTPTLAT", "INTPTLONG"];
+
+/// Loads the ZCTA gazetteer into `zcta_centroids`.
+pub struct ZctaGazetteerLoader {
+    /// A URL, or the path of a local file.
+    pub source: String,
+}
+
+/// Loads the county gazetteer into `county_centroids`.
+pub struct CountyGazetteerLoader {
+    /// A URL, or the path of a local file.
+    pub source: String,
+}
+
+#[async_trait]
+impl CmsDataLoader for ZctaGazetteerLoader {
+    fn key(&self) -> &str {
+        "census_zcta_gazetteer"
+    }
+
+    fn source(&self) -> DatasetSource {
+        DatasetSource::Url(self.source.clone())
+    }
+
+    fn version(&self) -> usize {
+        1
+    }
+
+    fn refresh_interval(&self) -> Duration {
+        REFRESH_INTERVAL
+    }
+
+    fn expected_columns(&self) -> Vec<ExpectedColumn> {
+        columns_of::<ZctaGazetteerRow>(REQUIRED_COLUMNS)
+    }
+
+    fn missing_column_policy(&self) -> MissingColumnPolicy {
+        MissingColumnPolicy::Fail
+    }
+
+    fn header(&self, file: &Path) -> Result<Vec<String>> {
+        header(file)
+    }
+
+    async fn get_metadata(
+        &self,
+        context: &LoaderContext<'_>,
+        source: &ResolvedSource,
+        cached: Option<&HttpValidators>,
+    ) -> Result<FetchOutcome> {
+        get_metadata(self.key(), context, source, cached).await
+    }
+
+    async fn load(
+        &self,
+        file: &Path,
+        conn: &mut sqlx::PgConnection,
+        rejected: &mut Vec<RejectedRow>,
+    ) -> Result<LoadCounts> {
+        let (rows, centroids) = parse::<ZctaGazetteerRow>(file, rejected).await?;
+        info!(
+            "Replacing ZCTA centroids with {} for '{}'...",
+            centroids.len(),
+            self.key()
+        );
+        common::db::replace_zcta_centroids(conn, &centroids).await?;
+        geocode(conn, self.key()).await?;
+
+        // The table is replaced as a whole, so every row counts as inserted.
+        Ok(LoadCounts {
+            rows_parsed: rows,
+            rows_inserted: centroids.len() as u64,
+            rows_updated: 0,
+            rows_rejected: rows - centroids.len() as u64,
+        })
+    }
+}
+
+#[async_trait]
+impl CmsDataLoader for CountyGazetteerLoader {
+    fn key(&self) -> &str {
+        "census_county_gazetteer"
+    }
+
+    /// Both gazetteers geocode every address when they load. Run concurrently, each would
+    /// place addresses without the other's uncommitted centroids, and the last to commit
+    /// would win.
+    fn dependencies(&self) -> &[&str] {
+        &["census_zcta_gazetteer"]
+    }
+
+    fn source(&self) -> DatasetSource {
+        DatasetSource::Url(self.source.clone())
+    }
+
+    fn version(&self) -> usize {
+        1
+    }
+
+    fn refresh_interval(&self) -> Duration {
+        REFRESH_INTERVAL
+    }
+
+    fn expected_columns(&self) -> Vec<ExpectedColumn> {
+        columns_of::<CountyGazetteerRow>(REQUIRED_COLUMNS)
+    }
+
+    fn missing_column_policy(&self) -> MissingColumnPolicy {
+        MissingColumnPolicy::Fail
+    }
+
+    fn header(&self, file: &Path) -> Result<Vec<String>> {
+        header(file)
+    }
+
+    async fn get_metadata(
+        &self,
+        context: &LoaderContext<'_>,
+        source: &ResolvedSource,
+        cached: Option<&HttpValidators>,
+    ) -> Result<FetchOutcome> {
+        get_metadata(self.key(), context, source, cached).await
+    }
+
+    async fn load(
+        &self,
+        file: &Path,
+        conn: &mut sqlx::PgConnection,
+        rejected: &mut Vec<RejectedRow>,
+    ) -> Result<LoadCounts> {
+        let (rows, centroids) = parse::<CountyGazetteerRow>(file, rejected).await?;
+        info!(
+            "Replacing county centroids with {} for '{}'...",
+            centroids.len(),
+            self.key()
+        );
+        common::db::replace_county_centroids(conn, &centroids).await?;
+        geocode(conn, self.key()).await?;
+
+        // The table is replaced as a whole, so every row counts as inserted.
+        Ok(LoadCounts {
+            rows_parsed: rows,
+            rows_inserted: centroids.len() as u64,
+            rows_updated: 0,
+            rows_rejected: rows - centroids.len() as u64,
+        })
+    }
+}
+
+async fn get_metadata(
+    key: &str,
+    context: &LoaderContext<'_>,
+    source: &ResolvedSource,
+    cached: Option<&HttpValidators>,
+) -> Result<FetchOutcome> {
+    let path = context
+        .data_dir
+        .join(super::file_name(key, &source.url, "txt"));
+    super::download_and_hash(context, &source.url, path, cached).await
+}
+
+/// Opens the gazetteer, either the text file itself or a zip containing it, and hands a
+/// reader over it to `read`.
+fn with_reader<T>(
+    path: &Path,
+    read: impl FnOnce(&mut csv::Reader<&mut dyn Read>) -> Result<T>,
+) -> Result<T> {
+    super::with_file_in_zip(
+        path,
+        |name| name.ends_with(".txt"),
+        |file| {
+            // The last header name is padded with spaces. Malformed records are rejected by
+            // `parse_byte_record` rather than failing the read.
+            let mut rdr = ReaderBuilder::new()
+                .has_headers(true)
+                .flexible(true)
+                .delimiter(b'\t')
+                .trim(Trim::All)
+                .from_reader(file);
+            read(&mut rdr)
+        },
+    )
+}
+
+fn header(file: &Path) -> Result<Vec<String>> {
+    with_reader(file, |rdr| {
+        Ok(rdr.headers()?.iter().map(str::to_string).collect())
+    })
+}
+
+/// Runs `read_rows` on a blocking thread, so parsing a whole gazetteer doesn't hold up the
+/// loaders running alongside.
+async fn parse<T>(file: &Path, rejected: &mut Vec<RejectedRow>) -> Result<(u64, Vec<T>)>
+where
+    T: DeserializeOwned + Validate + Send + 'static,
+{
+    let path = file.to_path_buf();
+    let (rows, kept, file_rejected) = tokio::task::spawn_blocking(move || {
+        let mut rejected = Vec::new();
+        let (rows, kept) = read_rows::<T>(&path, &mut rejected)?;
+        anyhow::Ok((rows, kept, rejected))
+    })
+    .await??;
+    rejected.extend(file_rejected);
+    Ok((rows, kept))
+}
+
+/// Reads every row of the gazetteer, reporting the ones that fail to parse or validate.
+/// Returns how many records were read, with the rows kept.
+fn read_rows<T>(file: &Path, rejected: &mut Vec<RejectedRow>) -> Result<(u64, Vec<T>)>
+where
+    T: DeserializeOwned + Validate,
+{
+    info!("Parsing gazetteer {:?}...", file);
+    with_reader(file, |rdr| {
+        let headers = rdr.headers()?.clone();
+        let mut record = csv::ByteRecord::new();
+        let mut rows = 0;
+        let mut kept = Vec::new();
+        while rdr.read_byte_record(&mut record)? {
+            rows += 1;
+            match parse_byte_record::<T>(&headers, &record) {
+                Ok(row) => kept.push(row),
+                Err(rejection) => rejected.push(rejection),
+            }
+        }
+        Ok((rows, kept))
+    })
+}
+
+/// Places the addresses again with the centroids just loaded.
+async fn geocode(conn: &mut sqlx::PgConnection, key: &str) -> Result<()> {
+    let counts = common::db::geocode_addresses(conn).await?;
+    info!(
+        "Geocoded addresses for '{}': {} changed; {} by ZCTA, {} by county, {} not placed.",
+        key, counts.updated, counts.by_zcta, counts.by_county, counts.missing
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    // As published: tab-delimited, with the last header name padded with spaces.
+    const ZCTA: &str = "GEOID\tALAND\tAWATER\tALAND_SQMI\tAWATER_SQMI\tINTPTLAT\tINTPTLONG                                                                                                               \n\
+        02115\t1824356\t12398\t0.704\t0.005\t42.342577\t-71.092265                    \n\
+        00601\t166847909\t799292\t64.42\t0.309\t18.180555\t-66.749961\n\
+        0211\t1\t1\t0\t0\t42.0\t-71.0\n\
+        99999\t1\t1\t0\t0\t95.0\t-71.0\n\
+        99998\t1\t1\t0\t0\t42.0\n";
+
+    const COUNTIES: &str = "USPS\tGEOID\tANSICODE\tNAME\tALAND\tAWATER\tALAND_SQMI\tAWATER_SQMI\tINTPTLAT\tINTPTLONG         \n\
+        MA\t25025\t00606939\tSuffolk County\t150855462\t160479920\t58.246\t61.962\t42.3387\t-71.0185\n";
+
+    fn write_file(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn padded_last_header_is_trimmed() {
+        let file = write_file(ZCTA);
+        let header = header(file.path()).unwrap();
+        assert_eq!(header.last().unwrap(), "INTPTLONG");
+    }
+
+    #[test]
+    fn zcta_rows_are_read_and_checked() {
+        let file = write_file(ZCTA);
+        let mut rejected = Vec::new();
+        let (rows, kept) = read_rows::<ZctaGazetteerRow>(file.path(), &mut rejected).unwrap();
+        assert_eq!(rows, 5);
+        assert_eq!(kept.len(), 2);
+        assert_eq!(kept[0].zcta, "02115");
+        assert_eq!(kept[0].latitude, 42.342577);
+        assert_eq!(kept[0].longitude, -71.092265);
+        assert_eq!(kept[1].zcta, "00601");
+
+        assert_eq!(rejected.len(), 3);
+        assert_eq!(rejected[0].line, 4);
+        assert_eq!(rejected[0].reason, "GEOID: '0211' is not a 5-digit code");
+        assert_eq!(rejected[1].reason, "INTPTLAT: 95 is not a latitude");
+        assert_eq!(rejected[2].line, 6);
+        assert_eq!(
+            rejected[2].reason,
+            "the record has 6 fields, but the header has 7"
+        );
+    }
+
+    #[test]
+    fn county_rows_are_read() {
+        let file = write_file(COUNTIES);
+        let mut rejected = Vec::new();
+        let (rows, kept) = read_rows::<CountyGazetteerRow>(file.path(), &mut rejected).unwrap();
+        assert_eq!(rows, 1);
+        assert!(rejected.is_empty());
+        assert_eq!(kept[0].geoid, "25025");
+        assert_eq!(kept[0].state_code.as_deref(), Some("MA"));
+        assert_eq!(kept[0].name.as_deref(), Some("Suffolk County"));
+        assert_eq!(kept[0].longitude, -71.0185);
+    }
+
+    #[tokio::test]
+    async fn rows_are_parsed_off_the_runtime() {
+        let file = write_file(ZCTA);
+        let mut rejected = Vec::new();
+        let (rows, kept) = parse::<ZctaGazetteerRow>(file.path(), &mut rejected)
+            .await
+            .unwrap();
+        assert_eq!((rows, kept.len(), rejected.len()), (5, 2, 3));
+    }
+
+    #[test]
+    fn text_file_is_read_from_a_zip() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut zip = zip::ZipWriter::new(file.reopen().unwrap());
+        zip.start_file("2024_Gaz_zcta_national.txt", Default::default())
+            .unwrap();
+        zip.write_all(ZCTA.as_bytes()).unwrap();
+        zip.finish().unwrap();
+
+        let mut rejected = Vec::new();
+        let (rows, kept) = read_rows::<ZctaGazetteerRow>(file.path(), &mut rejected).unwrap();
+        assert_eq!((rows, kept.len()), (5, 2));
+    }
+
+    #[test]
+    fn expected_columns_match_the_published_header() {
+        let file = write_file(COUNTIES);
+        let header = header(file.path()).unwrap();
+        let loader = CountyGazetteerLoader {
+            source: String::new(),
+        };
+        for column in loader.expected_columns() {
+            assert!(
+                header.contains(&column.name.to_string()),
+                "{:?}",
+                column.name
+            );
+        }
+    }
+}
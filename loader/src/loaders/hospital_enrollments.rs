@@ -0,0 +1,119 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use common::model::HospitalEnrollment;
+use common::schema::{ExpectedColumn, MissingColumnPolicy, columns_of};
+use common::traits::{
+    CatalogDataset, CmsDataLoader, DatasetSource, FetchOutcome, HttpValidators, LoadCounts,
+    LoaderContext, ResolvedSource,
+};
+use common::validation::{RejectedRow, parse_byte_record};
+use csv::ReaderBuilder;
+use std::fs::File;
+use std::path::Path;
+use tracing::info;
+
+/// Loads the Hospital Enrollments dataset, which covers hospitals along with
+/// Critical Access Hospitals and Rural Emergency Hospitals.
+pub struct HospitalEnrollmentsLoader;
+
+#[async_trait]
+impl CmsDataLoader for HospitalEnrollmentsLoader {
+    fn key(&self) -> &str {
+        "hospital_enrollments"
+    }
+
+    fn source(&self) -> DatasetSource {
+        DatasetSource::Catalog {
+            dataset: CatalogDataset::Identifier("f6f6505c-e8b0-4d57-b258-e2b94133aaf2".to_string()),
+            media_type: "text/csv".to_string(),
+        }
+    }
+
+    fn version(&self) -> usize {
+        2
+    }
+
+    fn expected_columns(&self) -> Vec<ExpectedColumn> {
+        columns_of::<HospitalEnrollment>(&["ENROLLMENT ID"])
+    }
+
+    /// The CAH and REH flags are derived from the provider type and subgroup columns, so a
+    /// file without one of them would mislabel hospitals rather than merely leave a gap.
+    fn missing_column_policy(&self) -> MissingColumnPolicy {
+        MissingColumnPolicy::Fail
+    }
+
+    async fn get_metadata(
+        &self,
+        context: &LoaderContext<'_>,
+        source: &ResolvedSource,
+        cached: Option<&HttpValidators>,
+    ) -> Result<FetchOutcome> {
+        let csv_path = context.data_dir.join(format!("{}.csv", self.key()));
+        super::download_and_hash(context, &source.url, csv_path, cached).await
+    }
+
+    async fn load(
+        &self,
+        file: &Path,
+        conn: &mut sqlx::PgConnection,
+        rejected: &mut Vec<RejectedRow>,
+    ) -> Result<LoadCounts> {
+        info!("Parsing CSV {:?}...", file);
+        // Parsed on a blocking thread, so the loaders running alongside carry on meanwhile.
+        let path = file.to_path_buf();
+        let (rows, enrollments, file_rejected) =
+            tokio::task::spawn_blocking(move || read_enrollments(&path)).await??;
+        let rows_rejected = file_rejected.len() as u64;
+        rejected.extend(file_rejected);
+
+        let critical_access = enrollments
+            .iter()
+            .filter(|e| e.is_critical_access_hospital())
+            .count();
+        let rural_emergency = enrollments
+            .iter()
+            .filter(|e| e.is_rural_emergency_hospital())
+            .count();
+        info!(
+            "Inserting {} enrollments ({} CAH, {} REH) for '{}'...",
+            enrollments.len(),
+            critical_access,
+            rural_emergency,
+            self.key()
+        );
+        let inserted = common::db::replace_hospital_enrollments(conn, &enrollments).await?;
+
+        // The table is replaced as a whole, so every row kept counts as inserted.
+        Ok(LoadCounts {
+            rows_parsed: rows,
+            rows_inserted: inserted,
+            rows_updated: 0,
+            rows_rejected,
+        })
+    }
+}
+
+/// Reads every enrollment in the file, with the records that fail to parse or validate.
+/// Returns how many records were read, the enrollments kept and the records rejected.
+fn read_enrollments(path: &Path) -> Result<(u64, Vec<HospitalEnrollment>, Vec<RejectedRow>)> {
+    // Malformed records are rejected by `parse_byte_record` rather than failing the read.
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .from_reader(File::open(path)?);
+    let headers = rdr.headers()?.clone();
+
+    let mut enrollments = Vec::new();
+    let mut rejected = Vec::new();
+    let mut record = csv::ByteRecord::new();
+    let mut rows = 0;
+    while rdr.read_byte_record(&mut record)? {
+        rows += 1;
+        match parse_byte_record::<HospitalEnrollment>(&headers, &record) {
+            Ok(enrollment) => enrollments.push(enrollment),
+            Err(rejection) => rejected.push(rejection),
+        }
+    }
+    Ok((rows, enrollments, rejected))
+}
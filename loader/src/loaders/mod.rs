@@ -1 +1,106 @@
+use anyhow::{Context, Result};
+use common::download::{DownloadOutcome, download, sha256_file};
+use common::traits::{CmsMetadata, FetchOutcome, FileHash, HttpValidators, LoaderContext};
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+pub mod gazetteer;
+pub mod hospital_enrollments;
 pub mod pos;
+
+/// The local file name for a loader's download: its key, with the extension of the URL.
+pub fn file_name(key: &str, url: &str, default_extension: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or(default_extension);
+    format!("{}.{}", key, extension.to_ascii_lowercase())
+}
+
+/// The local file a source names instead of a download: a `file://` URL, or a plain path.
+pub fn local_source(url: &str) -> Option<&Path> {
+    match url.strip_prefix("file://") {
+        Some(path) => Some(Path::new(path)),
+        None if !url.contains("://") => Some(Path::new(url)),
+        None => None,
+    }
+}
+
+/// Downloads `url` to `path` with the engine's HTTP client unless the file already exists,
+/// hashing it as it arrives. An existing file is hashed from disk. A local source (see
+/// `local_source`) is copied to `path`, so loading and cleaning up work the same way.
+/// Neither has validators, so the engine keeps those of the last download.
+///
+/// With `cached` validators the request is conditional, and a 304 from the server returns
+/// `FetchOutcome::NotModified` without touching `path`.
+pub async fn download_and_hash(
+    context: &LoaderContext<'_>,
+    url: &str,
+    path: PathBuf,
+    cached: Option<&HttpValidators>,
+) -> Result<FetchOutcome> {
+    let (file_hash, validators) = if let Some(local) = local_source(url) {
+        info!("Copying {:?} to {:?}...", local, path);
+        tokio::fs::copy(local, &path)
+            .await
+            .with_context(|| format!("Could not read {:?}", local))?;
+        (sha256_file(&path).await?, HttpValidators::default())
+    } else if !path.exists() {
+        info!("Downloading {} to {:?}...", url, path);
+        match download(context.http, url, &path, cached).await? {
+            DownloadOutcome::NotModified => return Ok(FetchOutcome::NotModified),
+            DownloadOutcome::Downloaded { validators, sha256 } => (sha256, validators),
+        }
+    } else {
+        info!("Using existing file at {:?}", path);
+        (sha256_file(&path).await?, HttpValidators::default())
+    };
+    info!("File hash (SHA256): {}", file_hash);
+
+    Ok(FetchOutcome::Fetched(CmsMetadata {
+        file: path.into(),
+        file_hash: FileHash::Sha256(file_hash),
+        validators,
+    }))
+}
+
+/// Opens `path` and hands it to `read`, or if it is a zip archive, its first entry whose name
+/// `wanted` accepts.
+pub fn with_file_in_zip<T>(
+    path: &Path,
+    wanted: impl Fn(&str) -> bool,
+    read: impl FnOnce(&mut dyn Read) -> Result<T>,
+) -> Result<T> {
+    let mut file = File::open(path)?;
+
+    let mut magic = [0u8; 4];
+    let is_zip = file.read(&mut magic)? == 4 && magic == *b"PK\x03\x04";
+    // Rewind past the magic bytes
+    file.rewind()?;
+
+    if !is_zip {
+        return read(&mut file);
+    }
+
+    info!("Extracting zip from stream...");
+    let mut archive = zip::ZipArchive::new(file)?;
+
+    let mut entry_name = None;
+    for i in 0..archive.len() {
+        let entry = archive.by_index(i)?;
+        if wanted(entry.name()) {
+            entry_name = Some(entry.name().to_string());
+            break;
+        }
+    }
+    let Some(entry_name) = entry_name else {
+        return Err(anyhow::anyhow!("No suitable file found in zip archive"));
+    };
+
+    info!("Found {}", entry_name);
+    let mut entry = archive.by_name(&entry_name)?;
+    read(&mut entry)
+}
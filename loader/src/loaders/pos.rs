@@ -1,15 +1,30 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
-use common::model::{Address, Provider};
-use common::traits::{CmsDataLoader, CmsMetadata, FileHash};
+use common::copy::BinaryCopyWriter;
+use common::model::ProviderOfServiceRow;
+use common::schema::{ExpectedColumn, columns_of};
+use common::traits::{
+    CatalogDataset, CmsDataLoader, DatasetSource, FetchOutcome, HttpValidators, LoadCounts,
+    LoaderContext, ResolvedSource,
+};
+use common::validation::{RejectedRow, parse_byte_record};
 use csv::ReaderBuilder;
-use sha2::{Digest, Sha256};
-use std::fs::File;
-use std::io::{Cursor, Read, Seek};
+use std::io::Read;
 use std::path::Path;
-use tracing::{error, info};
+use tokio::sync::mpsc;
+use tracing::info;
 
-pub struct ProviderOfServicesLoader;
+/// Rows are sent to Postgres in chunks of about this many bytes.
+const COPY_CHUNK_SIZE: usize = 1 << 20;
+
+/// How many encoded chunks may wait for the database before the parser blocks.
+const COPY_CHUNKS_IN_FLIGHT: usize = 4;
+
+pub struct ProviderOfServicesLoader {
+    /// The largest share of active providers a file may retire. Beyond it, the file is taken
+    /// for a truncated one and the load fails.
+    pub max_retired_rate: f64,
+}
 
 #[async_trait]
 impl CmsDataLoader for ProviderOfServicesLoader {
@@ -17,144 +32,192 @@ impl CmsDataLoader for ProviderOfServicesLoader {
         "pos_iqies"
     }
 
-    fn url(&self) -> &str {
-        "https://data.cms.gov/sites/default/files/dataset_zips/741d954b3c14b2299052175b44e895f4/Provider%20of%20Services%20File%20-%20Internet%20Quality%20Improvement%20and%20Evaluation%20System.zip"
+    fn source(&self) -> DatasetSource {
+        DatasetSource::Catalog {
+            dataset: CatalogDataset::Title(
+                "Provider of Services File - Internet Quality Improvement and Evaluation System"
+                    .to_string(),
+            ),
+            media_type: "text/csv".to_string(),
+        }
     }
 
     fn version(&self) -> usize {
         2
     }
 
-    async fn get_metadata(&self, data_dir: &Path) -> Result<CmsMetadata> {
-        let zip_path = data_dir.join(format!("{}.zip", self.key()));
-
-        // 1. Download if not exists
-        if !zip_path.exists() {
-            info!("Downloading POS data to {:?}...", zip_path);
-            let response = reqwest::blocking::get(self.url())?.error_for_status()?;
-            let bytes = response.bytes()?;
-            let mut file = File::create(&zip_path)?;
-            std::io::copy(&mut Cursor::new(bytes), &mut file)?;
-        } else {
-            info!("Using existing POS zip at {:?}", zip_path);
-        }
-
-        // Calculate file hash
-        let mut file = File::open(&zip_path)?;
-        let mut hasher = Sha256::new();
-
-        let mut buffer = [0; 1024];
-        loop {
-            let count = file.read(&mut buffer)?;
-            if count == 0 {
-                break;
-            }
-            hasher.update(&buffer[..count]);
-        }
-        let hash_result = hasher.finalize();
-        let file_hash_string = hex::encode(hash_result);
-        info!("File hash (SHA256): {}", file_hash_string);
-
-        Ok(CmsMetadata {
-            file: zip_path.into(),
-            file_hash: FileHash::Sha256(file_hash_string),
-        })
+    fn expected_columns(&self) -> Vec<ExpectedColumn> {
+        columns_of::<ProviderOfServiceRow>(&["prvdr_num"])
     }
 
-    async fn load(&self, file: &Box<Path>, pool: &sqlx::PgPool) -> Result<()> {
-        let mut file = File::open(file)?;
-
-        info!("Extracting zip from stream...");
-        // Rewind the file just in case
-        file.rewind()?;
-
-        let mut archive = zip::ZipArchive::new(file)?;
-
-        let mut csv_file_name = String::new();
+    fn header(&self, file: &Path) -> Result<Vec<String>> {
+        with_csv(file, |reader| common::schema::csv_header(reader))
+    }
 
-        // Find the first CSV in the archive
-        for i in 0..archive.len() {
-            let file = archive.by_index(i)?;
-            if file.name().ends_with(".csv") && file.name().contains("POS_File") {
-                csv_file_name = file.name().to_string();
-                break;
-            }
-        }
+    async fn get_metadata(
+        &self,
+        context: &LoaderContext<'_>,
+        source: &ResolvedSource,
+        cached: Option<&HttpValidators>,
+    ) -> Result<FetchOutcome> {
+        let path = context
+            .data_dir
+            .join(super::file_name(self.key(), &source.url, "csv"));
+        super::download_and_hash(context, &source.url, path, cached).await
+    }
 
-        if csv_file_name.is_empty() {
-            return Err(anyhow::anyhow!("No suitable CSV found in zip archive"));
+    /// Streams the file into a staging table with binary COPY, then merges it into
+    /// `addresses` and `providers`. The CSV is parsed on a blocking thread that hands
+    /// encoded chunks over a bounded channel, so memory use does not grow with the file.
+    async fn load(
+        &self,
+        file: &Path,
+        conn: &mut sqlx::PgConnection,
+        rejected: &mut Vec<RejectedRow>,
+    ) -> Result<LoadCounts> {
+        let (sender, mut receiver) = mpsc::channel(COPY_CHUNKS_IN_FLIGHT);
+        let path = file.to_path_buf();
+        let parser = tokio::task::spawn_blocking(move || {
+            with_csv(&path, |reader| encode_csv(reader, &sender))
+        });
+
+        common::db::create_pos_staging(conn).await?;
+
+        info!("Copying rows into staging for '{}'...", self.key());
+        let mut copy = conn
+            .copy_in_raw(&common::db::pos_staging_copy_statement())
+            .await?;
+        while let Some(chunk) = receiver.recv().await {
+            copy.send(chunk).await?;
         }
 
-        info!("Found CSV: {}", csv_file_name);
-
-        // Scope the borrow of archive
-        let (providers, unique_addresses, provider_to_addr_map) = {
-            let csv_file = archive.by_name(&csv_file_name)?;
-
-            // Stream parse
-            info!("Parsing CSV stream...");
-            let mut rdr = ReaderBuilder::new().has_headers(true).from_reader(csv_file);
-
-            let mut providers = Vec::new();
-            let mut unique_addresses = Vec::new();
-            let mut address_to_index = std::collections::HashMap::new();
-            let mut provider_to_addr_map = Vec::new();
-
-            for result in rdr.deserialize() {
-                let row: common::model::ProviderOfServiceRow = result.map_err(|e| {
-                    error!("Error parsing record: {}", e);
-                    e
-                })?;
-
-                let provider: Provider = row.clone().into();
-
-                // Identity for deduplication matches the DB unique index:
-                // COALESCE(street_address, ''), COALESCE(city, ''), COALESCE(state_code, ''), COALESCE(zip_code, '')
-                let identity = (
-                    row.street_address.as_deref().unwrap_or("").to_string(),
-                    row.city.as_deref().unwrap_or("").to_string(),
-                    row.state_code.as_deref().unwrap_or("").to_string(),
-                    row.zip_code.as_deref().unwrap_or("").to_string(),
-                );
-
-                let addr_idx = *address_to_index.entry(identity).or_insert_with(|| {
-                    let idx = unique_addresses.len();
-                    unique_addresses.push(Address::from(row));
-                    idx
-                });
-
-                providers.push(provider);
-                provider_to_addr_map.push(addr_idx);
+        // The channel closes when the parser is done; if it failed, the stream is incomplete.
+        let parsed = match parser.await? {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                copy.abort(e.to_string()).await?;
+                return Err(e);
             }
-            (providers, unique_addresses, provider_to_addr_map)
         };
+        copy.finish().await?;
+
+        let staged = parsed.rows - parsed.rejected.len() as u64;
+        info!(
+            "Merging {} staged rows into addresses and providers for '{}' ({} rejected)...",
+            staged,
+            self.key(),
+            parsed.rejected.len()
+        );
+        let held: Vec<String> = parsed
+            .rejected
+            .iter()
+            .filter_map(|row| row.record.get("prvdr_num")?.as_str())
+            .map(|ccn| ccn.trim().to_string())
+            .collect();
+        let counts = common::db::merge_pos_staging(conn, &held, self.max_retired_rate).await?;
+
+        info!(
+            "Inserted {} and updated {} providers, {} new addresses, retired {} providers, recorded {} new versions for '{}'.",
+            counts.inserted_providers,
+            counts.updated_providers,
+            counts.new_addresses,
+            counts.retired,
+            counts.new_versions,
+            self.key()
+        );
+        let geocoded = common::db::geocode_addresses(conn).await?;
+        info!(
+            "Geocoded {} addresses for '{}'; {} are not placed.",
+            geocoded.updated,
+            self.key(),
+            geocoded.missing
+        );
+
+        let counts = LoadCounts {
+            rows_parsed: parsed.rows,
+            rows_inserted: counts.inserted_providers,
+            rows_updated: counts.updated_providers,
+            rows_rejected: parsed.rejected.len() as u64,
+        };
+        rejected.extend(parsed.rejected);
+        Ok(counts)
+    }
+}
+
+/// What the parser read: every record, and the ones left out of the COPY stream.
+struct Parsed {
+    rows: u64,
+    rejected: Vec<RejectedRow>,
+}
 
-        // 4. Insert Data
-        if !unique_addresses.is_empty() {
-            info!(
-                "Inserting {} unique addresses (from {} total rows) for '{}'...",
-                unique_addresses.len(),
-                providers.len(),
-                self.key()
-            );
-            let address_ids = common::db::bulk_insert_addresses(pool, &unique_addresses).await?;
-
-            let mut providers = providers;
-            info!("Linking {} providers to addresses...", providers.len());
-            for (p_idx, addr_idx) in provider_to_addr_map.into_iter().enumerate() {
-                if addr_idx < address_ids.len() {
-                    providers[p_idx].address_id = Some(address_ids[addr_idx]);
-                }
+/// Opens the POS file, which is either the CSV itself or a zip containing it, and hands the
+/// CSV to `read`. The catalog publishes the quarterly CSV; older full-dataset downloads were
+/// zips.
+fn with_csv<T>(path: &Path, read: impl FnOnce(&mut dyn Read) -> Result<T>) -> Result<T> {
+    super::with_file_in_zip(
+        path,
+        |name| name.ends_with(".csv") && name.contains("POS_File"),
+        read,
+    )
+}
+
+fn encode_csv(reader: impl Read, sender: &mpsc::Sender<Vec<u8>>) -> Result<Parsed> {
+    // Stream parse
+    info!("Parsing CSV stream...");
+    // Malformed records are rejected by `parse_byte_record` rather than failing the read.
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .from_reader(reader);
+    let headers = rdr.headers()?.clone();
+
+    let mut writer = BinaryCopyWriter::new();
+    let mut record = csv::ByteRecord::new();
+    let mut rows = 0;
+    let mut rejected = Vec::new();
+
+    while rdr.read_byte_record(&mut record)? {
+        rows += 1;
+        let row: ProviderOfServiceRow = match parse_byte_record(&headers, &record) {
+            Ok(row) => row,
+            Err(rejection) => {
+                rejected.push(rejection);
+                continue;
             }
+        };
+        let line = record.position().map_or(0, |p| p.line()) as i64;
+        common::db::write_pos_row(&mut writer, line, &row);
 
-            info!(
-                "Inserting {} providers for '{}'...",
-                providers.len(),
-                self.key()
-            );
-            common::db::bulk_insert_providers(pool, &providers).await?;
+        if writer.len() >= COPY_CHUNK_SIZE {
+            sender
+                .blocking_send(writer.take())
+                .context("Database stopped accepting rows")?;
         }
+    }
+
+    sender
+        .blocking_send(writer.finish())
+        .context("Database stopped accepting rows")?;
+    Ok(Parsed { rows, rejected })
+}
 
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_records_are_rejected_without_ending_the_load() {
+        let data = b"prvdr_num,state_cd,fac_name\n\
+            220071,MA,GENERAL\n\
+            220072,MA\n\
+            220073,MA,CAF\xc9\n\
+            220074,MA,GENERAL\n";
+        let (sender, mut receiver) = mpsc::channel(COPY_CHUNKS_IN_FLIGHT);
+        let parsed = encode_csv(&data[..], &sender).unwrap();
+        assert!(receiver.try_recv().is_ok());
+
+        assert_eq!(parsed.rows, 4);
+        let lines: Vec<i64> = parsed.rejected.iter().map(|row| row.line).collect();
+        assert_eq!(lines, [3, 4]);
     }
 }
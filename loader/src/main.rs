@@ -1,18 +1,170 @@
-use clap::Parser;
-use common::args::PostgresSqlArguments;
+use clap::{Args, Parser, Subcommand};
+use common::args::{HttpArguments, PostgresSqlArguments};
+use common::engine::{LoaderEngine, RunOptions};
+use common::http::HttpClient;
+use common::lock::{AlreadyRunning, LockScope};
 use common::state::AppState;
 use dotenvy::dotenv;
-use tracing::info;
+use tracing::{error, info};
 use tracing_subscriber::{EnvFilter, fmt};
 
+mod commands;
+mod daemon;
 mod loaders;
+use crate::loaders::gazetteer::{
+    COUNTY_GAZETTEER_URL, CountyGazetteerLoader, ZCTA_GAZETTEER_URL, ZctaGazetteerLoader,
+};
+use crate::loaders::hospital_enrollments::HospitalEnrollmentsLoader;
 use crate::loaders::pos::ProviderOfServicesLoader;
+use std::net::SocketAddr;
 use std::path::Path;
+use std::time::Duration;
+
+/// The exit code when another process holds the lock: `EX_TEMPFAIL` from sysexits.h, since
+/// trying again later will work.
+const ALREADY_RUNNING_EXIT_CODE: i32 = 75;
+
+/// How many loaders run at once unless `--jobs` says otherwise.
+const DEFAULT_JOBS: usize = 2;
+
+/// The share of rejected rows that fails a loader unless `--max-rejected-rate` says otherwise.
+const DEFAULT_MAX_REJECTED_RATE: f64 = 0.01;
+
+/// The share of active providers a POS file may retire unless `--max-retired-rate` says
+/// otherwise. A quarterly release retires far fewer.
+const DEFAULT_MAX_RETIRED_RATE: f64 = 0.05;
 
 #[derive(Parser, Debug)]
 struct Cli {
     #[command(flatten)]
     postgres: PostgresSqlArguments,
+
+    #[command(flatten)]
+    http: HttpArguments,
+
+    /// The DCAT catalog used to resolve dataset download URLs.
+    #[arg(long, env = "CMS_CATALOG_URL", default_value = common::catalog::DEFAULT_CATALOG_URL)]
+    catalog_url: String,
+
+    /// The Census ZCTA gazetteer addresses are geocoded from: a URL, or a local file (the
+    /// zip or the text file in it).
+    #[arg(long, env = "LOADER_ZCTA_GAZETTEER", default_value = ZCTA_GAZETTEER_URL)]
+    zcta_gazetteer: String,
+
+    /// The Census county gazetteer, for addresses whose ZIP has no ZCTA: a URL, or a local
+    /// file.
+    #[arg(long, env = "LOADER_COUNTY_GAZETTEER", default_value = COUNTY_GAZETTEER_URL)]
+    county_gazetteer: String,
+
+    /// Fail the POS load, rolling it back, if the file would retire more than this fraction
+    /// of the active providers.
+    #[arg(long, env = "LOADER_MAX_RETIRED_RATE", default_value_t = DEFAULT_MAX_RETIRED_RATE, value_parser = parse_rate)]
+    max_retired_rate: f64,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Runs the registered loaders whose data changed. This is the default.
+    Run(RunArgs),
+    /// Lists the registered loaders with their versions and current download URLs.
+    List,
+    /// Keeps running, checking each loader on its own schedule, until SIGTERM or Ctrl-C.
+    Daemon(DaemonArgs),
+    /// Compares each loader with its last successful load, then shows the most recent runs.
+    Status {
+        /// Only show this loader.
+        #[arg(long)]
+        loader_key: Option<String>,
+
+        /// How many recent runs to show.
+        #[arg(long, default_value_t = 20)]
+        limit: i64,
+    },
+}
+
+#[derive(Args, Debug, Default)]
+struct RunArgs {
+    /// Only run these loaders. Takes a comma-separated list, or repeat the flag.
+    #[arg(long, value_delimiter = ',')]
+    only: Vec<String>,
+
+    /// Don't run these loaders. Takes a comma-separated list, or repeat the flag.
+    #[arg(long, value_delimiter = ',')]
+    skip: Vec<String>,
+
+    /// Load even if the file and plugin version are unchanged.
+    #[arg(long)]
+    force: bool,
+
+    /// Download and parse the files and report what would change, without writing anything.
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    schedule: ScheduleArgs,
+}
+
+#[derive(Args, Debug)]
+struct DaemonArgs {
+    /// The address of the health endpoint, `GET /health`.
+    #[arg(long, env = "LOADER_HEALTH_ADDRESS", default_value = "0.0.0.0:8081")]
+    health_address: SocketAddr,
+
+    #[command(flatten)]
+    schedule: ScheduleArgs,
+}
+
+/// How loaders are run, shared by `run` and `daemon`.
+#[derive(Args, Debug)]
+struct ScheduleArgs {
+    /// Lock each loader, or the whole run, against other loader processes.
+    #[arg(long, value_enum, default_value_t = LockScope::Loader)]
+    lock: LockScope,
+
+    /// How many seconds to wait for another process to finish. By default, give up at once.
+    #[arg(long, default_value_t = 0)]
+    lock_timeout: u64,
+
+    /// How many loaders to run at once, among those whose dependencies are loaded.
+    #[arg(long, default_value_t = DEFAULT_JOBS)]
+    jobs: usize,
+
+    /// Fail a loader, rolling it back, if more than this fraction of its rows is rejected.
+    #[arg(long, default_value_t = DEFAULT_MAX_REJECTED_RATE, value_parser = parse_rate)]
+    max_rejected_rate: f64,
+}
+
+impl Default for ScheduleArgs {
+    fn default() -> Self {
+        Self {
+            lock: LockScope::Loader,
+            lock_timeout: 0,
+            jobs: DEFAULT_JOBS,
+            max_rejected_rate: DEFAULT_MAX_REJECTED_RATE,
+        }
+    }
+}
+
+impl ScheduleArgs {
+    fn options(&self) -> RunOptions {
+        RunOptions {
+            lock: self.lock,
+            lock_timeout: Duration::from_secs(self.lock_timeout),
+            concurrency: self.jobs,
+            max_rejected_rate: Some(self.max_rejected_rate),
+            ..RunOptions::default()
+        }
+    }
+}
+
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("'{}' is not a fraction between 0 and 1", value)),
+    }
 }
 
 #[tokio::main]
@@ -36,19 +188,66 @@ async fn main() -> anyhow::Result<()> {
     sqlx::migrate!("../migrations").run(&state.pool).await?;
     info!("Migrations completed successfully.");
 
-    let mut engine = common::engine::LoaderEngine::new(state.pool.clone()).await?;
+    let http = HttpClient::new(&args.http)?;
+    let mut engine = LoaderEngine::new(state.pool.clone(), args.catalog_url, http).await?;
+
+    info!("Registering loaders...");
+    engine.register(Box::new(ProviderOfServicesLoader {
+        max_retired_rate: args.max_retired_rate,
+    }));
+    engine.register(Box::new(HospitalEnrollmentsLoader));
+    engine.register(Box::new(ZctaGazetteerLoader {
+        source: args.zcta_gazetteer,
+    }));
+    engine.register(Box::new(CountyGazetteerLoader {
+        source: args.county_gazetteer,
+    }));
+
     let data_dir = Path::new("data");
 
-    // Ensure data directory exists
-    if !data_dir.exists() {
-        std::fs::create_dir_all(data_dir)?;
-    }
+    match args.command.unwrap_or(Command::Run(RunArgs::default())) {
+        Command::Run(run) => {
+            // Ensure data directory exists
+            if !data_dir.exists() {
+                std::fs::create_dir_all(data_dir)?;
+            }
 
-    info!("Registering loaders...");
-    engine.register(Box::new(ProviderOfServicesLoader));
+            let options = RunOptions {
+                only: run.only,
+                skip: run.skip,
+                force: run.force,
+                dry_run: run.dry_run,
+                ..run.schedule.options()
+            };
 
-    info!("Running engine...");
-    engine.run(data_dir).await?;
+            info!("Running engine...");
+            if let Err(e) = engine.run(data_dir, &options).await {
+                // Not a failure: cron simply started us while another run was in progress.
+                if let Some(running) = e.downcast_ref::<AlreadyRunning>() {
+                    error!("{}.", running);
+                    std::process::exit(ALREADY_RUNNING_EXIT_CODE);
+                }
+                return Err(e);
+            }
+        }
+        Command::Daemon(daemon) => {
+            if !data_dir.exists() {
+                std::fs::create_dir_all(data_dir)?;
+            }
+            daemon::run(
+                &mut engine,
+                &state.pool,
+                data_dir,
+                daemon.schedule.options(),
+                daemon.health_address,
+            )
+            .await?
+        }
+        Command::List => commands::list(&engine).await,
+        Command::Status { loader_key, limit } => {
+            commands::status(&engine, &state.pool, loader_key.as_deref(), limit).await?
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,5 @@
+-- Indexes backing the filters of GET /providers.
+CREATE INDEX IF NOT EXISTS idx_providers_provider_type_id ON providers(provider_type_id, provider_subtype_id);
+CREATE INDEX IF NOT EXISTS idx_providers_facility_category_code ON providers(facility_category_code);
+CREATE INDEX IF NOT EXISTS idx_providers_ownership_type_code ON providers(ownership_type_code);
+CREATE INDEX IF NOT EXISTS idx_addresses_cbsa_code ON addresses(cbsa_code);
@@ -0,0 +1,5 @@
+-- Trigram indexes for fuzzy matching of facility names and cities (GET /providers/search).
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS idx_providers_name_trgm ON providers USING GIN (name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_addresses_city_trgm ON addresses USING GIN (city gin_trgm_ops);
@@ -0,0 +1,197 @@
+-- Lookup tables decoding the raw code columns of the Provider of Services (POS) file.
+-- Labels come from the POS data dictionary. Codes that show up in a newer file before they
+-- are added here are registered by the loader with a NULL label, so the foreign keys below
+-- never reject a load.
+
+CREATE TABLE IF NOT EXISTS provider_types (
+    id INTEGER PRIMARY KEY,
+    label TEXT
+);
+
+CREATE TABLE IF NOT EXISTS provider_subtypes (
+    provider_type_id INTEGER NOT NULL REFERENCES provider_types(id),
+    id INTEGER NOT NULL,
+    label TEXT,
+    PRIMARY KEY (provider_type_id, id)
+);
+
+CREATE TABLE IF NOT EXISTS ownership_types (
+    code TEXT PRIMARY KEY,
+    label TEXT
+);
+
+CREATE TABLE IF NOT EXISTS accreditation_types (
+    code TEXT PRIMARY KEY,
+    label TEXT
+);
+
+CREATE TABLE IF NOT EXISTS compliance_statuses (
+    code TEXT PRIMARY KEY,
+    label TEXT
+);
+
+CREATE TABLE IF NOT EXISTS certification_action_types (
+    code TEXT PRIMARY KEY,
+    label TEXT
+);
+
+CREATE TABLE IF NOT EXISTS facility_categories (
+    code TEXT PRIMARY KEY,
+    label TEXT
+);
+
+CREATE TABLE IF NOT EXISTS cbsa_urban_rural_indicators (
+    code TEXT PRIMARY KEY,
+    label TEXT
+);
+
+-- Provider category (PRVDR_CTGRY_CD)
+INSERT INTO provider_types (id, label) VALUES
+    (1, 'Hospital'),
+    (2, 'Skilled Nursing Facility/Nursing Facility (Dually Certified)'),
+    (3, 'Skilled Nursing Facility/Nursing Facility (Distinct Part)'),
+    (4, 'Skilled Nursing Facility'),
+    (5, 'Home Health Agency'),
+    (6, 'Psychiatric Residential Treatment Facility'),
+    (7, 'Portable X-Ray Supplier'),
+    (8, 'Outpatient Physical Therapy/Speech Pathology'),
+    (9, 'End Stage Renal Disease Facility'),
+    (10, 'Nursing Facility'),
+    (11, 'Intermediate Care Facility/Individuals with Intellectual Disabilities'),
+    (12, 'Rural Health Clinic'),
+    (14, 'Comprehensive Outpatient Rehabilitation Facility'),
+    (15, 'Ambulatory Surgical Center'),
+    (16, 'Hospice'),
+    (17, 'Organ Procurement Organization'),
+    (19, 'Community Mental Health Center'),
+    (21, 'Federally Qualified Health Center')
+ON CONFLICT (id) DO NOTHING;
+
+-- Hospital subtypes (PRVDR_CTGRY_SBTYP_CD for category 01)
+INSERT INTO provider_subtypes (provider_type_id, id, label) VALUES
+    (1, 1, 'Short Term'),
+    (1, 2, 'Long Term'),
+    (1, 3, 'Religious Non-Medical Health Care Institution'),
+    (1, 4, 'Psychiatric'),
+    (1, 5, 'Rehabilitation'),
+    (1, 6, 'Childrens'),
+    (1, 7, 'Distinct Part Psychiatric Hospital'),
+    (1, 11, 'Critical Access Hospital'),
+    (1, 20, 'Transplant Hospital'),
+    (1, 22, 'Medicaid Only Non-Psychiatric Hospital'),
+    (1, 23, 'Medicaid Only Psychiatric Hospital')
+ON CONFLICT (provider_type_id, id) DO NOTHING;
+
+-- Type of control (GNRL_CNTL_TYPE_CD)
+INSERT INTO ownership_types (code, label) VALUES
+    ('01', 'Church'),
+    ('02', 'Private (Not for Profit)'),
+    ('03', 'Other (Private Not for Profit)'),
+    ('04', 'Private (For Profit)'),
+    ('05', 'Federal'),
+    ('06', 'State'),
+    ('07', 'Local'),
+    ('08', 'Hospital District or Authority'),
+    ('09', 'Physician Ownership'),
+    ('10', 'Tribal')
+ON CONFLICT (code) DO NOTHING;
+
+-- Accreditation organization (ACRDTN_TYPE_CD)
+INSERT INTO accreditation_types (code, label) VALUES
+    ('1', 'The Joint Commission'),
+    ('2', 'American Osteopathic Association / Healthcare Facilities Accreditation Program'),
+    ('3', 'Community Health Accreditation Partner'),
+    ('4', 'Accreditation Commission for Health Care'),
+    ('5', 'Accreditation Association for Ambulatory Health Care'),
+    ('6', 'American Association for Accreditation of Ambulatory Surgery Facilities'),
+    ('7', 'DNV Healthcare'),
+    ('8', 'Center for Improvement in Healthcare Quality')
+ON CONFLICT (code) DO NOTHING;
+
+-- Compliance status (CMPLNC_STUS_CD)
+INSERT INTO compliance_statuses (code, label) VALUES
+    ('A', 'In Compliance'),
+    ('B', 'Not in Compliance')
+ON CONFLICT (code) DO NOTHING;
+
+-- Certification action type (CRTFCTN_ACTN_TYPE_CD)
+INSERT INTO certification_action_types (code, label) VALUES
+    ('1', 'Initial Certification'),
+    ('2', 'Recertification'),
+    ('3', 'Termination'),
+    ('4', 'Change of Ownership'),
+    ('5', 'Validation'),
+    ('7', 'Complaint Investigation')
+ON CONFLICT (code) DO NOTHING;
+
+-- General facility type (GNRL_FAC_TYPE_CD)
+INSERT INTO facility_categories (code, label) VALUES
+    ('01', 'Short Term'),
+    ('02', 'Long Term'),
+    ('03', 'Religious Non-Medical Health Care Institution'),
+    ('04', 'Psychiatric'),
+    ('05', 'Rehabilitation'),
+    ('06', 'Childrens'),
+    ('07', 'Distinct Part Psychiatric Hospital'),
+    ('11', 'Critical Access Hospital')
+ON CONFLICT (code) DO NOTHING;
+
+-- CBSA urban/rural indicator (CBSA_URBN_RRL_IND)
+INSERT INTO cbsa_urban_rural_indicators (code, label) VALUES
+    ('U', 'Urban'),
+    ('R', 'Rural')
+ON CONFLICT (code) DO NOTHING;
+
+-- Register codes already present in loaded data so the foreign keys can be created.
+INSERT INTO provider_types (id)
+SELECT DISTINCT provider_type_id FROM providers WHERE provider_type_id IS NOT NULL
+ON CONFLICT (id) DO NOTHING;
+
+INSERT INTO provider_subtypes (provider_type_id, id)
+SELECT DISTINCT provider_type_id, provider_subtype_id FROM providers
+WHERE provider_type_id IS NOT NULL AND provider_subtype_id IS NOT NULL
+ON CONFLICT (provider_type_id, id) DO NOTHING;
+
+INSERT INTO ownership_types (code)
+SELECT DISTINCT ownership_type_code FROM providers WHERE ownership_type_code IS NOT NULL
+ON CONFLICT (code) DO NOTHING;
+
+INSERT INTO accreditation_types (code)
+SELECT DISTINCT accreditation_type_code FROM providers WHERE accreditation_type_code IS NOT NULL
+ON CONFLICT (code) DO NOTHING;
+
+INSERT INTO compliance_statuses (code)
+SELECT DISTINCT compliance_status_code FROM providers WHERE compliance_status_code IS NOT NULL
+ON CONFLICT (code) DO NOTHING;
+
+INSERT INTO certification_action_types (code)
+SELECT DISTINCT certification_action_type_code FROM providers WHERE certification_action_type_code IS NOT NULL
+ON CONFLICT (code) DO NOTHING;
+
+INSERT INTO facility_categories (code)
+SELECT DISTINCT facility_category_code FROM providers WHERE facility_category_code IS NOT NULL
+ON CONFLICT (code) DO NOTHING;
+
+INSERT INTO cbsa_urban_rural_indicators (code)
+SELECT DISTINCT cbsa_urban_rural_indicator FROM addresses WHERE cbsa_urban_rural_indicator IS NOT NULL
+ON CONFLICT (code) DO NOTHING;
+
+ALTER TABLE providers
+    ADD CONSTRAINT fk_providers_provider_type
+        FOREIGN KEY (provider_type_id) REFERENCES provider_types(id),
+    ADD CONSTRAINT fk_providers_provider_subtype
+        FOREIGN KEY (provider_type_id, provider_subtype_id) REFERENCES provider_subtypes(provider_type_id, id),
+    ADD CONSTRAINT fk_providers_ownership_type
+        FOREIGN KEY (ownership_type_code) REFERENCES ownership_types(code),
+    ADD CONSTRAINT fk_providers_accreditation_type
+        FOREIGN KEY (accreditation_type_code) REFERENCES accreditation_types(code),
+    ADD CONSTRAINT fk_providers_compliance_status
+        FOREIGN KEY (compliance_status_code) REFERENCES compliance_statuses(code),
+    ADD CONSTRAINT fk_providers_certification_action_type
+        FOREIGN KEY (certification_action_type_code) REFERENCES certification_action_types(code),
+    ADD CONSTRAINT fk_providers_facility_category
+        FOREIGN KEY (facility_category_code) REFERENCES facility_categories(code);
+
+ALTER TABLE addresses
+    ADD CONSTRAINT fk_addresses_cbsa_urban_rural_indicator
+        FOREIGN KEY (cbsa_urban_rural_indicator) REFERENCES cbsa_urban_rural_indicators(code);
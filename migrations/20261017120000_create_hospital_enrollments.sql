@@ -0,0 +1,57 @@
+-- Hospital Enrollments dataset (https://data.cms.gov/provider-characteristics/hospitals-and-other-facilities/hospital-enrollments)
+-- One row per enrollment. Joins to providers on cms_certification_number; there is no foreign key
+-- because an enrollment can reference a CCN that the Provider of Services file does not carry.
+CREATE TABLE IF NOT EXISTS hospital_enrollments (
+    enrollment_id TEXT PRIMARY KEY,
+    enrollment_state TEXT,
+    provider_type_code TEXT,
+    provider_type_text TEXT,
+    npi TEXT,
+    multiple_npi_flag BOOLEAN,
+    cms_certification_number TEXT,
+    associate_id TEXT,
+    organization_name TEXT,
+    doing_business_as_name TEXT,
+    incorporation_date DATE,
+    incorporation_state TEXT,
+    organization_type_structure TEXT,
+    organization_other_type_text TEXT,
+    proprietary_nonprofit TEXT,
+
+    -- Practice location
+    address_line_1 TEXT,
+    address_line_2 TEXT,
+    city TEXT,
+    state_code TEXT,
+    zip_code TEXT,
+    practice_location_type TEXT,
+    location_other_type_text TEXT,
+
+    -- Hospital type flags
+    critical_access_hospital BOOLEAN NOT NULL DEFAULT FALSE,
+    rural_emergency_hospital BOOLEAN NOT NULL DEFAULT FALSE,
+
+    -- Subgroups
+    subgroup_general BOOLEAN,
+    subgroup_acute_care BOOLEAN,
+    subgroup_alcohol_drug BOOLEAN,
+    subgroup_childrens BOOLEAN,
+    subgroup_long_term BOOLEAN,
+    subgroup_psychiatric BOOLEAN,
+    subgroup_rehabilitation BOOLEAN,
+    subgroup_short_term BOOLEAN,
+    subgroup_swing_bed_approved BOOLEAN,
+    subgroup_psychiatric_unit BOOLEAN,
+    subgroup_rehabilitation_unit BOOLEAN,
+    subgroup_specialty_hospital BOOLEAN,
+    subgroup_other BOOLEAN,
+    subgroup_other_text TEXT,
+
+    -- Rural Emergency Hospital conversion
+    reh_conversion_flag BOOLEAN,
+    reh_conversion_date DATE,
+    cah_or_hospital_ccn TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_hospital_enrollments_ccn ON hospital_enrollments(cms_certification_number);
+CREATE INDEX IF NOT EXISTS idx_hospital_enrollments_npi ON hospital_enrollments(npi);
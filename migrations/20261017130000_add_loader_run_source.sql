@@ -0,0 +1,4 @@
+-- The download URL each loader last ran from, and the catalog's modified date for it.
+ALTER TABLE loader_runs
+    ADD COLUMN IF NOT EXISTS source_url TEXT,
+    ADD COLUMN IF NOT EXISTS source_modified TIMESTAMPTZ;
@@ -0,0 +1,6 @@
+-- HTTP cache validators from the last download, sent back as If-None-Match/If-Modified-Since
+-- so an unchanged file is not downloaded again.
+ALTER TABLE loader_runs
+    ADD COLUMN IF NOT EXISTS etag TEXT,
+    ADD COLUMN IF NOT EXISTS last_modified TEXT,
+    ADD COLUMN IF NOT EXISTS content_length BIGINT;
@@ -0,0 +1,17 @@
+-- Track which providers the latest POS file still lists. Each load stamps the providers it
+-- contains with last_seen_at and retires the rest by setting retired_at; a retired provider
+-- that reappears in a later file is reinstated.
+ALTER TABLE providers
+    ADD COLUMN IF NOT EXISTS last_seen_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS retired_at TIMESTAMPTZ;
+
+-- Everything loaded so far came from the last POS run.
+UPDATE providers
+SET last_seen_at = COALESCE(
+    (SELECT last_run FROM loader_runs WHERE loader_key = 'pos_iqies'),
+    NOW()
+)
+WHERE last_seen_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_providers_active ON providers(cms_certification_number)
+    WHERE retired_at IS NULL;
@@ -0,0 +1,32 @@
+-- Slowly changing history of providers. Each row is one version of a provider, valid from the
+-- POS load that first saw it until the load that changed or retired it (valid_to is NULL for
+-- the current version). `record` holds the provider's columns with its address inlined.
+CREATE TABLE IF NOT EXISTS provider_history (
+    id BIGSERIAL PRIMARY KEY,
+    cms_certification_number TEXT NOT NULL,
+    valid_from TIMESTAMPTZ NOT NULL,
+    valid_to TIMESTAMPTZ,
+    record JSONB NOT NULL,
+    UNIQUE (cms_certification_number, valid_from)
+);
+
+-- At most one open version per provider.
+CREATE UNIQUE INDEX IF NOT EXISTS idx_provider_history_current
+    ON provider_history(cms_certification_number) WHERE valid_to IS NULL;
+
+-- Start the history with the providers already loaded.
+INSERT INTO provider_history (cms_certification_number, valid_from, valid_to, record)
+SELECT
+    p.cms_certification_number,
+    COALESCE(p.last_seen_at, NOW()),
+    p.retired_at,
+    (to_jsonb(p) - ARRAY['cms_certification_number', 'address_id', 'last_seen_at', 'retired_at'])
+        || jsonb_build_object(
+            'street_address', a.street_address,
+            'city', a.city,
+            'state_code', a.state_code,
+            'zip_code', a.zip_code
+        )
+FROM providers p
+LEFT JOIN addresses a ON a.id = p.address_id
+ON CONFLICT DO NOTHING;
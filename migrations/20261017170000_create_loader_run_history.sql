@@ -0,0 +1,23 @@
+-- One row per loader run, appended when the run starts and completed when it ends.
+-- `loader_runs` only keeps the latest successful load; this keeps every attempt, including
+-- failed ones. A run left in 'running' was interrupted before it could record its outcome.
+CREATE TABLE IF NOT EXISTS loader_run_history (
+    id BIGSERIAL PRIMARY KEY,
+    loader_key TEXT NOT NULL,
+    plugin_version INTEGER NOT NULL,
+    status TEXT NOT NULL DEFAULT 'running'
+        CHECK (status IN ('running', 'succeeded', 'skipped', 'failed')),
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMPTZ,
+    duration_ms BIGINT,
+    source_url TEXT,
+    file_hash TEXT,
+    rows_parsed BIGINT,
+    rows_inserted BIGINT,
+    rows_updated BIGINT,
+    rows_rejected BIGINT,
+    error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_loader_run_history_key_started
+    ON loader_run_history (loader_key, started_at DESC);
@@ -0,0 +1,13 @@
+-- Records left out of a load because they could not be parsed or broke a validation rule.
+-- Each belongs to the run that rejected it; `record` holds the raw values by column name.
+CREATE TABLE IF NOT EXISTS rejected_rows (
+    id BIGSERIAL PRIMARY KEY,
+    run_id BIGINT NOT NULL REFERENCES loader_run_history(id) ON DELETE CASCADE,
+    loader_key TEXT NOT NULL,
+    line_number BIGINT NOT NULL,
+    reason TEXT NOT NULL,
+    record JSONB NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_rejected_rows_run ON rejected_rows (run_id, line_number);
+CREATE INDEX IF NOT EXISTS idx_rejected_rows_key ON rejected_rows (loader_key);
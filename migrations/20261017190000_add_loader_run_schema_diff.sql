@@ -0,0 +1,4 @@
+-- How the header of the loaded file differed from the columns the loader expects:
+-- {"added": [...], "removed": [...], "renamed": [{"from": ..., "to": ...}]}.
+-- NULL when the header matched, or the run never got as far as checking it.
+ALTER TABLE loader_run_history ADD COLUMN IF NOT EXISTS schema_diff JSONB;
@@ -0,0 +1,227 @@
+-- Standardize the stored addresses the way the loader now does (see common/src/address.rs),
+-- then merge the rows that turn out to be the same address: "123 North Main Street" and
+-- "123 N MAIN ST", or one ZIP with and without its +4. The functions below mirror the Rust
+-- rules as of this migration and are dropped when it finishes.
+ALTER TABLE addresses ADD COLUMN IF NOT EXISTS zip4 TEXT;
+
+CREATE TEMP TABLE address_abbreviations (
+    kind TEXT NOT NULL,
+    word TEXT NOT NULL,
+    abbreviation TEXT NOT NULL,
+    PRIMARY KEY (kind, word)
+) ON COMMIT DROP;
+
+INSERT INTO address_abbreviations (kind, word, abbreviation) VALUES
+    ('directional', 'NORTH', 'N'), ('directional', 'SOUTH', 'S'),
+    ('directional', 'EAST', 'E'), ('directional', 'WEST', 'W'),
+    ('directional', 'NORTHEAST', 'NE'), ('directional', 'NORTHWEST', 'NW'),
+    ('directional', 'SOUTHEAST', 'SE'), ('directional', 'SOUTHWEST', 'SW'),
+    ('directional', 'N', 'N'), ('directional', 'S', 'S'),
+    ('directional', 'E', 'E'), ('directional', 'W', 'W'),
+    ('directional', 'NE', 'NE'), ('directional', 'NW', 'NW'),
+    ('directional', 'SE', 'SE'), ('directional', 'SW', 'SW'),
+
+    ('suffix', 'ALLEY', 'ALY'), ('suffix', 'ALLEE', 'ALY'),
+    ('suffix', 'AVENUE', 'AVE'), ('suffix', 'AV', 'AVE'), ('suffix', 'AVEN', 'AVE'),
+    ('suffix', 'AVENU', 'AVE'), ('suffix', 'AVN', 'AVE'), ('suffix', 'AVNUE', 'AVE'),
+    ('suffix', 'BOULEVARD', 'BLVD'), ('suffix', 'BOUL', 'BLVD'), ('suffix', 'BOULV', 'BLVD'),
+    ('suffix', 'BYPASS', 'BYP'), ('suffix', 'CAUSEWAY', 'CSWY'),
+    ('suffix', 'CENTER', 'CTR'), ('suffix', 'CENTRE', 'CTR'), ('suffix', 'CENTR', 'CTR'),
+    ('suffix', 'CIRCLE', 'CIR'), ('suffix', 'CIRC', 'CIR'), ('suffix', 'CIRCL', 'CIR'),
+    ('suffix', 'COURT', 'CT'), ('suffix', 'COVE', 'CV'), ('suffix', 'CREEK', 'CRK'),
+    ('suffix', 'CROSSING', 'XING'),
+    ('suffix', 'DRIVE', 'DR'), ('suffix', 'DRIV', 'DR'), ('suffix', 'DRV', 'DR'),
+    ('suffix', 'EXPRESSWAY', 'EXPY'), ('suffix', 'EXPRESS', 'EXPY'), ('suffix', 'EXPW', 'EXPY'),
+    ('suffix', 'FREEWAY', 'FWY'), ('suffix', 'FREEWY', 'FWY'),
+    ('suffix', 'GROVE', 'GRV'), ('suffix', 'HARBOR', 'HBR'), ('suffix', 'HEIGHTS', 'HTS'),
+    ('suffix', 'HIGHWAY', 'HWY'), ('suffix', 'HIGHWY', 'HWY'), ('suffix', 'HIWAY', 'HWY'),
+    ('suffix', 'HILL', 'HL'), ('suffix', 'LANE', 'LN'), ('suffix', 'MEADOWS', 'MDWS'),
+    ('suffix', 'PARKWAY', 'PKWY'), ('suffix', 'PARKWY', 'PKWY'), ('suffix', 'PKY', 'PKWY'),
+    ('suffix', 'PLACE', 'PL'), ('suffix', 'PLAZA', 'PLZ'), ('suffix', 'PLZA', 'PLZ'),
+    ('suffix', 'POINT', 'PT'), ('suffix', 'RIDGE', 'RDG'), ('suffix', 'ROAD', 'RD'),
+    ('suffix', 'ROUTE', 'RTE'), ('suffix', 'SQUARE', 'SQ'), ('suffix', 'SQR', 'SQ'),
+    ('suffix', 'STREET', 'ST'), ('suffix', 'STR', 'ST'), ('suffix', 'STRT', 'ST'),
+    ('suffix', 'TERRACE', 'TER'), ('suffix', 'TERR', 'TER'),
+    ('suffix', 'TRAIL', 'TRL'), ('suffix', 'TRAILS', 'TRL'),
+    ('suffix', 'TURNPIKE', 'TPKE'), ('suffix', 'TURNPK', 'TPKE'), ('suffix', 'VIEW', 'VW'),
+    ('suffix', 'ALY', 'ALY'), ('suffix', 'AVE', 'AVE'), ('suffix', 'BLVD', 'BLVD'),
+    ('suffix', 'BYP', 'BYP'), ('suffix', 'CIR', 'CIR'), ('suffix', 'CSWY', 'CSWY'),
+    ('suffix', 'CT', 'CT'), ('suffix', 'CTR', 'CTR'), ('suffix', 'CV', 'CV'),
+    ('suffix', 'DR', 'DR'), ('suffix', 'EXPY', 'EXPY'), ('suffix', 'FWY', 'FWY'),
+    ('suffix', 'HWY', 'HWY'), ('suffix', 'LN', 'LN'), ('suffix', 'LOOP', 'LOOP'),
+    ('suffix', 'PARK', 'PARK'), ('suffix', 'PIKE', 'PIKE'), ('suffix', 'PKWY', 'PKWY'),
+    ('suffix', 'PL', 'PL'), ('suffix', 'PLZ', 'PLZ'), ('suffix', 'RD', 'RD'),
+    ('suffix', 'RTE', 'RTE'), ('suffix', 'RUN', 'RUN'), ('suffix', 'SQ', 'SQ'),
+    ('suffix', 'ST', 'ST'), ('suffix', 'TER', 'TER'), ('suffix', 'TPKE', 'TPKE'),
+    ('suffix', 'TRL', 'TRL'), ('suffix', 'WAY', 'WAY'), ('suffix', 'XING', 'XING'),
+
+    ('unit', 'APARTMENT', 'APT'), ('unit', 'BASEMENT', 'BSMT'), ('unit', 'BUILDING', 'BLDG'),
+    ('unit', 'BLD', 'BLDG'), ('unit', 'DEPARTMENT', 'DEPT'), ('unit', 'FLOOR', 'FL'),
+    ('unit', 'FLR', 'FL'), ('unit', 'FRONT', 'FRNT'), ('unit', 'LOBBY', 'LBBY'),
+    ('unit', 'LOWER', 'LOWR'), ('unit', 'OFFICE', 'OFC'), ('unit', 'PENTHOUSE', 'PH'),
+    ('unit', 'ROOM', 'RM'), ('unit', 'SPACE', 'SPC'), ('unit', 'SUITE', 'STE'),
+    ('unit', 'SUIT', 'STE'), ('unit', 'STE', 'STE'), ('unit', 'TRAILER', 'TRLR'),
+    ('unit', 'UPPER', 'UPPR'), ('unit', 'APT', 'APT'), ('unit', 'BLDG', 'BLDG'),
+    ('unit', 'BSMT', 'BSMT'), ('unit', 'DEPT', 'DEPT'), ('unit', 'FL', 'FL'),
+    ('unit', 'LOT', 'LOT'), ('unit', 'OFC', 'OFC'), ('unit', 'PH', 'PH'),
+    ('unit', 'REAR', 'REAR'), ('unit', 'RM', 'RM'), ('unit', 'SPC', 'SPC'),
+    ('unit', 'TRLR', 'TRLR'), ('unit', 'UNIT', 'UNIT');
+
+CREATE FUNCTION pg_temp.abbreviate(kind TEXT, word TEXT) RETURNS TEXT
+LANGUAGE sql STABLE AS $$
+    SELECT abbreviation FROM address_abbreviations a WHERE a.kind = $1 AND a.word = $2
+$$;
+
+-- The uppercase words of s, without periods or commas. A '#' is a word of its own.
+CREATE FUNCTION pg_temp.address_words(s TEXT) RETURNS TEXT[]
+LANGUAGE sql IMMUTABLE AS $$
+    SELECT COALESCE(array_agg(w ORDER BY n), '{}')
+    FROM regexp_split_to_table(replace(replace(upper(s), '#', ' # '), '.', ''), '[\s,]+')
+        WITH ORDINALITY AS t(w, n)
+    WHERE w <> ''
+$$;
+
+CREATE FUNCTION pg_temp.ends_street_name(word TEXT) RETURNS BOOLEAN
+LANGUAGE sql STABLE AS $$
+    SELECT $1 IS NULL OR $1 = '#'
+        OR pg_temp.abbreviate('directional', $1) IS NOT NULL
+        OR pg_temp.abbreviate('unit', $1) IS NOT NULL
+$$;
+
+CREATE FUNCTION pg_temp.standardize_street(street TEXT) RETURNS TEXT
+LANGUAGE plpgsql STABLE AS $$
+DECLARE
+    words TEXT[] := pg_temp.address_words(street);
+    result TEXT[] := '{}';
+    past_suffix BOOLEAN := FALSE;
+    word TEXT;
+    following TEXT;
+    previous TEXT;
+BEGIN
+    IF street IS NULL THEN
+        RETURN NULL;
+    END IF;
+    FOR i IN 1 .. COALESCE(array_length(words, 1), 0) LOOP
+        word := words[i];
+        following := words[i + 1];
+        previous := result[array_length(result, 1)];
+
+        IF word = '#' AND pg_temp.abbreviate('unit', previous) IS NOT NULL THEN
+            CONTINUE;
+        END IF;
+        IF pg_temp.abbreviate('unit', word) IS NOT NULL
+            AND (following = '#' OR following ~ '^[0-9]'
+                OR (following IS NULL AND array_length(result, 1) > 1 AND previous ~ '^[0-9]')
+                OR (past_suffix AND following IS NOT NULL)) THEN
+            result := result || pg_temp.abbreviate('unit', word);
+            CONTINUE;
+        END IF;
+        IF NOT (pg_temp.abbreviate('suffix', following) IS NOT NULL
+                AND pg_temp.ends_street_name(words[i + 2]))
+            AND pg_temp.abbreviate('directional', word) IS NOT NULL THEN
+            result := result || pg_temp.abbreviate('directional', word);
+            CONTINUE;
+        END IF;
+        IF previous IS NOT NULL AND previous !~ '^[0-9]'
+            AND pg_temp.ends_street_name(following)
+            AND pg_temp.abbreviate('suffix', word) IS NOT NULL THEN
+            result := result || pg_temp.abbreviate('suffix', word);
+            past_suffix := TRUE;
+            CONTINUE;
+        END IF;
+        result := result || word;
+    END LOOP;
+    RETURN array_to_string(result, ' ');
+END
+$$;
+
+CREATE FUNCTION pg_temp.split_zip(zip TEXT, OUT zip5 TEXT, OUT zip4 TEXT)
+LANGUAGE plpgsql IMMUTABLE AS $$
+DECLARE
+    digits TEXT;
+BEGIN
+    zip5 := regexp_replace(zip, '^\s+|\s+$', '', 'g');
+    IF zip5 !~ '^[0-9 -]*$' THEN
+        RETURN;
+    END IF;
+    digits := regexp_replace(zip5, '[^0-9]', '', 'g');
+    IF length(digits) IN (4, 8) THEN
+        digits := '0' || digits;
+    END IF;
+    IF length(digits) = 5 THEN
+        zip5 := digits;
+    ELSIF length(digits) = 9 THEN
+        zip5 := left(digits, 5);
+        zip4 := NULLIF(right(digits, 4), '0000');
+    END IF;
+END
+$$;
+
+CREATE TEMP TABLE standardized_addresses ON COMMIT DROP AS
+SELECT
+    a.id,
+    NULLIF(pg_temp.standardize_street(a.street_address), '') AS street_address,
+    NULLIF(array_to_string(pg_temp.address_words(a.city), ' '), '') AS city,
+    NULLIF(upper(btrim(a.state_code)), '') AS state_code,
+    NULLIF(z.zip5, '') AS zip_code,
+    z.zip4
+FROM addresses a, LATERAL pg_temp.split_zip(a.zip_code) z;
+
+-- Each address merges into the oldest row with the same standardized identity.
+CREATE TEMP TABLE address_merges ON COMMIT DROP AS
+SELECT id, MIN(id) OVER (
+    PARTITION BY COALESCE(street_address, ''), COALESCE(city, ''),
+        COALESCE(state_code, ''), COALESCE(zip_code, '')
+) AS keep_id
+FROM standardized_addresses;
+
+UPDATE providers p
+SET address_id = m.keep_id
+FROM address_merges m
+WHERE p.address_id = m.id AND m.id <> m.keep_id;
+
+DELETE FROM addresses a
+USING address_merges m
+WHERE a.id = m.id AND m.id <> m.keep_id;
+
+-- Rewriting the identity columns in place could briefly collide with another row's old
+-- values, so the index is rebuilt afterwards.
+DROP INDEX IF EXISTS idx_addresses_identity;
+
+UPDATE addresses a
+SET street_address = s.street_address,
+    city = s.city,
+    state_code = s.state_code,
+    zip_code = s.zip_code,
+    zip4 = s.zip4
+FROM standardized_addresses s
+WHERE a.id = s.id;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_addresses_identity
+ON addresses (
+    COALESCE(street_address, ''),
+    COALESCE(city, ''),
+    COALESCE(state_code, ''),
+    COALESCE(zip_code, '')
+);
+
+-- Standardizing is not a change to the provider: bring the open versions in line, so the
+-- next load doesn't record a new version of every provider.
+UPDATE provider_history h
+SET record = h.record || jsonb_build_object(
+    'street_address', a.street_address,
+    'city', a.city,
+    'state_code', a.state_code,
+    'zip_code', a.zip_code,
+    'zip4', a.zip4
+)
+FROM providers p
+LEFT JOIN addresses a ON a.id = p.address_id
+WHERE h.cms_certification_number = p.cms_certification_number
+  AND h.valid_to IS NULL;
+
+DROP FUNCTION pg_temp.standardize_street(TEXT);
+DROP FUNCTION pg_temp.split_zip(TEXT);
+DROP FUNCTION pg_temp.ends_street_name(TEXT);
+DROP FUNCTION pg_temp.address_words(TEXT);
+DROP FUNCTION pg_temp.abbreviate(TEXT, TEXT);
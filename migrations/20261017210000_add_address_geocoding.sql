@@ -0,0 +1,29 @@
+-- Reference centroids from the Census Bureau's gazetteer files, used to geocode addresses
+-- without an online geocoder. Each table is replaced as a whole by its loader.
+CREATE TABLE IF NOT EXISTS zcta_centroids (
+    -- ZIP Code Tabulation Area, which matches the five-digit ZIP for most addresses.
+    zcta TEXT PRIMARY KEY,
+    latitude DOUBLE PRECISION NOT NULL,
+    longitude DOUBLE PRECISION NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS county_centroids (
+    -- Five-digit FIPS code: the state's two digits, then the county's three.
+    geoid TEXT PRIMARY KEY,
+    state_code TEXT,
+    name TEXT,
+    latitude DOUBLE PRECISION NOT NULL,
+    longitude DOUBLE PRECISION NOT NULL
+);
+
+-- An address is placed at the centroid of its ZIP's ZCTA, or failing that, of its county.
+-- geocode_method says which; all three stay NULL when neither is known.
+ALTER TABLE addresses
+    ADD COLUMN IF NOT EXISTS latitude DOUBLE PRECISION,
+    ADD COLUMN IF NOT EXISTS longitude DOUBLE PRECISION,
+    ADD COLUMN IF NOT EXISTS geocode_method TEXT
+        CHECK (geocode_method IN ('zcta', 'county'));
+
+-- Serves bounding-box (<@) and nearest-neighbor (<->) searches on point(longitude, latitude).
+CREATE INDEX IF NOT EXISTS idx_addresses_location
+    ON addresses USING GIST (point(longitude, latitude));
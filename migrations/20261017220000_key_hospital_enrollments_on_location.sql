@@ -0,0 +1,15 @@
+-- The Hospital Enrollments file lists an enrollment once per practice location, so the enrollment id
+-- alone doesn't identify a row: keying on it kept only the first location of each enrollment.
+-- Key rows on the enrollment and its location instead.
+ALTER TABLE hospital_enrollments DROP CONSTRAINT IF EXISTS hospital_enrollments_pkey;
+ALTER TABLE hospital_enrollments ALTER COLUMN enrollment_id SET NOT NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_hospital_enrollments_location ON hospital_enrollments (
+    enrollment_id,
+    COALESCE(address_line_1, ''),
+    COALESCE(address_line_2, ''),
+    COALESCE(city, ''),
+    COALESCE(state_code, ''),
+    COALESCE(zip_code, ''),
+    COALESCE(practice_location_type, '')
+);